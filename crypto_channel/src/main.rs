@@ -1,23 +1,61 @@
 use modules::prompt::{
-    complete_key_exchange, create_new_key_exchange, decrypt_prompt, delete_key_exchange,
-    encrypt_prompt, select_menu_action, show_public_key,
+    backup_prompt, check_permissions_prompt, complete_key_exchange, confirm_key_exchange,
+    create_new_key_exchange, decrypt_file_prompt, decrypt_prompt, delete_key_exchange,
+    encrypt_file_prompt, encrypt_prompt, group_channels_prompt, rotate_keys_prompt,
+    select_menu_action, show_public_key, storage_encryption_prompt, unlock_storage_prompt,
+    verify_key_exchange,
 };
 
+use clap::Parser;
 use inquire::ui::{Attributes, Color, RenderConfig, StyleSheet, Styled};
+use modules::cli::{run, Cli};
 
 fn main() {
+    let cli = Cli::parse();
     inquire::set_global_render_config(get_render_config());
 
+    if let Some(command) = cli.command {
+        if let Err(e) = run(command, cli.allow_insecure_permissions) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(e) = check_permissions_prompt(cli.allow_insecure_permissions) {
+        println!("{}", e);
+        return;
+    }
+
+    if let Err(e) = unlock_storage_prompt() {
+        println!("{}", e);
+        return;
+    }
+
     loop {
-        let action = select_menu_action().unwrap();
+        let action = match select_menu_action() {
+            Ok(action) => action,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
 
         match action.as_str() {
             "Create New Key Exchange" => create_new_key_exchange(),
             "Complete Key Exchange" => complete_key_exchange(),
             "Delete Key Exchange" => delete_key_exchange(),
             "Send Public Key" => show_public_key(),
+            "Verify Key Exchange" => verify_key_exchange(),
+            "Confirm Key Exchange" => confirm_key_exchange(),
+            "Rotate Keys" => rotate_keys_prompt(),
             "Encrypt Message" => encrypt_prompt(),
             "Decrypt Message" => decrypt_prompt(),
+            "Encrypt File" => encrypt_file_prompt(),
+            "Decrypt File" => decrypt_file_prompt(),
+            "Group Channels" => group_channels_prompt(),
+            "Backup" => backup_prompt(),
+            "Storage Encryption" => storage_encryption_prompt(),
             _ => return,
         }
     }
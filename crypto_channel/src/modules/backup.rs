@@ -0,0 +1,255 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+use crate::error::Error;
+use crate::key_store_classes::KeyStorage;
+use crate::storage_encryption::{open_storage, seal_storage, SealedStorage};
+use crate::storage_migration::parse_storage;
+
+pub const BACKUP_FORMAT: &str = "crypto-channel-backup";
+pub const BACKUP_VERSION: u32 = 1;
+
+/// A backup file. The sealed key storage keeps its own `schema_version`, so
+/// backups of older versions go through the storage migrations on import.
+#[derive(Serialize, Deserialize, Debug)]
+struct Backup {
+    format: String,
+    version: u32,
+    storage: SealedStorage,
+}
+
+/// What to do with a Key Exchange or Group Channel of the backup whose name
+/// is already used in the key storage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportConflict {
+    /// Keep both, the one from the backup is imported under a new name.
+    Merge,
+    /// Replace the one in the key storage with the one from the backup.
+    Overwrite,
+    /// Keep the one in the key storage.
+    Skip,
+}
+
+impl FromStr for ImportConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(ImportConflict::Merge),
+            "overwrite" => Ok(ImportConflict::Overwrite),
+            "skip" => Ok(ImportConflict::Skip),
+            _ => Err(String::from(
+                "The conflict handling must be `merge`, `overwrite` or `skip`",
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub imported: Vec<String>,
+    pub overwritten: Vec<String>,
+    pub skipped: Vec<String>,
+    /// Names in the backup, and the names they were imported under.
+    pub renamed: Vec<(String, String)>,
+}
+
+/// Shown once a backup was imported: the message counters and ratchet
+/// sessions it restored don't know about the messages sent since it was made.
+pub const RESTORED_STATE_WARNING: &str = "Warning: the restored Key Exchanges and Group \
+     Channels are as they were when the backup was made. If you sent messages since, \
+     your next ones might be rejected as replays, or reuse message keys: redo the Key \
+     Exchange with those contacts.";
+
+impl ImportSummary {
+    /// Tells whether anything was taken from the backup.
+    pub fn restored_any(&self) -> bool {
+        !self.imported.is_empty() || !self.overwritten.is_empty() || !self.renamed.is_empty()
+    }
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let renamed: Vec<String> = self
+            .renamed
+            .iter()
+            .map(|(name, new_name)| format!("{} as {}", name, new_name))
+            .collect();
+        let lines = [
+            ("Imported", &self.imported),
+            ("Overwritten", &self.overwritten),
+            ("Imported under a new name", &renamed),
+            ("Skipped, already in the key storage", &self.skipped),
+        ];
+
+        let mut empty = true;
+        for (label, names) in lines.iter().filter(|(_, names)| !names.is_empty()) {
+            if !empty {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", label, names.join(", "))?;
+            empty = false;
+        }
+        if empty {
+            write!(f, "The backup is empty")?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the Key Exchanges in `names`, or all of them if it's empty, and the
+/// Group Channels whose members are all kept.
+pub fn select_exchanges(mut storage: KeyStorage, names: &[String]) -> Result<KeyStorage, Error> {
+    if names.is_empty() {
+        return Ok(storage);
+    }
+
+    let mut selected = KeyStorage::new();
+    for name in names {
+        let entry = storage
+            .exchange_map
+            .remove(name)
+            .ok_or(Error::UnknownExchange(name.clone()))?;
+        selected.exchange_map.insert(name.clone(), entry);
+    }
+    for (name, group) in storage.group_map.drain() {
+        if group.members.iter().all(|member| names.contains(member)) {
+            selected.group_map.insert(name, group);
+        }
+    }
+    Ok(selected)
+}
+
+pub fn seal_backup(storage: &KeyStorage, passphrase: &str) -> Result<String, Error> {
+    let json = Zeroizing::new(serde_json::to_string(storage).map_err(Error::StorageParse)?);
+    let backup = Backup {
+        format: BACKUP_FORMAT.to_string(),
+        version: BACKUP_VERSION,
+        storage: seal_storage(json.as_bytes(), passphrase)?,
+    };
+    serde_json::to_string_pretty(&backup).map_err(Error::StorageParse)
+}
+
+pub fn open_backup(contents: &str, passphrase: &str) -> Result<KeyStorage, Error> {
+    let backup: Backup = serde_json::from_str(contents).map_err(|_| Error::InvalidBackup)?;
+    if backup.format != BACKUP_FORMAT {
+        return Err(Error::InvalidBackup);
+    }
+    if backup.version != BACKUP_VERSION {
+        return Err(Error::UnsupportedBackupVersion(backup.version));
+    }
+
+    let json = open_storage(&backup.storage, passphrase)?;
+    parse_storage(&json).map(|(storage, _)| storage)
+}
+
+fn free_name(used: impl Fn(&str) -> bool, name: &str) -> String {
+    let mut new_name = format!("{}-imported", name);
+    let mut i = 2;
+    while used(&new_name) {
+        new_name = format!("{}-imported-{}", name, i);
+        i += 1;
+    }
+    new_name
+}
+
+/// Adds the Key Exchanges and Group Channels of `backup` to `storage`.
+pub fn import_into(
+    storage: &mut KeyStorage,
+    mut backup: KeyStorage,
+    conflict: ImportConflict,
+) -> Result<ImportSummary, Error> {
+    let mut summary = ImportSummary::default();
+    let mut exchange_names: HashMap<String, String> = HashMap::new();
+
+    let mut names: Vec<String> = backup.exchange_map.keys().cloned().collect();
+    names.sort();
+    for name in names {
+        let (exchange, signature) = backup.exchange_map.remove(&name).unwrap();
+        let exists = storage.exchange_map.contains_key(&name);
+
+        // The same keys under two names would make messages match either one.
+        let stored_name = storage
+            .exchange_map
+            .iter()
+            .find(|(_, (stored, _))| stored.get_your_public_key() == exchange.get_your_public_key())
+            .map(|(stored_name, _)| stored_name.clone());
+        if let Some(stored_name) = stored_name {
+            if conflict != ImportConflict::Overwrite || stored_name != name {
+                exchange_names.insert(name.clone(), stored_name);
+                summary.skipped.push(name);
+                continue;
+            }
+        }
+        let new_name = match (exists, conflict) {
+            (true, ImportConflict::Skip) => {
+                // Groups of the backup go on with the exchange already stored.
+                exchange_names.insert(name.clone(), name.clone());
+                summary.skipped.push(name);
+                continue;
+            }
+            (true, ImportConflict::Merge) => {
+                free_name(|n| storage.exchange_map.contains_key(n), &name)
+            }
+            _ => name.clone(),
+        };
+
+        let overwrite = conflict == ImportConflict::Overwrite;
+        storage.create_exchange(&new_name, overwrite, Some(exchange), Some(signature))?;
+        match (exists, overwrite) {
+            (false, _) => summary.imported.push(new_name.clone()),
+            (true, true) => summary.overwritten.push(new_name.clone()),
+            (true, false) => summary.renamed.push((name.clone(), new_name.clone())),
+        }
+        exchange_names.insert(name, new_name);
+    }
+
+    let mut names: Vec<String> = backup.group_map.keys().cloned().collect();
+    names.sort();
+    for name in names {
+        let mut group = backup.group_map.remove(&name).unwrap();
+        // Members are named after the exchanges, which might have been renamed,
+        // and a group is only usable if all of them are in the key storage.
+        if !group
+            .members
+            .iter()
+            .all(|member| exchange_names.contains_key(member))
+        {
+            summary.skipped.push(name);
+            continue;
+        }
+        group.members = group
+            .members
+            .iter()
+            .map(|member| exchange_names[member].clone())
+            .collect();
+        group.member_keys = group
+            .member_keys
+            .drain()
+            .filter_map(|(member, key)| Some((exchange_names.get(&member)?.clone(), key)))
+            .collect();
+
+        let exists = storage.group_map.contains_key(&name);
+        let new_name = match (exists, conflict) {
+            (true, ImportConflict::Skip) => {
+                summary.skipped.push(name);
+                continue;
+            }
+            (true, ImportConflict::Merge) => {
+                free_name(|n| storage.group_map.contains_key(n), &name)
+            }
+            _ => name.clone(),
+        };
+        match (exists, conflict) {
+            (false, _) => summary.imported.push(new_name.clone()),
+            (true, ImportConflict::Overwrite) => summary.overwritten.push(new_name.clone()),
+            _ => summary.renamed.push((name, new_name.clone())),
+        }
+        storage.group_map.insert(new_name, group);
+    }
+
+    Ok(summary)
+}
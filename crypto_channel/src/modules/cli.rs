@@ -0,0 +1,586 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+use zeroize::Zeroizing;
+
+use crate::backup::{ImportConflict, RESTORED_STATE_WARNING};
+use crate::error::Error;
+use crate::file_encryption::{decrypt_file, encrypt_file};
+use crate::group::{
+    add_member, create_group, decrypt_for_group, encrypt_for_group, remove_member,
+    share_sender_key, SenderKeyUpdate,
+};
+use crate::key_encoding::{
+    encode_public_key, parse_peer_key, parse_public_key, PeerKey, LEGACY_KEY_WARNING,
+    SIGNING_KEY_PREFIX,
+};
+use crate::key_exchange::key_to_string;
+use crate::key_store::{
+    complete_exchange, confirm_exchange, delete_exchange, delete_group, export_backup,
+    find_exposed_files, get_exchange_dh_public, get_exchange_kind, get_exchange_public_key,
+    get_exchange_receive_key, get_exchange_safety_number, get_exchange_send_key,
+    get_exchange_signing_public, get_group, get_group_names, get_key_exchange_names, import_backup,
+    is_signature_enabled, is_storage_encrypted, new_exchange, restrict_file_permissions,
+    set_exchange_verified, start_key_confirmation, unlock_storage, validate_new_exchange_name,
+};
+use crate::key_store_classes::{ExchangeKind, MessageOrder};
+use crate::message::{
+    decrypt_for_exchange, decrypt_from_any_exchange, encrypt_for_exchange, encrypt_for_exchanges,
+    rotate_keys_for_exchange, SignatureStatus,
+};
+use crate::message_io::{read_message, write_message, MessageSink, MessageSource};
+use crate::prompt::{backup_passphrase_prompt, unlock_storage_prompt};
+use crate::qr::{export_qr, key_qr_data, message_qr_to_terminal, qr_to_terminal};
+use crate::storage_file::write_atomically;
+
+pub const PASSPHRASE_ENV: &str = "CRYPTO_CHANNEL_PASSPHRASE";
+pub const BACKUP_PASSPHRASE_ENV: &str = "CRYPTO_CHANNEL_BACKUP_PASSPHRASE";
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "crypto_channel",
+    version,
+    about = "Secure key exchanges and message encryption over unsecure channels",
+    long_about = "Secure key exchanges and message encryption over unsecure channels.\n\n\
+                  Run without a command to start the interactive menu."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Use the key storage even if other users can access it
+    #[arg(long, global = true)]
+    pub allow_insecure_permissions: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Create a new Key Exchange
+    New {
+        name: String,
+        /// Combine X25519 with the post-quantum ML-KEM-768, both parties must use it
+        #[arg(long)]
+        hybrid: bool,
+    },
+    /// Print your Public Key for a Key Exchange
+    Pubkey {
+        name: String,
+        /// Print your Signing Key instead of the Public Key
+        #[arg(long)]
+        signing: bool,
+        /// Print the key in the base64 format of older versions, without
+        /// checksum. Only send it to people using such a version, as their keys
+        /// are then derived as older versions do
+        #[arg(long)]
+        base64: bool,
+        /// Print the key as a QR code
+        #[arg(long)]
+        qr: bool,
+        /// Export the key as a QR code to a `.png` or `.svg` file
+        #[arg(long, value_name = "PATH")]
+        qr_file: Option<PathBuf>,
+    },
+    /// Complete a Key Exchange with the Public Key of the other party
+    Complete {
+        name: String,
+        peer_key: String,
+        /// Signing Key of the other party, required when signatures are enabled
+        #[arg(long)]
+        peer_signing_key: Option<String>,
+        /// Hold the exchange until the confirmation token of the other party is
+        /// checked with `confirm`, and print your token
+        #[arg(long)]
+        confirm: bool,
+    },
+    /// Print your key confirmation token, or check the one of the other party
+    Confirm {
+        name: String,
+        /// Confirmation token of the other party
+        token: Option<String>,
+    },
+    /// Print the safety number of a Key Exchange, to compare with the other party
+    SafetyNumber {
+        name: String,
+        /// Mark the Key Exchange as verified, once the numbers matched
+        #[arg(long)]
+        mark_verified: bool,
+    },
+    /// Encrypt a message, read from stdin by default. With several Key
+    /// Exchanges, the message is encrypted once for all of them
+    Encrypt {
+        #[arg(required = true)]
+        names: Vec<String>,
+        /// Where to read the message from: `-` for stdin, `clipboard` or a file path
+        #[arg(short, long, default_value = "-")]
+        input: MessageSource,
+        /// Where to write the encrypted message: `-` for stdout, `clipboard` or a file path
+        #[arg(short, long, default_value = "-")]
+        output: MessageSink,
+        /// Also show the encrypted message as a QR code, on stderr
+        #[arg(long)]
+        qr: bool,
+    },
+    /// Decrypt an encrypted message, read from stdin by default
+    Decrypt {
+        /// Can be left out, the Key Exchange is then found from the message
+        name: Option<String>,
+        /// Where to read the encrypted message from: `-` for stdin, `clipboard` or a file path
+        #[arg(short, long, default_value = "-")]
+        input: MessageSource,
+        /// Where to write the decrypted message: `-` for stdout, `clipboard` or a file path
+        #[arg(short, long, default_value = "-")]
+        output: MessageSink,
+    },
+    /// Replace your key pair for a Key Exchange, and print the message that sends
+    /// the new Public Key to the other party
+    Rotate {
+        name: String,
+        /// Where to write the message: `-` for stdout, `clipboard` or a file path
+        #[arg(short, long, default_value = "-")]
+        output: MessageSink,
+    },
+    /// Encrypt a file in chunks, without loading it into memory
+    EncryptFile {
+        name: String,
+        input: PathBuf,
+        output: PathBuf,
+    },
+    /// Decrypt a file encrypted with `encrypt-file`
+    DecryptFile {
+        name: String,
+        input: PathBuf,
+        output: PathBuf,
+    },
+    /// Manage Group Channels, where one encrypted message is readable by all members
+    Group {
+        #[command(subcommand)]
+        command: GroupCommand,
+    },
+    /// Delete a Key Exchange
+    Delete { name: String },
+    /// List the Key Exchanges in the key storage
+    List,
+    /// Make the config directory and the key storage accessible to you only
+    FixPermissions,
+    /// Write an encrypted backup of your Key Exchanges, all of them by default
+    Export {
+        /// Where to write the backup
+        output: PathBuf,
+        /// Only back up these Key Exchanges, and the groups made of them
+        names: Vec<String>,
+    },
+    /// Restore the Key Exchanges and Group Channels of a backup
+    Import {
+        input: PathBuf,
+        /// With the same name already in the key storage: `skip` keeps yours,
+        /// `overwrite` replaces it, `merge` keeps both under different names
+        #[arg(long, default_value = "skip")]
+        on_conflict: ImportConflict,
+    },
+}
+
+/// Commands that change your sender key print it for every member, one
+/// `member<TAB>message` line each, to send over your Key Exchange with them.
+#[derive(Subcommand, Debug)]
+pub enum GroupCommand {
+    /// Create a Group Channel with the other party of each Key Exchange
+    Create {
+        name: String,
+        #[arg(required = true)]
+        members: Vec<String>,
+    },
+    /// Add a member, and replace your sender key
+    Add { name: String, member: String },
+    /// Remove a member, and replace your sender key
+    Remove { name: String, member: String },
+    /// Print your current sender key for every member
+    Share { name: String },
+    /// Encrypt a message for all the members, read from stdin by default
+    Encrypt {
+        name: String,
+        /// Where to read the message from: `-` for stdin, `clipboard` or a file path
+        #[arg(short, long, default_value = "-")]
+        input: MessageSource,
+        /// Where to write the encrypted message: `-` for stdout, `clipboard` or a file path
+        #[arg(short, long, default_value = "-")]
+        output: MessageSink,
+    },
+    /// Decrypt a message sent to the group, read from stdin by default
+    Decrypt {
+        name: String,
+        /// Where to read the encrypted message from: `-` for stdin, `clipboard` or a file path
+        #[arg(short, long, default_value = "-")]
+        input: MessageSource,
+        /// Where to write the decrypted message: `-` for stdout, `clipboard` or a file path
+        #[arg(short, long, default_value = "-")]
+        output: MessageSink,
+    },
+    /// Delete a Group Channel
+    Delete { name: String },
+    /// List the Group Channels and their members
+    List,
+}
+
+fn check_permissions_for_cli(allow_insecure_permissions: bool) -> Result<(), Error> {
+    let exposed = find_exposed_files()?;
+    if exposed.is_empty() {
+        return Ok(());
+    }
+    if !allow_insecure_permissions {
+        return Err(Error::InsecurePermissions(exposed));
+    }
+
+    for path in exposed {
+        let warning = format!("Warning: other users can access `{}`.", path.display());
+        eprintln!("{}", warning.yellow());
+    }
+    Ok(())
+}
+
+fn backup_passphrase_for_cli(confirm: bool) -> Result<Zeroizing<String>, Error> {
+    match env::var(BACKUP_PASSPHRASE_ENV) {
+        Ok(passphrase) => Ok(Zeroizing::new(passphrase)),
+        Err(_) => backup_passphrase_prompt(confirm)
+            .map(Zeroizing::new)
+            .map_err(|_| Error::MissingBackupPassphrase),
+    }
+}
+
+fn unlock_storage_for_cli() -> Result<(), Error> {
+    if !is_storage_encrypted()? {
+        return Ok(());
+    }
+
+    match env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => unlock_storage(passphrase),
+        Err(_) => unlock_storage_prompt().map_err(|_| Error::StorageLocked),
+    }
+}
+
+fn warn_signature_status(status: &SignatureStatus) {
+    let warning = match status {
+        SignatureStatus::Unsigned => "Warning: the message is not signed.",
+        SignatureStatus::UnknownSigner => {
+            "Warning: the message is signed, but the Signing Key of the other party is unknown."
+        }
+        _ => return,
+    };
+    eprintln!("{}", warning.yellow());
+}
+
+fn warn_message_order(order: &MessageOrder) {
+    let warning = match order {
+        MessageOrder::OutOfOrder => "Warning: the message arrived out of order.",
+        MessageOrder::Replayed => {
+            "Warning: this message was already decrypted, it might be a replay."
+        }
+        MessageOrder::TooOld => {
+            "Warning: the message is too old to tell whether it was already decrypted."
+        }
+        _ => return,
+    };
+    eprintln!("{}", warning.yellow());
+}
+
+fn print_sender_key_messages(messages: Vec<(String, String)>) {
+    eprintln!("Send each member their message, they decrypt it with `decrypt`:");
+    for (member, encoded_msg) in messages {
+        println!("{}\t{}", member, encoded_msg);
+    }
+}
+
+fn run_group(command: GroupCommand) -> Result<(), Error> {
+    match command {
+        GroupCommand::Create { name, members } => {
+            print_sender_key_messages(create_group(name, members)?)
+        }
+        GroupCommand::Add { name, member } => print_sender_key_messages(add_member(name, member)?),
+        GroupCommand::Remove { name, member } => {
+            print_sender_key_messages(remove_member(name, member)?)
+        }
+        GroupCommand::Share { name } => print_sender_key_messages(share_sender_key(name)?),
+        GroupCommand::Encrypt {
+            name,
+            input,
+            output,
+        } => {
+            let message = Zeroizing::new(read_message(&input)?);
+            let mut encoded_msg = encrypt_for_group(name, &message)?;
+            if output != MessageSink::Clipboard {
+                encoded_msg.push('\n');
+            }
+            write_message(&output, encoded_msg.as_bytes())?;
+        }
+        GroupCommand::Decrypt {
+            name,
+            input,
+            output,
+        } => {
+            let encoded_msg =
+                String::from_utf8(read_message(&input)?).map_err(|_| Error::BadEncoding)?;
+            let decrypted = decrypt_for_group(name, encoded_msg)?;
+            eprintln!("From: {}", decrypted.sender);
+            warn_message_order(&decrypted.order);
+            write_message(&output, &decrypted.message)?;
+        }
+        GroupCommand::Delete { name } => delete_group(name)?,
+        GroupCommand::List => {
+            let mut names = get_group_names()?;
+            names.sort();
+            for name in names {
+                println!("{}: {}", name, get_group(name.clone())?.members.join(", "));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn warn_sender_key_update(update: &SenderKeyUpdate) {
+    match update {
+        SenderKeyUpdate::Stored(group) => eprintln!(
+            "{}",
+            format!(
+                "The sender key of the other party for the group `{}` was stored.",
+                group
+            )
+            .green()
+        ),
+        SenderKeyUpdate::Outdated(group) => eprintln!(
+            "{}",
+            format!(
+                "Warning: a newer sender key of the other party for the group `{}` is \
+                 already known, this one was ignored.",
+                group
+            )
+            .yellow()
+        ),
+        SenderKeyUpdate::NotMember(group) => eprintln!(
+            "{}",
+            format!(
+                "Warning: the other party is not a member of the group `{}`, their \
+                 sender key is kept until you add them.",
+                group
+            )
+            .yellow()
+        ),
+    }
+}
+
+pub fn run(command: Command, allow_insecure_permissions: bool) -> Result<(), Error> {
+    if !matches!(command, Command::FixPermissions) {
+        check_permissions_for_cli(allow_insecure_permissions)?;
+        unlock_storage_for_cli()?;
+    }
+
+    match command {
+        Command::New { name, hybrid } => {
+            let kind = match hybrid {
+                true => ExchangeKind::Hybrid,
+                false => ExchangeKind::Classic,
+            };
+            validate_new_exchange_name(name.clone())?;
+            new_exchange(name.clone(), kind)?;
+            println!("{}", get_exchange_public_key(name)?);
+        }
+        Command::Pubkey {
+            name,
+            signing,
+            base64,
+            qr,
+            qr_file,
+        } => {
+            let key_str = match (signing, base64) {
+                (true, true) => key_to_string(get_exchange_signing_public(name)?),
+                (true, false) => {
+                    encode_public_key(get_exchange_signing_public(name)?, SIGNING_KEY_PREFIX)
+                }
+                (false, true) if get_exchange_kind(name.clone())? == ExchangeKind::Hybrid => {
+                    return Err(Error::WrongExchangeKind(name));
+                }
+                (false, true) => key_to_string(get_exchange_dh_public(name)?),
+                (false, false) => get_exchange_public_key(name)?,
+            };
+
+            let qr_str = key_qr_data(&key_str);
+            if let Some(path) = qr_file {
+                export_qr(&qr_str, &path)?;
+            }
+            match qr {
+                true => print!("{}", qr_to_terminal(&qr_str)?),
+                false => println!("{}", key_str),
+            }
+        }
+        Command::Complete {
+            name,
+            peer_key,
+            peer_signing_key,
+            confirm,
+        } => {
+            let peer_key = parse_peer_key(&peer_key)?;
+            let is_hybrid_public = matches!(peer_key, PeerKey::HybridPublic(..));
+            let is_legacy = matches!(peer_key, PeerKey::Legacy(_));
+            let signing_public = match peer_signing_key {
+                Some(key) => Some(parse_public_key(&key, SIGNING_KEY_PREFIX)?),
+                None => {
+                    if is_signature_enabled()? {
+                        eprintln!(
+                            "{}",
+                            "Warning: signatures are enabled but no --peer-signing-key \
+                             was given. Messages from this exchange can't be verified."
+                                .yellow()
+                        );
+                    }
+                    None
+                }
+            };
+            complete_exchange(name.clone(), peer_key, signing_public)?;
+            eprintln!(
+                "Safety number: {}",
+                get_exchange_safety_number(name.clone())?
+            );
+            if is_hybrid_public {
+                eprintln!("Send this response to the other party to complete the exchange:");
+                println!("{}", get_exchange_public_key(name.clone())?);
+            }
+            if is_legacy {
+                eprintln!("{}", LEGACY_KEY_WARNING.yellow());
+                println!("{}", get_exchange_public_key(name.clone())?);
+            }
+            if confirm {
+                println!("{}", start_key_confirmation(name)?);
+            }
+        }
+        Command::Confirm { name, token } => match token {
+            Some(token) => {
+                confirm_exchange(name, &token)?;
+                eprintln!("{}", "The Key Exchange is confirmed.".green());
+            }
+            None => println!("{}", start_key_confirmation(name)?),
+        },
+        Command::SafetyNumber {
+            name,
+            mark_verified,
+        } => {
+            println!("{}", get_exchange_safety_number(name.clone())?);
+            if mark_verified {
+                set_exchange_verified(name, true)?;
+            }
+        }
+        Command::Encrypt {
+            mut names,
+            input,
+            output,
+            qr,
+        } => {
+            let message = Zeroizing::new(read_message(&input)?);
+            let (mut encoded_msg, status) = match names.len() {
+                1 => encrypt_for_exchange(names.remove(0), &message)?,
+                _ => encrypt_for_exchanges(names, &message)?,
+            };
+            warn_signature_status(&status);
+            if output != MessageSink::Clipboard {
+                encoded_msg.push('\n');
+            }
+            write_message(&output, encoded_msg.as_bytes())?;
+            // Only shown once the message is written: its counter or ratchet key
+            // is already used up, it couldn't be encrypted again.
+            if qr {
+                match message_qr_to_terminal(encoded_msg.trim_end()) {
+                    Ok(qr_code) => eprint!("{}", qr_code),
+                    Err(e) => eprintln!("{}", format!("Warning: {}", e).yellow()),
+                }
+            }
+        }
+        Command::Decrypt {
+            name,
+            input,
+            output,
+        } => {
+            let encoded_msg =
+                String::from_utf8(read_message(&input)?).map_err(|_| Error::BadEncoding)?;
+            let decrypted = match name {
+                Some(name) => decrypt_for_exchange(name, encoded_msg)?,
+                None => {
+                    let (name, decrypted) = decrypt_from_any_exchange(encoded_msg)?;
+                    eprintln!("From: {}", name);
+                    decrypted
+                }
+            };
+            warn_signature_status(&decrypted.signature);
+            warn_message_order(&decrypted.order);
+            if let Some(update) = decrypted.sender_key {
+                warn_sender_key_update(&update);
+                return Ok(());
+            }
+            if decrypted.key_rotated {
+                eprintln!(
+                    "{}",
+                    "The other party rotated their keys, the Key Exchange now uses their \
+                     new Public Key."
+                        .green()
+                );
+                return Ok(());
+            }
+            write_message(&output, &decrypted.message)?;
+        }
+        Command::Rotate { name, output } => {
+            let (mut encoded_msg, status) = rotate_keys_for_exchange(name)?;
+            warn_signature_status(&status);
+            if output != MessageSink::Clipboard {
+                encoded_msg.push('\n');
+            }
+            write_message(&output, encoded_msg.as_bytes())?;
+        }
+        Command::EncryptFile {
+            name,
+            input,
+            output,
+        } => {
+            let encryption_key = get_exchange_send_key(name)?;
+            encrypt_file(&input, &output, &encryption_key)?;
+        }
+        Command::DecryptFile {
+            name,
+            input,
+            output,
+        } => {
+            let encryption_key = get_exchange_receive_key(name)?;
+            decrypt_file(&input, &output, &encryption_key)?;
+        }
+        Command::Group { command } => run_group(command)?,
+        Command::Delete { name } => delete_exchange(name)?,
+        Command::List => {
+            let mut names = get_key_exchange_names()?;
+            names.sort();
+            for name in names {
+                println!("{}", name);
+            }
+        }
+        Command::FixPermissions => {
+            let exposed = find_exposed_files()?;
+            restrict_file_permissions(&exposed)?;
+            for path in exposed {
+                eprintln!("Only you can access `{}` now.", path.display());
+            }
+        }
+        Command::Export { output, names } => {
+            let passphrase = backup_passphrase_for_cli(true)?;
+            let backup = export_backup(&names, &passphrase)?;
+            write_atomically(&output, backup.as_bytes()).map_err(Error::BackupIo)?;
+            eprintln!("Backup written to: {}", output.display());
+        }
+        Command::Import { input, on_conflict } => {
+            let contents = fs::read_to_string(&input).map_err(Error::BackupIo)?;
+            let passphrase = backup_passphrase_for_cli(false)?;
+            let summary = import_backup(&contents, &passphrase, on_conflict)?;
+            eprintln!("{}", summary);
+            if summary.restored_any() {
+                eprintln!("{}", RESTORED_STATE_WARNING.yellow());
+            }
+        }
+    }
+
+    Ok(())
+}
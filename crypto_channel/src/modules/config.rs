@@ -1,81 +1,118 @@
 use home::home_dir;
 use serde::{Deserialize, Serialize};
-use serde_json;
 use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::DirBuilderExt;
 use std::path::PathBuf;
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::error::Error;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ConfigSettings {
     pub storage_path: PathBuf,
     pub encrypt_storage: bool,
     pub use_signature: bool,
+    #[serde(default)]
+    pub use_ratchet: bool,
+    #[serde(default)]
+    pub storage_backend: StorageBackend,
+}
+
+/// How the key storage at `storage_path` is kept.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// A single JSON file, which can be encrypted with a passphrase.
+    #[default]
+    Json,
+    /// A SQLite database, with one row per Key Exchange and Group Channel.
+    Sqlite,
 }
 
 impl ConfigSettings {
     fn new(home_path: PathBuf) -> Self {
+        Self::with_storage_path(home_path.join(".config/crypto-channel/storage.json"))
+    }
+
+    /// The default settings, with the key storage at `storage_path`.
+    pub fn with_storage_path(storage_path: PathBuf) -> Self {
         ConfigSettings {
-            storage_path: home_path.join(".config/crypto-channel/storage.json"),
+            storage_path,
             encrypt_storage: false,
             use_signature: false,
+            use_ratchet: false,
+            storage_backend: StorageBackend::Json,
         }
     }
 }
 
-pub fn find_config_file() -> PathBuf {
-    let home_path = home_dir().unwrap();
+pub fn find_config_file() -> Result<PathBuf, Error> {
+    let home_path = home_dir().ok_or(Error::HomeDirNotFound)?;
     let config_dir_path = home_path.join(".config/crypto-channel");
 
     let config_dir = fs::read_dir(&config_dir_path);
     match config_dir {
-        Ok(_) => println!("Config directory found at: {}", &config_dir_path.display()),
-        Err(_) => create_config_dir(&config_dir_path),
+        Ok(_) => eprintln!("Config directory found at: {}", &config_dir_path.display()),
+        Err(_) => create_config_dir(&config_dir_path)?,
     }
 
     let config_file_path = config_dir_path.join("config.json");
 
     let config_file = fs::read(&config_file_path);
     match config_file {
-        Ok(_) => println!("Config file found at: {}", &config_file_path.display()),
+        Ok(_) => eprintln!("Config file found at: {}", &config_file_path.display()),
         Err(_) => {
-            create_config_file(&config_file_path);
-            write_default_config(&config_file_path);
+            create_config_file(&config_file_path)?;
+            write_default_config(&config_file_path)?;
         }
     }
 
-    config_file_path
+    Ok(config_file_path)
 }
 
-pub fn read_config_file(config_file_path: PathBuf) -> ConfigSettings {
-    let contents =
-        fs::read_to_string(config_file_path).expect("Should have been able to read the file");
+pub fn read_config_file(config_file_path: PathBuf) -> Result<ConfigSettings, Error> {
+    let contents = fs::read_to_string(config_file_path).map_err(Error::ConfigIo)?;
 
-    serde_json::from_str(&contents).unwrap()
+    serde_json::from_str(&contents).map_err(Error::ConfigParse)
 }
 
-fn create_config_dir(config_dir_path: &PathBuf) {
-    println!("Creating config dir...");
-    _ = fs::create_dir(&config_dir_path).expect("Config directory created!");
-    println!("Config directory crated at: {}", config_dir_path.display());
+fn create_config_dir(config_dir_path: &PathBuf) -> Result<(), Error> {
+    eprintln!("Creating config dir...");
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    // The key storage is kept in there by default, so only you may open it.
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(config_dir_path).map_err(Error::ConfigIo)?;
+    eprintln!("Config directory crated at: {}", config_dir_path.display());
+    Ok(())
 }
 
-fn create_config_file(config_file_path: &PathBuf) {
-    println!("Creating config file...");
-    _ = fs::File::create(&config_file_path).expect("Config file created!");
-    println!("Config file created at: {}", config_file_path.display());
+fn create_config_file(config_file_path: &PathBuf) -> Result<(), Error> {
+    eprintln!("Creating config file...");
+    fs::File::create(config_file_path).map_err(Error::ConfigIo)?;
+    eprintln!("Config file created at: {}", config_file_path.display());
+    Ok(())
 }
 
-pub fn write_default_config(config_file_path: &PathBuf) {
-    let home_path = home_dir().unwrap();
+pub fn write_default_config(config_file_path: &PathBuf) -> Result<(), Error> {
+    let home_path = home_dir().ok_or(Error::HomeDirNotFound)?;
     let new_config = ConfigSettings::new(home_path);
-    let j = serde_json::to_string(&new_config).unwrap();
-    _ = fs::write(config_file_path, j).expect("Default settings written successfully!");
+    write_config_file(config_file_path, &new_config)?;
+
+    eprintln!("Default settings written to config file!");
+    Ok(())
+}
+
+pub fn write_config_file(config_file_path: &PathBuf, cfg: &ConfigSettings) -> Result<(), Error> {
+    let j = serde_json::to_string(cfg).map_err(Error::ConfigParse)?;
 
-    println!("Default settings written to config file!");
+    fs::write(config_file_path, j).map_err(Error::ConfigIo)
 }
 
 pub fn test() {
-    let cfg_path = find_config_file();
-    let cfg = read_config_file(cfg_path);
+    let cfg_path = find_config_file().unwrap();
+    let cfg = read_config_file(cfg_path).unwrap();
 
     println!("{:?}", cfg);
     println!("{}", cfg.storage_path.display());
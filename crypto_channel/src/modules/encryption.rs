@@ -1,40 +1,98 @@
-use aes_gcm::aead::{generic_array::GenericArray, Aead, AeadCore, KeyInit};
+use aes_gcm::aead::{generic_array::GenericArray, Aead, AeadCore, KeyInit, Payload};
 use aes_gcm::Aes256Gcm;
 use base64::{engine::general_purpose, Engine as _};
 use rand_core::OsRng;
 
-pub fn encrypt_message(message: &[u8], key: &[u8; 32]) -> (Vec<u8>, Vec<u8>) {
+use crate::envelope::{Envelope, ALG_AES_256_GCM, FLAG_SIGNED, KEY_ID_SIZE, LEGACY_VERSION};
+use crate::error::Error;
+
+pub fn encrypt_message(message: &[u8], key: &[u8; 32]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    encrypt_message_with_aad(message, &[], key)
+}
+
+pub fn encrypt_message_with_aad(
+    message: &[u8],
+    aad: &[u8],
+    key: &[u8; 32],
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
     let cipher = Aes256Gcm::new(GenericArray::from_slice(key));
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
     let ciphertext = cipher
-        .encrypt(&nonce, message)
-        .expect("Encryption successful");
+        .encrypt(&nonce, Payload { msg: message, aad })
+        .map_err(|_| Error::EncryptionFailed)?;
+
+    Ok((ciphertext, nonce.to_vec()))
+}
 
-    (ciphertext, nonce.to_vec())
+pub fn encrypted_to_str(envelope: &Envelope) -> String {
+    general_purpose::STANDARD.encode(envelope.to_bytes())
 }
 
-pub fn encrypted_to_str(ciphertext: Vec<u8>, nonce: Vec<u8>) -> String {
-    let ciphertext_str = general_purpose::STANDARD.encode(ciphertext);
-    let nonce_str = general_purpose::STANDARD.encode(nonce);
-    nonce_str + ";" + &ciphertext_str
+fn decode_base64(s: &str) -> Result<Vec<u8>, Error> {
+    general_purpose::STANDARD
+        .decode(s)
+        .map_err(|_| Error::BadEncoding)
 }
 
-pub fn str_to_encrypted(s: String) -> (Vec<u8>, Vec<u8>) {
-    let split_index = s.find(";").expect("Nonce division found");
-    let nonce = String::from(&s[0..split_index]);
-    let msg = String::from(&s[split_index + 1..]);
+pub fn str_to_encrypted(s: String) -> Result<Envelope, Error> {
+    let s = s.trim();
+    if !s.contains(';') {
+        return Envelope::from_bytes(&decode_base64(s)?);
+    }
 
-    let nonce_vec = general_purpose::STANDARD.decode(nonce).unwrap();
-    let msg_vec = general_purpose::STANDARD.decode(msg).unwrap();
+    // Messages from before the envelope format: `nonce;ciphertext[;signature]`
+    let parts: Vec<&str> = s.split(';').collect();
+    if parts.len() > 3 {
+        return Err(Error::BadEncoding);
+    }
 
-    (msg_vec, nonce_vec)
+    let signature = match parts.get(2) {
+        Some(signature) => Some(decode_base64(signature)?),
+        None => None,
+    };
+
+    Ok(Envelope {
+        version: LEGACY_VERSION,
+        algorithm: ALG_AES_256_GCM,
+        flags: if signature.is_some() { FLAG_SIGNED } else { 0 },
+        key_id: [0u8; KEY_ID_SIZE],
+        counter: None,
+        ratchet: None,
+        recipients: Vec::new(),
+        nonce: decode_base64(parts[0])?,
+        ciphertext: decode_base64(parts[1])?,
+        signature,
+    })
+}
+
+pub fn decrypt_message(
+    encrypted_msg: Vec<u8>,
+    nonce: &[u8],
+    key: &[u8; 32],
+) -> Result<Vec<u8>, Error> {
+    decrypt_message_with_aad(encrypted_msg, nonce, &[], key)
 }
 
-pub fn decrypt_message(encrypted_msg: Vec<u8>, nonce: &Vec<u8>, key: &[u8; 32]) -> Vec<u8> {
+pub fn decrypt_message_with_aad(
+    encrypted_msg: Vec<u8>,
+    nonce: &[u8],
+    aad: &[u8],
+    key: &[u8; 32],
+) -> Result<Vec<u8>, Error> {
+    if nonce.len() != 12 {
+        return Err(Error::BadEncoding);
+    }
+
     let cipher = Aes256Gcm::new(GenericArray::from_slice(key));
-    let nonce = GenericArray::from_slice(&nonce);
+    let nonce = GenericArray::from_slice(nonce);
 
     cipher
-        .decrypt(&nonce, encrypted_msg.as_ref())
-        .expect("Decryption successful")
+        .decrypt(
+            nonce,
+            Payload {
+                msg: encrypted_msg.as_ref(),
+                aad,
+            },
+        )
+        .map_err(|_| Error::AuthenticationFailed)
 }
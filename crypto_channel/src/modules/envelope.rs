@@ -0,0 +1,387 @@
+use base64::{engine::general_purpose, Engine as _};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::encryption::{decrypt_message_with_aad, encrypt_message_with_aad};
+use crate::error::Error;
+use crate::ratchet::{RatchetHeader, RATCHET_HEADER_SIZE};
+
+pub const ENVELOPE_MAGIC: &[u8; 2] = b"CC";
+pub const LEGACY_VERSION: u8 = 0;
+pub const ENVELOPE_VERSION: u8 = 1;
+pub const ALG_AES_256_GCM: u8 = 1;
+pub const FLAG_SIGNED: u8 = 0b0000_0001;
+pub const FLAG_COUNTER: u8 = 0b0000_0010;
+pub const FLAG_RATCHET: u8 = 0b0000_0100;
+/// The message carries a new Public Key of the sender, see `rotate_keys_for_exchange`.
+pub const FLAG_KEY_ROTATION: u8 = 0b0000_1000;
+/// The message carries a sender key for a group, see `share_sender_key`.
+pub const FLAG_SENDER_KEY: u8 = 0b0001_0000;
+/// The content key is wrapped once per recipient, see `encrypt_for_exchanges`.
+pub const FLAG_MULTI_RECIPIENT: u8 = 0b0010_0000;
+
+pub const KEY_ID_SIZE: usize = 8;
+pub const HEADER_SIZE: usize = 2 + 1 + 1 + 1 + KEY_ID_SIZE;
+const COUNTER_SIZE: usize = 8;
+const NONCE_SIZE: usize = 12;
+const SIGNATURE_SIZE: usize = 64;
+const WRAPPED_KEY_SIZE: usize = KEY_ID_SIZE + COUNTER_SIZE + NONCE_SIZE + 32 + 16;
+pub const MAX_RECIPIENTS: usize = u8::MAX as usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub version: u8,
+    pub algorithm: u8,
+    pub flags: u8,
+    pub key_id: [u8; KEY_ID_SIZE],
+    pub counter: Option<u64>,
+    pub ratchet: Option<RatchetHeader>,
+    pub recipients: Vec<WrappedKey>,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub signature: Option<Vec<u8>>,
+}
+
+/// The content key of a multi-recipient message, encrypted with the key of
+/// one recipient's exchange. The counter is the one of that exchange.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WrappedKey {
+    pub key_id: [u8; KEY_ID_SIZE],
+    pub counter: u64,
+    pub nonce: Vec<u8>,
+    pub wrapped_key: Vec<u8>,
+}
+
+impl WrappedKey {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.key_id.to_vec();
+        bytes.extend_from_slice(&self.counter.to_be_bytes());
+        bytes.extend_from_slice(&self.nonce);
+        bytes.extend_from_slice(&self.wrapped_key);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let (key_id, rest) = bytes.split_at(KEY_ID_SIZE);
+        let (counter, rest) = rest.split_at(COUNTER_SIZE);
+        let (nonce, wrapped_key) = rest.split_at(NONCE_SIZE);
+
+        WrappedKey {
+            key_id: key_id.try_into().unwrap(),
+            counter: u64::from_be_bytes(counter.try_into().unwrap()),
+            nonce: nonce.to_vec(),
+            wrapped_key: wrapped_key.to_vec(),
+        }
+    }
+}
+
+pub fn key_id(key: &[u8; 32]) -> [u8; KEY_ID_SIZE] {
+    let digest = Sha256::new()
+        .chain_update(b"CryptoChannel key id")
+        .chain_update(key)
+        .finalize();
+
+    let mut id = [0u8; KEY_ID_SIZE];
+    id.copy_from_slice(&digest[..KEY_ID_SIZE]);
+    id
+}
+
+impl Envelope {
+    pub fn is_legacy(&self) -> bool {
+        self.version == LEGACY_VERSION
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.flags & FLAG_SIGNED != 0
+    }
+
+    pub fn is_key_rotation(&self) -> bool {
+        self.flags & FLAG_KEY_ROTATION != 0
+    }
+
+    pub fn is_sender_key(&self) -> bool {
+        self.flags & FLAG_SENDER_KEY != 0
+    }
+
+    pub fn is_multi_recipient(&self) -> bool {
+        self.flags & FLAG_MULTI_RECIPIENT != 0
+    }
+
+    pub fn header(&self) -> Vec<u8> {
+        let mut header = ENVELOPE_MAGIC.to_vec();
+        header.push(self.version);
+        header.push(self.algorithm);
+        header.push(self.flags);
+        header.extend_from_slice(&self.key_id);
+        if let Some(counter) = self.counter {
+            header.extend_from_slice(&counter.to_be_bytes());
+        }
+        if let Some(ratchet) = &self.ratchet {
+            header.extend_from_slice(&ratchet.to_bytes());
+        }
+        header
+    }
+
+    /// Bytes covered by the sender's signature, everything but the signature itself.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        if self.is_legacy() {
+            let nonce_str = general_purpose::STANDARD.encode(&self.nonce);
+            let ciphertext_str = general_purpose::STANDARD.encode(&self.ciphertext);
+            return (nonce_str + ";" + &ciphertext_str).into_bytes();
+        }
+
+        let mut bytes = self.header();
+        if self.is_multi_recipient() {
+            bytes.push(self.recipients.len() as u8);
+            for recipient in &self.recipients {
+                bytes.extend_from_slice(&recipient.to_bytes());
+            }
+        }
+        bytes.extend_from_slice(&self.nonce);
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+
+    /// Each wrapped key is bound to the payload, so a recipient can't reuse
+    /// the content key to pass another message off as the sender's.
+    fn wrapping_aad(&self, recipient_id: &[u8; KEY_ID_SIZE], counter: u64) -> Vec<u8> {
+        let mut aad = self.header();
+        aad.extend_from_slice(recipient_id);
+        aad.extend_from_slice(&counter.to_be_bytes());
+        aad.extend_from_slice(&self.nonce);
+        aad.extend_from_slice(&self.ciphertext);
+        aad
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.signed_bytes();
+        if let Some(signature) = &self.signature {
+            bytes.extend_from_slice(signature);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < HEADER_SIZE || &bytes[0..2] != ENVELOPE_MAGIC {
+            return Err(Error::BadEncoding);
+        }
+
+        let version = bytes[2];
+        if version != ENVELOPE_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let algorithm = bytes[3];
+        if algorithm != ALG_AES_256_GCM {
+            return Err(Error::UnsupportedAlgorithm(algorithm));
+        }
+        let flags = bytes[4];
+        let mut key_id = [0u8; KEY_ID_SIZE];
+        key_id.copy_from_slice(&bytes[5..HEADER_SIZE]);
+
+        let mut body = &bytes[HEADER_SIZE..];
+        let mut counter = None;
+        if flags & FLAG_COUNTER != 0 {
+            if body.len() < COUNTER_SIZE {
+                return Err(Error::BadEncoding);
+            }
+            let (counter_bytes, rest) = body.split_at(COUNTER_SIZE);
+            counter = Some(u64::from_be_bytes(counter_bytes.try_into().unwrap()));
+            body = rest;
+        }
+        let mut ratchet = None;
+        if flags & FLAG_RATCHET != 0 {
+            if body.len() < RATCHET_HEADER_SIZE {
+                return Err(Error::BadEncoding);
+            }
+            let (ratchet_bytes, rest) = body.split_at(RATCHET_HEADER_SIZE);
+            ratchet = Some(RatchetHeader::from_bytes(ratchet_bytes)?);
+            body = rest;
+        }
+
+        let mut recipients = Vec::new();
+        if flags & FLAG_MULTI_RECIPIENT != 0 {
+            let (count, rest) = body.split_first().ok_or(Error::BadEncoding)?;
+            let size = *count as usize * WRAPPED_KEY_SIZE;
+            if rest.len() < size {
+                return Err(Error::BadEncoding);
+            }
+            let (recipient_bytes, rest) = rest.split_at(size);
+            recipients = recipient_bytes
+                .chunks(WRAPPED_KEY_SIZE)
+                .map(WrappedKey::from_bytes)
+                .collect();
+            body = rest;
+        }
+
+        let mut signature = None;
+        if flags & FLAG_SIGNED != 0 {
+            if body.len() < SIGNATURE_SIZE {
+                return Err(Error::BadEncoding);
+            }
+            let (rest, sig) = body.split_at(body.len() - SIGNATURE_SIZE);
+            signature = Some(sig.to_vec());
+            body = rest;
+        }
+
+        if body.len() < NONCE_SIZE {
+            return Err(Error::BadEncoding);
+        }
+        let (nonce, ciphertext) = body.split_at(NONCE_SIZE);
+
+        Ok(Envelope {
+            version,
+            algorithm,
+            flags,
+            key_id,
+            counter,
+            ratchet,
+            recipients,
+            nonce: nonce.to_vec(),
+            ciphertext: ciphertext.to_vec(),
+            signature,
+        })
+    }
+}
+
+fn seal(mut envelope: Envelope, message: &[u8], key: &[u8; 32]) -> Result<Envelope, Error> {
+    let (ciphertext, nonce) = encrypt_message_with_aad(message, &envelope.header(), key)?;
+    envelope.nonce = nonce;
+    envelope.ciphertext = ciphertext;
+    Ok(envelope)
+}
+
+/// The counter goes in the header, so it's authenticated along with the rest of it.
+pub fn seal_envelope(
+    message: &[u8],
+    key: &[u8; 32],
+    flags: u8,
+    counter: Option<u64>,
+) -> Result<Envelope, Error> {
+    let mut flags = flags & !(FLAG_COUNTER | FLAG_RATCHET | FLAG_MULTI_RECIPIENT);
+    if counter.is_some() {
+        flags |= FLAG_COUNTER;
+    }
+
+    let envelope = Envelope {
+        version: ENVELOPE_VERSION,
+        algorithm: ALG_AES_256_GCM,
+        flags,
+        key_id: key_id(key),
+        counter,
+        ratchet: None,
+        recipients: Vec::new(),
+        nonce: Vec::new(),
+        ciphertext: Vec::new(),
+        signature: None,
+    };
+    seal(envelope, message, key)
+}
+
+pub fn seal_ratchet_envelope(
+    message: &[u8],
+    message_key: &[u8; 32],
+    flags: u8,
+    ratchet: RatchetHeader,
+) -> Result<Envelope, Error> {
+    let envelope = Envelope {
+        version: ENVELOPE_VERSION,
+        algorithm: ALG_AES_256_GCM,
+        flags: (flags & !(FLAG_COUNTER | FLAG_MULTI_RECIPIENT)) | FLAG_RATCHET,
+        key_id: key_id(message_key),
+        counter: None,
+        ratchet: Some(ratchet),
+        recipients: Vec::new(),
+        nonce: Vec::new(),
+        ciphertext: Vec::new(),
+        signature: None,
+    };
+    seal(envelope, message, message_key)
+}
+
+/// Encrypts the message once with a random content key, and wraps that key
+/// with the key of each recipient, along with the counter of their exchange.
+pub fn seal_multi_recipient_envelope(
+    message: &[u8],
+    recipients: &[(&[u8; 32], u64)],
+) -> Result<Envelope, Error> {
+    if recipients.len() > MAX_RECIPIENTS {
+        return Err(Error::TooManyRecipients(recipients.len()));
+    }
+    let mut content_key = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(content_key.as_mut());
+
+    let envelope = Envelope {
+        version: ENVELOPE_VERSION,
+        algorithm: ALG_AES_256_GCM,
+        flags: FLAG_MULTI_RECIPIENT,
+        key_id: key_id(&content_key),
+        counter: None,
+        ratchet: None,
+        recipients: Vec::new(),
+        nonce: Vec::new(),
+        ciphertext: Vec::new(),
+        signature: None,
+    };
+    let mut envelope = seal(envelope, message, &content_key)?;
+
+    for (key, counter) in recipients {
+        let recipient_id = key_id(key);
+        let aad = envelope.wrapping_aad(&recipient_id, *counter);
+        let (wrapped_key, nonce) = encrypt_message_with_aad(content_key.as_ref(), &aad, key)?;
+        envelope.recipients.push(WrappedKey {
+            key_id: recipient_id,
+            counter: *counter,
+            nonce,
+            wrapped_key,
+        });
+    }
+    Ok(envelope)
+}
+
+/// Opens a multi-recipient message with the key of your exchange, and returns
+/// it with the counter the sender used for that exchange.
+pub fn open_multi_recipient_envelope(
+    envelope: &Envelope,
+    key: &[u8; 32],
+) -> Result<(Vec<u8>, u64), Error> {
+    let recipient_id = key_id(key);
+    let recipient = envelope
+        .recipients
+        .iter()
+        .find(|recipient| recipient.key_id == recipient_id)
+        .ok_or(Error::KeyMismatch)?;
+
+    let aad = envelope.wrapping_aad(&recipient_id, recipient.counter);
+    let unwrapped = Zeroizing::new(decrypt_message_with_aad(
+        recipient.wrapped_key.clone(),
+        &recipient.nonce,
+        &aad,
+        key,
+    )?);
+    let content_key: Zeroizing<[u8; 32]> = Zeroizing::new(
+        unwrapped
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::AuthenticationFailed)?,
+    );
+
+    Ok((open_envelope(envelope, &content_key)?, recipient.counter))
+}
+
+pub fn open_envelope(envelope: &Envelope, key: &[u8; 32]) -> Result<Vec<u8>, Error> {
+    if envelope.is_legacy() {
+        return decrypt_message_with_aad(envelope.ciphertext.clone(), &envelope.nonce, &[], key);
+    }
+
+    if envelope.key_id != key_id(key) {
+        return Err(Error::KeyMismatch);
+    }
+
+    decrypt_message_with_aad(
+        envelope.ciphertext.clone(),
+        &envelope.nonce,
+        &envelope.header(),
+        key,
+    )
+}
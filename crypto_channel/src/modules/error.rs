@@ -0,0 +1,269 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    BadEncoding,
+    BadChecksum,
+    WrongKeyPrefix(String, String),
+    UnsupportedVersion(u8),
+    UnsupportedAlgorithm(u8),
+    WrongKeyLength(usize),
+    EncryptionFailed,
+    AuthenticationFailed,
+    KeyMismatch,
+    MessageKeyUnavailable,
+    InvalidSignature,
+    StorageIo(io::Error),
+    StorageParse(serde_json::Error),
+    StorageCorrupted(serde_json::Error, PathBuf),
+    StorageLocked,
+    UnsupportedStorageVersion(u32),
+    UnsupportedStorageEncryption,
+    SqliteUnavailable,
+    InsecurePermissions(Vec<PathBuf>),
+    BackupIo(io::Error),
+    InvalidBackup,
+    MissingBackupPassphrase,
+    UnsupportedBackupVersion(u32),
+    WrongPassphrase,
+    KeyDerivationFailed(argon2::Error),
+    ConfigIo(io::Error),
+    ConfigParse(serde_json::Error),
+    HomeDirNotFound,
+    UnknownExchange(String),
+    ExchangeExists(String),
+    ExchangeNotCompleted(String),
+    ExchangeNotConfirmed(String),
+    ConfirmationMismatch,
+    MissingKeyExchange,
+    Clipboard(arboard::Error),
+    MessageIo(io::Error),
+    QrCode(qrcode::types::QrError),
+    MessageTooLongForQr(usize),
+    UnsupportedImageFormat(String),
+    KemFailed,
+    WrongExchangeKind(String),
+    HybridResponseExpected(String),
+    UnknownGroup(String),
+    GroupExists(String),
+    NotGroupMember(String, String),
+    ExchangeInGroup(String, String),
+    TooManyRecipients(usize),
+    MultiRecipientWithRatchet,
+    NoMatchingExchange,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadEncoding => write!(
+                f,
+                "The input is not correctly encoded, make sure it was copied \
+                 without typos or omissions"
+            ),
+            Error::BadChecksum => write!(
+                f,
+                "The key has a typo: its checksum doesn't match, check it character by character"
+            ),
+            Error::WrongKeyPrefix(expected, found) => write!(
+                f,
+                "This is a `{}` key, but a `{}` key was expected",
+                found, expected
+            ),
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "Version {} is not supported, you might need to update the tool",
+                version
+            ),
+            Error::UnsupportedAlgorithm(algorithm) => write!(
+                f,
+                "Encryption algorithm {} is not supported, you might need to update the tool",
+                algorithm
+            ),
+            Error::WrongKeyLength(len) => {
+                write!(
+                    f,
+                    "Keys must be 32 bytes long, but this one has {} bytes",
+                    len
+                )
+            }
+            Error::EncryptionFailed => write!(f, "Failed to encrypt the message"),
+            Error::AuthenticationFailed => write!(
+                f,
+                "The message could not be authenticated. It might have been \
+                 encrypted for another Key Exchange or tampered with"
+            ),
+            Error::KeyMismatch => write!(f, "The message was encrypted for another Key Exchange"),
+            Error::MessageKeyUnavailable => write!(
+                f,
+                "The key of this message is not available anymore. It was \
+                 already decrypted, or too many messages were skipped"
+            ),
+            Error::InvalidSignature => write!(
+                f,
+                "The message signature is invalid, it might have been forged"
+            ),
+            Error::StorageIo(e) => write!(f, "Failed to access the key storage: {}", e),
+            Error::StorageParse(e) => write!(f, "The key storage file is corrupted: {}", e),
+            Error::StorageCorrupted(e, backup) => write!(
+                f,
+                "The key storage file is corrupted: {}. The previous version is kept at `{}`",
+                e,
+                backup.display()
+            ),
+            Error::StorageLocked => {
+                write!(f, "Storage is encrypted but no passphrase was provided")
+            }
+            Error::UnsupportedStorageVersion(version) => write!(
+                f,
+                "The key storage was written by a newer version of CryptoChannel \
+                 (layout version {}), please update the tool",
+                version
+            ),
+            Error::UnsupportedStorageEncryption => write!(
+                f,
+                "Storage encryption is only available with the `json` storage backend"
+            ),
+            Error::SqliteUnavailable => write!(
+                f,
+                "This build of CryptoChannel doesn't include the `sqlite` storage backend"
+            ),
+            Error::BackupIo(e) => write!(f, "Failed to read or write the backup: {}", e),
+            Error::InvalidBackup => write!(f, "The file is not a CryptoChannel backup"),
+            Error::MissingBackupPassphrase => {
+                write!(f, "No passphrase was provided for the backup")
+            }
+            Error::UnsupportedBackupVersion(version) => write!(
+                f,
+                "The backup was made by a newer version of CryptoChannel \
+                 (backup version {}), please update the tool",
+                version
+            ),
+            Error::InsecurePermissions(paths) => {
+                let paths: Vec<String> = paths
+                    .iter()
+                    .map(|path| format!("`{}`", path.display()))
+                    .collect();
+                write!(
+                    f,
+                    "Other users can access {}. Run `crypto_channel fix-permissions` \
+                     to restrict them, or use --allow-insecure-permissions",
+                    paths.join(", ")
+                )
+            }
+            Error::WrongPassphrase => write!(f, "Wrong passphrase or corrupted storage file"),
+            Error::KeyDerivationFailed(e) => write!(
+                f,
+                "Failed to derive the key from the passphrase, the Argon2id settings \
+                 might not be supported: {}",
+                e
+            ),
+            Error::ConfigIo(e) => write!(f, "Failed to access the config file: {}", e),
+            Error::ConfigParse(e) => write!(f, "The config file is invalid: {}", e),
+            Error::HomeDirNotFound => write!(f, "Could not find the home directory"),
+            Error::UnknownExchange(name) => {
+                write!(f, "Key Exchange `{}` doesn't exist in Key Storage", name)
+            }
+            Error::ExchangeExists(name) => {
+                write!(f, "Key Exchange `{}` already exists in Key Storage", name)
+            }
+            Error::ExchangeNotCompleted(name) => {
+                write!(f, "Key Exchange was not completed for `{}`", name)
+            }
+            Error::ExchangeNotConfirmed(name) => write!(
+                f,
+                "Key Exchange `{}` is waiting for the confirmation token of the other party",
+                name
+            ),
+            Error::ConfirmationMismatch => write!(
+                f,
+                "The confirmation token doesn't match. The Public Keys might have \
+                 been mistyped or swapped, the Key Exchange can't be used"
+            ),
+            Error::Clipboard(e) => write!(f, "Failed to access the clipboard: {}", e),
+            Error::MessageIo(e) => write!(f, "Failed to read or write the message: {}", e),
+            Error::QrCode(e) => write!(f, "Failed to create the QR code: {}", e),
+            Error::MessageTooLongForQr(len) => write!(
+                f,
+                "The encrypted message is too long for a QR code, it has {} characters, \
+                 at most {} fit",
+                len,
+                crate::qr::MAX_QR_MESSAGE_LEN
+            ),
+            Error::UnsupportedImageFormat(ext) => write!(
+                f,
+                "Can't export a QR code to a `{}` file, use a `.png` or `.svg` file",
+                ext
+            ),
+            Error::KemFailed => write!(
+                f,
+                "The ML-KEM operation failed, the key or ciphertext might be corrupted"
+            ),
+            Error::WrongExchangeKind(name) => write!(
+                f,
+                "This key doesn't match the kind of the Key Exchange `{}`: classic \
+                 exchanges use `ccpub` keys, hybrid ones `cchpub` keys or `cchct` responses",
+                name
+            ),
+            Error::HybridResponseExpected(name) => write!(
+                f,
+                "The other party completes the hybrid Key Exchange `{}` first: send them \
+                 your Public Key if you haven't yet, and complete it with the `cchct` \
+                 response they send back",
+                name
+            ),
+            Error::UnknownGroup(name) => {
+                write!(f, "Group Channel `{}` doesn't exist in Key Storage", name)
+            }
+            Error::GroupExists(name) => {
+                write!(f, "Group Channel `{}` already exists in Key Storage", name)
+            }
+            Error::NotGroupMember(exchange, group) => write!(
+                f,
+                "`{}` is not a member of the Group Channel `{}`, add them to the group first",
+                exchange, group
+            ),
+            Error::ExchangeInGroup(exchange, group) => write!(
+                f,
+                "`{}` is a member of the Group Channel `{}`, remove them from the group first",
+                exchange, group
+            ),
+            Error::TooManyRecipients(count) => write!(
+                f,
+                "A message can be encrypted for at most 255 recipients, not {}",
+                count
+            ),
+            Error::MultiRecipientWithRatchet => write!(
+                f,
+                "A message can't be encrypted for several Key Exchanges while the \
+                 ratchet is on, encrypt it for each of them instead"
+            ),
+            Error::NoMatchingExchange => write!(
+                f,
+                "None of your Key Exchanges can decrypt this message, it might \
+                 have been encrypted for someone else"
+            ),
+            Error::MissingKeyExchange => write!(
+                f,
+                "A Key Signature can't be stored without its Key Exchange"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::StorageIo(e) | Error::ConfigIo(e) | Error::MessageIo(e) | Error::BackupIo(e) => {
+                Some(e)
+            }
+            Error::StorageParse(e) | Error::StorageCorrupted(e, _) | Error::ConfigParse(e) => {
+                Some(e)
+            }
+            Error::Clipboard(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use aead::stream::{DecryptorBE32, EncryptorBE32};
+use aes_gcm::aead::{generic_array::GenericArray, KeyInit};
+use aes_gcm::Aes256Gcm;
+use rand_core::{OsRng, RngCore};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::storage_file::recreate_private_file;
+
+pub const FILE_MAGIC: &[u8; 4] = b"CCFS";
+pub const FILE_FORMAT_VERSION: u8 = 1;
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+const TAG_SIZE: usize = 16;
+const STREAM_NONCE_SIZE: usize = 7;
+
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Error::MessageIo(e)),
+        }
+    }
+    Ok(filled)
+}
+
+pub fn encrypt_stream<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    key: &[u8; 32],
+) -> Result<(), Error> {
+    let mut nonce = [0u8; STREAM_NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+
+    let cipher = Aes256Gcm::new(GenericArray::from_slice(key));
+    let mut encryptor = EncryptorBE32::from_aead(cipher, GenericArray::from_slice(&nonce));
+
+    let mut header = FILE_MAGIC.to_vec();
+    header.push(FILE_FORMAT_VERSION);
+    header.extend_from_slice(&(CHUNK_SIZE as u32).to_be_bytes());
+    header.extend_from_slice(&nonce);
+    writer.write_all(&header).map_err(Error::MessageIo)?;
+
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    let mut next_chunk = vec![0u8; CHUNK_SIZE];
+    let mut chunk_len = read_full(&mut reader, &mut chunk)?;
+
+    loop {
+        let next_len = if chunk_len == CHUNK_SIZE {
+            read_full(&mut reader, &mut next_chunk)?
+        } else {
+            0
+        };
+
+        if next_len == 0 {
+            let ciphertext = encryptor
+                .encrypt_last(&chunk[..chunk_len])
+                .map_err(|_| Error::EncryptionFailed)?;
+            writer.write_all(&ciphertext).map_err(Error::MessageIo)?;
+            break;
+        }
+
+        let ciphertext = encryptor
+            .encrypt_next(&chunk[..chunk_len])
+            .map_err(|_| Error::EncryptionFailed)?;
+        writer.write_all(&ciphertext).map_err(Error::MessageIo)?;
+
+        std::mem::swap(&mut chunk, &mut next_chunk);
+        chunk_len = next_len;
+    }
+
+    writer.flush().map_err(Error::MessageIo)
+}
+
+pub fn decrypt_stream<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    key: &[u8; 32],
+) -> Result<(), Error> {
+    let mut header = [0u8; 4 + 1 + 4 + STREAM_NONCE_SIZE];
+    if read_full(&mut reader, &mut header)? != header.len() || &header[0..4] != FILE_MAGIC {
+        return Err(Error::BadEncoding);
+    }
+    if header[4] != FILE_FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(header[4]));
+    }
+
+    let chunk_size = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as usize;
+    if chunk_size == 0 || chunk_size > 16 * 1024 * 1024 {
+        return Err(Error::BadEncoding);
+    }
+    let nonce = &header[9..];
+
+    let cipher = Aes256Gcm::new(GenericArray::from_slice(key));
+    let mut decryptor = DecryptorBE32::from_aead(cipher, GenericArray::from_slice(nonce));
+
+    let encrypted_chunk_size = chunk_size + TAG_SIZE;
+    let mut chunk = vec![0u8; encrypted_chunk_size];
+    let mut next_chunk = vec![0u8; encrypted_chunk_size];
+    let mut chunk_len = read_full(&mut reader, &mut chunk)?;
+
+    loop {
+        let next_len = if chunk_len == encrypted_chunk_size {
+            read_full(&mut reader, &mut next_chunk)?
+        } else {
+            0
+        };
+
+        if next_len == 0 {
+            let plaintext = decryptor
+                .decrypt_last(&chunk[..chunk_len])
+                .map_err(|_| Error::AuthenticationFailed)?;
+            writer.write_all(&plaintext).map_err(Error::MessageIo)?;
+            break;
+        }
+
+        let plaintext = decryptor
+            .decrypt_next(&chunk[..chunk_len])
+            .map_err(|_| Error::AuthenticationFailed)?;
+        writer.write_all(&plaintext).map_err(Error::MessageIo)?;
+
+        std::mem::swap(&mut chunk, &mut next_chunk);
+        chunk_len = next_len;
+    }
+
+    writer.flush().map_err(Error::MessageIo)
+}
+
+/// Writes to `<output>.partial`, renamed to `output` once `write` succeeded, so
+/// `output` can be the input file and is never left half written. The file is
+/// only readable by you, as it may hold the decrypted contents.
+pub(crate) fn write_through_partial_file(
+    output: &Path,
+    write: impl FnOnce(BufWriter<File>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut partial_name = output.as_os_str().to_owned();
+    partial_name.push(".partial");
+    let partial_path = Path::new(&partial_name);
+    let file = recreate_private_file(partial_path).map_err(Error::MessageIo)?;
+
+    match write(BufWriter::new(file)) {
+        Ok(_) => fs::rename(partial_path, output).map_err(Error::MessageIo),
+        Err(e) => {
+            _ = fs::remove_file(partial_path);
+            Err(e)
+        }
+    }
+}
+
+pub fn encrypt_file(input: &Path, output: &Path, key: &[u8; 32]) -> Result<(), Error> {
+    let reader = BufReader::new(File::open(input).map_err(Error::MessageIo)?);
+    write_through_partial_file(output, |writer| encrypt_stream(reader, writer, key))
+}
+
+pub fn decrypt_file(input: &Path, output: &Path, key: &[u8; 32]) -> Result<(), Error> {
+    let reader = BufReader::new(File::open(input).map_err(Error::MessageIo)?);
+    // Nothing is written to `output` until every chunk was authenticated.
+    write_through_partial_file(output, |writer| decrypt_stream(reader, writer, key))
+}
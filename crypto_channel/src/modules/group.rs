@@ -0,0 +1,199 @@
+use crate::encryption::{encrypted_to_str, str_to_encrypted};
+use crate::envelope::{open_envelope, seal_envelope, FLAG_SENDER_KEY, FLAG_SIGNED};
+use crate::error::Error;
+use crate::key_store::{
+    add_group_member, get_group, lock_storage, new_group, next_group_send_counter,
+    record_group_counter, remove_group_member, save_group,
+};
+use crate::key_store_classes::{GroupChannel, MessageOrder, SenderKey, GROUP_ID_SIZE};
+use crate::message::seal_for_exchange;
+use crate::signature::{sign_message, verify_signature};
+
+use zeroize::Zeroizing;
+
+const DISTRIBUTION_HEADER_SIZE: usize = GROUP_ID_SIZE + 4 + 32 + 32;
+
+/// Your sender key for a group, sent to a member inside a message encrypted
+/// for your Key Exchange with them.
+#[derive(Debug, PartialEq)]
+pub struct SenderKeyDistribution {
+    pub group_id: [u8; GROUP_ID_SIZE],
+    pub generation: u32,
+    pub key: [u8; 32],
+    pub signing_public: [u8; 32],
+    pub group_name: String,
+}
+
+/// What happened to a sender key received for a group, named in each case.
+#[derive(Debug, PartialEq)]
+pub enum SenderKeyUpdate {
+    Stored(String),
+    /// A newer key of the sender is already known, it might be a replay.
+    Outdated(String),
+    /// The sender is not a member yet, the key is used once you add them.
+    NotMember(String),
+}
+
+#[derive(Debug)]
+pub struct DecryptedGroupMessage {
+    pub message: Zeroizing<Vec<u8>>,
+    /// Your Key Exchange with the member who sent the message.
+    pub sender: String,
+    pub order: MessageOrder,
+}
+
+impl SenderKeyDistribution {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.group_id.to_vec();
+        bytes.extend_from_slice(&self.generation.to_be_bytes());
+        bytes.extend_from_slice(&self.key);
+        bytes.extend_from_slice(&self.signing_public);
+        bytes.extend_from_slice(self.group_name.as_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < DISTRIBUTION_HEADER_SIZE {
+            return Err(Error::BadEncoding);
+        }
+
+        let (header, name) = bytes.split_at(DISTRIBUTION_HEADER_SIZE);
+        let mut group_id = [0u8; GROUP_ID_SIZE];
+        let mut key = [0u8; 32];
+        let mut signing_public = [0u8; 32];
+        group_id.copy_from_slice(&header[..GROUP_ID_SIZE]);
+        let generation =
+            u32::from_be_bytes(header[GROUP_ID_SIZE..GROUP_ID_SIZE + 4].try_into().unwrap());
+        key.copy_from_slice(&header[GROUP_ID_SIZE + 4..GROUP_ID_SIZE + 36]);
+        signing_public.copy_from_slice(&header[GROUP_ID_SIZE + 36..]);
+
+        Ok(SenderKeyDistribution {
+            group_id,
+            generation,
+            key,
+            signing_public,
+            group_name: String::from_utf8(name.to_vec()).map_err(|_| Error::BadEncoding)?,
+        })
+    }
+
+    pub fn to_sender_key(&self) -> SenderKey {
+        SenderKey::from_member(self.generation, self.key, self.signing_public)
+    }
+}
+
+/// Encrypts your current sender key for every member of the group. Returns
+/// the name of each member with the message to send them.
+pub fn share_sender_key(group_name: String) -> Result<Vec<(String, String)>, Error> {
+    let group = get_group(group_name.clone())?;
+    seal_sender_key(group_name, &group)
+}
+
+fn seal_sender_key(
+    group_name: String,
+    group: &GroupChannel,
+) -> Result<Vec<(String, String)>, Error> {
+    let distribution = SenderKeyDistribution {
+        group_id: group.group_id,
+        generation: group.sender_key.generation,
+        key: group.sender_key.key,
+        signing_public: group.sender_key.signing_public,
+        group_name,
+    }
+    .to_bytes();
+
+    group
+        .members
+        .iter()
+        .map(|member| {
+            let (encoded_msg, _) =
+                seal_for_exchange(member.clone(), &distribution, FLAG_SENDER_KEY)?;
+            Ok((member.clone(), encoded_msg))
+        })
+        .collect()
+}
+
+/// Saves the group with its new sender key once it was sealed for every
+/// member, so you never encrypt with a key some of them can't get.
+fn share_new_sender_key(
+    group_name: String,
+    group: GroupChannel,
+) -> Result<Vec<(String, String)>, Error> {
+    let messages = seal_sender_key(group_name.clone(), &group)?;
+    save_group(group_name, group)?;
+    Ok(messages)
+}
+
+pub fn create_group(
+    group_name: String,
+    members: Vec<String>,
+) -> Result<Vec<(String, String)>, Error> {
+    new_group(group_name.clone(), members)?;
+    share_sender_key(group_name)
+}
+
+/// Adds a member and replaces your sender key, so they can't read the
+/// messages sent before. Every member gets the new key.
+pub fn add_member(
+    group_name: String,
+    exchange_name: String,
+) -> Result<Vec<(String, String)>, Error> {
+    let _lock = lock_storage()?;
+    let group = add_group_member(group_name.clone(), exchange_name)?;
+    share_new_sender_key(group_name, group)
+}
+
+/// Removes a member and replaces your sender key, so they can't read the next
+/// messages. The other members should remove them too, to replace theirs.
+pub fn remove_member(
+    group_name: String,
+    exchange_name: String,
+) -> Result<Vec<(String, String)>, Error> {
+    let _lock = lock_storage()?;
+    let group = remove_group_member(group_name.clone(), exchange_name)?;
+    share_new_sender_key(group_name, group)
+}
+
+/// Encrypts a message once for all the members of the group.
+pub fn encrypt_for_group(group_name: String, message: &[u8]) -> Result<String, Error> {
+    // Held from taking the counter until the message is sealed, like for
+    // Key Exchanges.
+    let _lock = lock_storage()?;
+    let (sender_key, counter) = next_group_send_counter(group_name)?;
+
+    let mut envelope = seal_envelope(message, &sender_key.key, FLAG_SIGNED, Some(counter))?;
+    let signature = sign_message(&envelope.signed_bytes(), &sender_key.signing_secret);
+    envelope.signature = Some(signature.to_vec());
+    Ok(encrypted_to_str(&envelope))
+}
+
+pub fn decrypt_for_group(
+    group_name: String,
+    encoded_msg: String,
+) -> Result<DecryptedGroupMessage, Error> {
+    let envelope = str_to_encrypted(encoded_msg)?;
+    let group = get_group(group_name.clone())?;
+
+    let sender = group
+        .find_sender(&envelope.key_id)
+        .ok_or(Error::KeyMismatch)?;
+    let sender_key = &group.member_keys[sender];
+
+    let signature = envelope.signature.as_ref().ok_or(Error::InvalidSignature)?;
+    verify_signature(
+        &envelope.signed_bytes(),
+        signature,
+        &sender_key.signing_public,
+    )?;
+    let message = open_envelope(&envelope, &sender_key.key)?;
+
+    let order = match envelope.counter {
+        Some(counter) => record_group_counter(group_name, sender.to_string(), counter)?,
+        None => MessageOrder::Untracked,
+    };
+
+    Ok(DecryptedGroupMessage {
+        message: Zeroizing::new(message),
+        sender: sender.to_string(),
+        order,
+    })
+}
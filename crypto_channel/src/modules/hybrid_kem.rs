@@ -0,0 +1,73 @@
+use aws_lc_rs::kem::{Ciphertext, DecapsulationKey, EncapsulationKey, ML_KEM_768};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::error::Error;
+
+pub const KEM_PUBLIC_KEY_SIZE: usize = 1184;
+pub const KEM_SECRET_KEY_SIZE: usize = 2400;
+pub const KEM_CIPHERTEXT_SIZE: usize = 1088;
+
+const HYBRID_LABEL: &[u8] = b"CryptoChannel hybrid X25519 ML-KEM-768";
+
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct KemKeyPair {
+    pub public: Vec<u8>,
+    pub secret: Vec<u8>,
+}
+
+fn to_shared_secret(bytes: &[u8]) -> Result<[u8; 32], Error> {
+    bytes.try_into().map_err(|_| Error::KemFailed)
+}
+
+pub fn gen_kem_kp() -> Result<KemKeyPair, Error> {
+    let secret = DecapsulationKey::generate(&ML_KEM_768).map_err(|_| Error::KemFailed)?;
+    let public = secret
+        .encapsulation_key()
+        .and_then(|public| public.key_bytes())
+        .map_err(|_| Error::KemFailed)?;
+    let secret_bytes = secret.key_bytes().map_err(|_| Error::KemFailed)?;
+
+    Ok(KemKeyPair {
+        public: public.as_ref().to_vec(),
+        secret: secret_bytes.as_ref().to_vec(),
+    })
+}
+
+/// Creates a new secret for the owner of `public`, returns the ciphertext to
+/// send them and the secret.
+pub fn kem_encapsulate(public: &[u8]) -> Result<(Vec<u8>, [u8; 32]), Error> {
+    let public = EncapsulationKey::new(&ML_KEM_768, public).map_err(|_| Error::KemFailed)?;
+    let (ciphertext, shared_secret) = public.encapsulate().map_err(|_| Error::KemFailed)?;
+
+    Ok((
+        ciphertext.as_ref().to_vec(),
+        to_shared_secret(shared_secret.as_ref())?,
+    ))
+}
+
+pub fn kem_decapsulate(secret: &[u8], ciphertext: &[u8]) -> Result<[u8; 32], Error> {
+    if ciphertext.len() != KEM_CIPHERTEXT_SIZE {
+        return Err(Error::KemFailed);
+    }
+    let secret = DecapsulationKey::new(&ML_KEM_768, secret).map_err(|_| Error::KemFailed)?;
+    let shared_secret = secret
+        .decapsulate(Ciphertext::from(ciphertext))
+        .map_err(|_| Error::KemFailed)?;
+    to_shared_secret(shared_secret.as_ref())
+}
+
+/// Combines the X25519 and ML-KEM secrets, the result stays secret as long as
+/// one of the two algorithms isn't broken. The ciphertext is bound to it too.
+pub fn hybrid_shared_secret(
+    x25519_secret: [u8; 32],
+    kem_secret: [u8; 32],
+    kem_ciphertext: &[u8],
+) -> [u8; 32] {
+    let hk = Hkdf::<Sha256>::new(Some(HYBRID_LABEL), &[x25519_secret, kem_secret].concat());
+    let mut okm = [0u8; 32];
+    hk.expand(kem_ciphertext, &mut okm)
+        .expect("32 is a valid length for Sha256 to output");
+    okm
+}
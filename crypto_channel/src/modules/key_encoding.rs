@@ -0,0 +1,236 @@
+use crate::error::Error;
+use crate::hybrid_kem::{KEM_CIPHERTEXT_SIZE, KEM_PUBLIC_KEY_SIZE};
+use crate::key_exchange::{string_to_key, CURRENT_KDF_VERSION, KDF_V1};
+
+pub const PUBLIC_KEY_PREFIX: &str = "ccpub";
+pub const SIGNING_KEY_PREFIX: &str = "ccsig";
+pub const HYBRID_PUBLIC_KEY_PREFIX: &str = "cchpub";
+pub const HYBRID_RESPONSE_PREFIX: &str = "cchct";
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+const BECH32M_CONST: u32 = 0x2bc830a3;
+const CHECKSUM_SIZE: usize = 6;
+const MAX_LENGTH: usize = 90;
+// Hybrid keys carry an ML-KEM key or ciphertext. Past 90 characters the
+// checksum still catches typos, just without the guarantee for up to four.
+const MAX_LONG_LENGTH: usize = 2048;
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut chk: u32 = 1;
+    for value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ (*value as u32);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut values: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|c| c & 31));
+    values
+}
+
+fn create_checksum(hrp: &str, data: &[u8]) -> Vec<u8> {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0u8; CHECKSUM_SIZE]);
+
+    let chk = polymod(&values) ^ BECH32M_CONST;
+    (0..CHECKSUM_SIZE)
+        .map(|i| ((chk >> (5 * (5 - i))) & 31) as u8)
+        .collect()
+}
+
+fn to_five_bits(bytes: &[u8]) -> Vec<u8> {
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    let mut groups = Vec::new();
+    for byte in bytes {
+        acc = (acc << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            groups.push(((acc >> bits) & 31) as u8);
+        }
+    }
+    if bits > 0 {
+        groups.push(((acc << (5 - bits)) & 31) as u8);
+    }
+    groups
+}
+
+fn from_five_bits(groups: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    let mut bytes = Vec::new();
+    for group in groups {
+        acc = (acc << 5) | *group as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push(((acc >> bits) & 255) as u8);
+        }
+    }
+    if bits >= 5 || (acc << (8 - bits)) & 255 != 0 {
+        return Err(Error::BadEncoding);
+    }
+    Ok(bytes)
+}
+
+/// Encodes `data` with bech32m: a human readable prefix, then the data and a
+/// checksum that detects any typo of up to four characters.
+pub fn bech32_encode(hrp: &str, data: &[u8]) -> String {
+    let groups = to_five_bits(data);
+    let checksum = create_checksum(hrp, &groups);
+
+    let mut encoded = String::from(hrp);
+    encoded.push('1');
+    encoded.extend(
+        groups
+            .iter()
+            .chain(checksum.iter())
+            .map(|g| CHARSET[*g as usize] as char),
+    );
+    encoded
+}
+
+pub fn bech32_decode(s: &str) -> Result<(String, Vec<u8>), Error> {
+    decode(s, MAX_LENGTH)
+}
+
+pub fn bech32_decode_long(s: &str) -> Result<(String, Vec<u8>), Error> {
+    decode(s, MAX_LONG_LENGTH)
+}
+
+fn decode(s: &str, max_length: usize) -> Result<(String, Vec<u8>), Error> {
+    if s.len() > max_length || !s.is_ascii() {
+        return Err(Error::BadEncoding);
+    }
+    if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(Error::BadEncoding);
+    }
+    let s = s.to_ascii_lowercase();
+
+    let separator = s.rfind('1').ok_or(Error::BadEncoding)?;
+    let (hrp, data) = (&s[..separator], &s[separator + 1..]);
+    if hrp.is_empty() || data.len() < CHECKSUM_SIZE {
+        return Err(Error::BadEncoding);
+    }
+
+    let groups = data
+        .bytes()
+        .map(|c| {
+            CHARSET
+                .iter()
+                .position(|x| *x == c)
+                .map(|p| p as u8)
+                .ok_or(Error::BadEncoding)
+        })
+        .collect::<Result<Vec<u8>, Error>>()?;
+
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(&groups);
+    if polymod(&values) != BECH32M_CONST {
+        return Err(Error::BadChecksum);
+    }
+
+    let data = from_five_bits(&groups[..groups.len() - CHECKSUM_SIZE])?;
+    Ok((hrp.to_string(), data))
+}
+
+pub fn encode_public_key(key: [u8; 32], prefix: &str) -> String {
+    bech32_encode(prefix, &key)
+}
+
+/// Accepts keys in the checksummed format, with the expected prefix, or in the
+/// base64 format of older versions, which always ends with `=`.
+pub fn parse_public_key(key_str: &str, prefix: &str) -> Result<[u8; 32], Error> {
+    let key_str = key_str.trim();
+    if key_str.ends_with('=') {
+        return string_to_key(key_str.to_string());
+    }
+
+    let (hrp, data) = bech32_decode(key_str)?;
+    if hrp != prefix {
+        return Err(Error::WrongKeyPrefix(prefix.to_string(), hrp));
+    }
+    if data.len() != 32 {
+        return Err(Error::WrongKeyLength(data.len()));
+    }
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&data);
+    Ok(key)
+}
+
+/// Shown when a Key Exchange is completed with a `PeerKey::Legacy` key: the
+/// other party only derives the same keys if they got a base64 key too.
+pub const LEGACY_KEY_WARNING: &str = "Warning: this Public Key is in the base64 format \
+     of older versions, so the Key Exchange derives its keys like they do (KDF v1). \
+     Send the other party your Public Key in the same format, shown below, and make \
+     sure they complete their Key Exchange with it, or your messages can't be decrypted.";
+
+/// A key received from the other party, to complete a Key Exchange with.
+#[derive(Debug, PartialEq)]
+pub enum PeerKey {
+    Classic([u8; 32]),
+    /// A Public Key in the base64 format of older versions, which only derive
+    /// keys with KDF v1.
+    Legacy([u8; 32]),
+    /// X25519 key and ML-KEM encapsulation key of a hybrid exchange.
+    HybridPublic([u8; 32], Vec<u8>),
+    /// X25519 key and ML-KEM ciphertext, sent back by the party that completed
+    /// the hybrid exchange first.
+    HybridResponse([u8; 32], Vec<u8>),
+}
+
+impl PeerKey {
+    /// The newest KDF version the other party can derive keys with, as told
+    /// by the format of their key.
+    pub fn kdf_version(&self) -> u8 {
+        match self {
+            PeerKey::Legacy(_) => KDF_V1,
+            _ => CURRENT_KDF_VERSION,
+        }
+    }
+}
+
+pub fn encode_hybrid_key(dh_public: [u8; 32], kem_bytes: &[u8], prefix: &str) -> String {
+    bech32_encode(prefix, &[&dh_public, kem_bytes].concat())
+}
+
+fn parse_hybrid_key(data: Vec<u8>, kem_size: usize) -> Result<([u8; 32], Vec<u8>), Error> {
+    if data.len() != 32 + kem_size {
+        return Err(Error::BadEncoding);
+    }
+    let mut dh_public = [0u8; 32];
+    dh_public.copy_from_slice(&data[..32]);
+    Ok((dh_public, data[32..].to_vec()))
+}
+
+/// Parses any kind of Public Key, the prefix tells which one it is.
+pub fn parse_peer_key(key_str: &str) -> Result<PeerKey, Error> {
+    let key_str = key_str.trim();
+    if key_str.ends_with('=') {
+        return string_to_key(key_str.to_string()).map(PeerKey::Legacy);
+    }
+    if key_str.len() <= MAX_LENGTH {
+        return parse_public_key(key_str, PUBLIC_KEY_PREFIX).map(PeerKey::Classic);
+    }
+
+    let (hrp, data) = bech32_decode_long(key_str)?;
+    match hrp.as_str() {
+        HYBRID_PUBLIC_KEY_PREFIX => parse_hybrid_key(data, KEM_PUBLIC_KEY_SIZE)
+            .map(|(dh_public, kem_public)| PeerKey::HybridPublic(dh_public, kem_public)),
+        HYBRID_RESPONSE_PREFIX => parse_hybrid_key(data, KEM_CIPHERTEXT_SIZE)
+            .map(|(dh_public, ciphertext)| PeerKey::HybridResponse(dh_public, ciphertext)),
+        _ => Err(Error::WrongKeyPrefix(PUBLIC_KEY_PREFIX.to_string(), hrp)),
+    }
+}
@@ -1,10 +1,26 @@
 use base64::{engine::general_purpose, Engine as _};
 use hex_literal::hex;
 use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
 use rand_core::OsRng;
-use sha2::Sha256;
-use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret, StaticSecret};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
+use crate::error::Error;
+
+pub const KDF_V1: u8 = 1;
+pub const KDF_V2: u8 = 2;
+pub const CURRENT_KDF_VERSION: u8 = KDF_V2;
+
+const KDF_V2_LABEL: &[u8] = b"CryptoChannel KDF v2";
+const SAFETY_NUMBER_LABEL: &[u8] = b"CryptoChannel safety number";
+const SAFETY_NUMBER_ITERATIONS: usize = 5200;
+const SAFETY_NUMBER_GROUPS: usize = 6;
+const CONFIRMATION_LABEL: &[u8] = b"CryptoChannel key confirmation";
+const CONFIRMATION_TOKEN_SIZE: usize = 8;
+
+#[derive(Zeroize, ZeroizeOnDrop)]
 pub struct DhKeyPair {
     pub public: [u8; 32],
     pub secret: [u8; 32],
@@ -30,19 +46,19 @@ pub fn key_to_string(key: [u8; 32]) -> String {
     general_purpose::STANDARD.encode(key)
 }
 
-pub fn string_to_key(key_str: String) -> [u8; 32] {
-    let key_vec = general_purpose::STANDARD.decode(key_str).unwrap();
+pub fn string_to_key(key_str: String) -> Result<[u8; 32], Error> {
+    let key_vec = general_purpose::STANDARD
+        .decode(key_str.trim())
+        .map_err(|_| Error::BadEncoding)?;
 
     if key_vec.len() != 32 {
-        panic!("Key Vector has not 32 elements!");
+        return Err(Error::WrongKeyLength(key_vec.len()));
     }
 
     let mut key_arr = [0u8; 32];
-    for i in 0..32 {
-        key_arr[i] = key_vec[i];
-    }
+    key_arr.copy_from_slice(&key_vec);
 
-    key_arr
+    Ok(key_arr)
 }
 
 pub fn gen_shared_secret(pub_k: [u8; 32], priv_k: [u8; 32]) -> [u8; 32] {
@@ -60,3 +76,167 @@ pub fn gen_encryption_key(prk: [u8; 32]) -> [u8; 32] {
         .expect("32 is a valid length for Sha256 to output");
     okm
 }
+
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct ChannelKeys {
+    pub send_key: [u8; 32],
+    pub receive_key: [u8; 32],
+    pub mac_key: [u8; 32],
+}
+
+fn sorted_public_keys(your_public: [u8; 32], other_public: [u8; 32]) -> Vec<u8> {
+    let (first, second) = if your_public <= other_public {
+        (your_public, other_public)
+    } else {
+        (other_public, your_public)
+    };
+    [first, second].concat()
+}
+
+fn expand_key(hk: &Hkdf<Sha256>, purpose: &[u8], context: &[u8]) -> [u8; 32] {
+    let info = [KDF_V2_LABEL, purpose, context].concat();
+    let mut okm = [0u8; 32];
+    hk.expand(&info, &mut okm)
+        .expect("32 is a valid length for Sha256 to output");
+    okm
+}
+
+/// KDF v2: extracts from the shared secret with a salt bound to both public keys,
+/// then expands one key per sending direction and a MAC key.
+pub fn derive_channel_keys(
+    shared_secret: [u8; 32],
+    your_public: [u8; 32],
+    other_public: [u8; 32],
+) -> ChannelKeys {
+    let public_keys = sorted_public_keys(your_public, other_public);
+    let salt = [KDF_V2_LABEL, &public_keys].concat();
+    let hk = Hkdf::<Sha256>::new(Some(&salt), &shared_secret);
+
+    // Each direction is named after its sender, so both parties agree on it.
+    ChannelKeys {
+        send_key: expand_key(&hk, b" message key from ", &your_public),
+        receive_key: expand_key(&hk, b" message key from ", &other_public),
+        mac_key: expand_key(&hk, b" mac key ", &public_keys),
+    }
+}
+
+/// A 30 digit number computed from both public keys, the same for both parties.
+/// Comparing it out-of-band proves nobody swapped the keys in transit.
+pub fn safety_number(your_public: [u8; 32], other_public: [u8; 32]) -> String {
+    let public_keys = sorted_public_keys(your_public, other_public);
+
+    // Iterated so that finding a key with a matching number is expensive.
+    let mut digest = Sha256::new()
+        .chain_update(SAFETY_NUMBER_LABEL)
+        .chain_update(&public_keys)
+        .finalize();
+    for _ in 0..SAFETY_NUMBER_ITERATIONS {
+        digest = Sha256::new()
+            .chain_update(digest)
+            .chain_update(&public_keys)
+            .finalize();
+    }
+
+    digest
+        .chunks(5)
+        .take(SAFETY_NUMBER_GROUPS)
+        .map(|chunk| {
+            let mut bytes = [0u8; 8];
+            bytes[3..].copy_from_slice(chunk);
+            format!("{:05}", u64::from_be_bytes(bytes) % 100000)
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Key for the confirmation round of exchanges derived with KDF v1, which
+/// don't have a MAC key.
+pub fn gen_confirmation_key(shared_secret: [u8; 32]) -> [u8; 32] {
+    let hk = Hkdf::<Sha256>::new(Some(CONFIRMATION_LABEL), &shared_secret);
+    let mut okm = [0u8; 32];
+    hk.expand(CONFIRMATION_LABEL, &mut okm)
+        .expect("32 is a valid length for Sha256 to output");
+    okm
+}
+
+/// The transcript names the sender, so a token can't be reflected back to it,
+/// and includes the keys it derived for both directions.
+fn confirmation_mac(
+    confirmation_key: &[u8; 32],
+    sender_public: [u8; 32],
+    receiver_public: [u8; 32],
+    sender_send_key: [u8; 32],
+    sender_receive_key: [u8; 32],
+) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(confirmation_key).expect("HMAC can take a key of any size");
+    mac.update(CONFIRMATION_LABEL);
+    mac.update(&sorted_public_keys(sender_public, receiver_public));
+    mac.update(&sender_public);
+    mac.update(&sender_send_key);
+    mac.update(&sender_receive_key);
+    mac
+}
+
+pub fn confirmation_token(
+    confirmation_key: &[u8; 32],
+    your_public: [u8; 32],
+    other_public: [u8; 32],
+    send_key: [u8; 32],
+    receive_key: [u8; 32],
+) -> String {
+    let tag = confirmation_mac(
+        confirmation_key,
+        your_public,
+        other_public,
+        send_key,
+        receive_key,
+    )
+    .finalize()
+    .into_bytes();
+
+    tag[..CONFIRMATION_TOKEN_SIZE]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+fn parse_confirmation_token(token: &str) -> Result<Vec<u8>, Error> {
+    let digits: Vec<char> = token
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect();
+    if digits.len() != CONFIRMATION_TOKEN_SIZE * 2 {
+        return Err(Error::BadEncoding);
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            u8::from_str_radix(&byte, 16).map_err(|_| Error::BadEncoding)
+        })
+        .collect()
+}
+
+/// Checks the token of the other party, whose send key is our receive key.
+pub fn check_confirmation_token(
+    token: &str,
+    confirmation_key: &[u8; 32],
+    your_public: [u8; 32],
+    other_public: [u8; 32],
+    send_key: [u8; 32],
+    receive_key: [u8; 32],
+) -> Result<(), Error> {
+    let token = parse_confirmation_token(token)?;
+    confirmation_mac(
+        confirmation_key,
+        other_public,
+        your_public,
+        receive_key,
+        send_key,
+    )
+    .verify_truncated_left(&token)
+    .map_err(|_| Error::ConfirmationMismatch)
+}
@@ -1,93 +1,944 @@
-use std::fs;
 use std::path::PathBuf;
-use x25519_dalek::{PublicKey, SharedSecret, StaticSecret};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
 
-use crate::config::{find_config_file, read_config_file, ConfigSettings};
-use crate::key_exchange::key_to_string;
-use crate::key_store_classes::{KeyExchange, KeySignature, KeyStorage};
+use crate::backup::{
+    import_into, open_backup, seal_backup, select_exchanges, ImportConflict, ImportSummary,
+};
+use crate::config::{
+    find_config_file, read_config_file, write_config_file, ConfigSettings, StorageBackend,
+};
+use crate::envelope::{key_id, KEY_ID_SIZE};
+use crate::error::Error;
+use crate::group::SenderKeyUpdate;
+use crate::hybrid_kem::{gen_kem_kp, hybrid_shared_secret, kem_decapsulate, kem_encapsulate};
+use crate::key_encoding::{
+    encode_hybrid_key, encode_public_key, PeerKey, HYBRID_PUBLIC_KEY_PREFIX,
+    HYBRID_RESPONSE_PREFIX, PUBLIC_KEY_PREFIX,
+};
+use crate::key_exchange::{
+    derive_channel_keys, gen_encryption_key, gen_shared_secret, gen_static_kp, key_to_string,
+    DhKeyPair, CURRENT_KDF_VERSION, KDF_V1, KDF_V2,
+};
+use crate::key_store_classes::{
+    ExchangeKind, GroupChannel, KeyExchange, KeySignature, KeyStorage, MessageOrder, PreviousKeys,
+    SenderKey, GROUP_ID_SIZE, ROTATION_GRACE_PERIOD,
+};
+use crate::ratchet::RatchetState;
+use crate::signature::gen_signing_kp;
+use crate::storage_backend::{open_key_store, JsonFileStore, KeyStore};
+use crate::storage_encryption::{SealedStorage, StorageKey};
+use crate::storage_file::{backup_path, is_exposed, restrict_permissions, StorageLock};
 
-fn create_storage(storage_path: &PathBuf) {
-    println!("Creating storage file...");
-    _ = fs::File::create(&storage_path).expect("Storage file created!");
+static STORAGE_PASSPHRASE: Mutex<Option<Zeroizing<String>>> = Mutex::new(None);
+static STORAGE_KEY: Mutex<Option<StorageKey>> = Mutex::new(None);
+static KEY_STORE: Mutex<Option<OpenStore>> = Mutex::new(None);
 
-    let new_ks = KeyStorage::new();
-    let j = serde_json::to_string(&new_ks).unwrap();
-    _ = fs::write(storage_path, j).expect("Default storage written successfully!");
+/// The key storage used by every function of this module, with the config it
+/// was opened with, so the config is read only once.
+struct OpenStore {
+    store: Arc<dyn KeyStore>,
+    cfg: ConfigSettings,
+}
+
+pub fn set_storage_passphrase(passphrase: Option<String>) {
+    *STORAGE_PASSPHRASE.lock().unwrap() = passphrase.map(Zeroizing::new);
+    *STORAGE_KEY.lock().unwrap() = None;
+}
+
+fn get_storage_passphrase() -> Option<Zeroizing<String>> {
+    STORAGE_PASSPHRASE.lock().unwrap().clone()
+}
+
+/// Returns the key to open `sealed` with, or to seal the storage with when
+/// it's `None`. Argon2id only runs when no key was derived yet, or when the
+/// storage was sealed with another salt, by another instance of the tool.
+pub(crate) fn get_storage_key(sealed: Option<&SealedStorage>) -> Result<StorageKey, Error> {
+    let mut cached = STORAGE_KEY.lock().unwrap();
+    if let Some(key) = cached.as_ref() {
+        if sealed.is_none_or(|sealed| key.matches(sealed)) {
+            return Ok(key.clone());
+        }
+    }
+
+    let passphrase = get_storage_passphrase().ok_or(Error::StorageLocked)?;
+    let key = match sealed {
+        Some(sealed) => StorageKey::for_sealed(sealed, &passphrase)?,
+        None => StorageKey::new(&passphrase)?,
+    };
+    *cached = Some(key.clone());
+    Ok(key)
+}
 
-    println!("Storage file create at: {}", storage_path.display());
+/// Replaces the key storage used by every function of this module, and the
+/// settings they follow, instead of the ones of the config file.
+pub fn use_key_store(store: Arc<dyn KeyStore>, cfg: ConfigSettings) {
+    *KEY_STORE.lock().unwrap() = Some(OpenStore { store, cfg });
 }
 
-fn get_storage_path() -> PathBuf {
-    let cfg_path = find_config_file();
-    let cfg: ConfigSettings = read_config_file(cfg_path);
-    cfg.storage_path
+// The config is only read to open the storage the first time it's used.
+fn with_open_store<T>(f: impl FnOnce(&OpenStore) -> T) -> Result<T, Error> {
+    let mut open = KEY_STORE.lock().unwrap();
+    if open.is_none() {
+        let cfg = read_config_file(find_config_file()?)?;
+        let store = open_key_store(&cfg)?;
+        *open = Some(OpenStore { store, cfg });
+    }
+    Ok(f(open.as_ref().unwrap()))
 }
 
-pub fn read_storage() -> KeyStorage {
-    let storage_path = get_storage_path();
+fn key_store() -> Result<Arc<dyn KeyStore>, Error> {
+    with_open_store(|open| open.store.clone())
+}
+
+fn get_config() -> Result<ConfigSettings, Error> {
+    with_open_store(|open| open.cfg.clone())
+}
 
-    match fs::read_to_string(&storage_path) {
-        Ok(contents) => {
-            return serde_json::from_str(&contents).unwrap();
+pub fn read_storage() -> Result<KeyStorage, Error> {
+    key_store()?.load()
+}
+
+/// Holds the advisory lock on the key storage until the returned guard is
+/// dropped. Take it around a whole read-modify-write cycle so another
+/// CryptoChannel running at the same time can't overwrite the changes.
+/// Taking it again while it's held by this process doesn't wait.
+pub fn lock_storage() -> Result<StorageLock, Error> {
+    key_store()?.lock()
+}
+
+/// Returns the config directory and key storage files other users can access.
+pub fn find_exposed_files() -> Result<Vec<PathBuf>, Error> {
+    let cfg_path = find_config_file()?;
+    let cfg = read_config_file(cfg_path.clone())?;
+
+    let mut paths = vec![];
+    if let Some(config_dir) = cfg_path.parent() {
+        paths.push(config_dir.to_path_buf());
+    }
+    paths.push(backup_path(&cfg.storage_path));
+    paths.push(cfg.storage_path);
+
+    let mut exposed = vec![];
+    for path in paths {
+        if path.exists() && is_exposed(&path).map_err(Error::StorageIo)? {
+            exposed.push(path);
         }
-        Err(_) => create_storage(&storage_path),
+    }
+    Ok(exposed)
+}
+
+/// Makes the given files and directories accessible to you only.
+pub fn restrict_file_permissions(paths: &[PathBuf]) -> Result<(), Error> {
+    for path in paths {
+        restrict_permissions(path).map_err(Error::StorageIo)?;
+    }
+    Ok(())
+}
+
+pub fn unlock_storage(passphrase: String) -> Result<(), Error> {
+    let cfg = get_config()?;
+    set_storage_passphrase(Some(passphrase));
+    if !cfg.storage_path.exists() {
+        return Ok(());
     }
 
-    let contents = fs::read_to_string(storage_path).expect("Storage file read successfully!");
+    let result = JsonFileStore::new(cfg.storage_path, true)
+        .load()
+        .map(|_| ());
+    if result.is_err() {
+        set_storage_passphrase(None);
+    }
+    result
+}
 
-    serde_json::from_str(&contents).unwrap()
+pub fn is_storage_encrypted() -> Result<bool, Error> {
+    Ok(get_config()?.encrypt_storage)
 }
 
-pub fn get_key_exchange_names() -> Vec<String> {
-    let curr_storage = read_storage();
-    curr_storage
+pub fn is_signature_enabled() -> Result<bool, Error> {
+    Ok(get_config()?.use_signature)
+}
+
+pub fn is_ratchet_enabled() -> Result<bool, Error> {
+    Ok(get_config()?.use_ratchet)
+}
+
+pub fn set_storage_encryption(passphrase: Option<String>) -> Result<(), Error> {
+    let cfg_path = find_config_file()?;
+    let mut cfg = read_config_file(cfg_path.clone())?;
+    if cfg.storage_backend != StorageBackend::Json {
+        return Err(Error::UnsupportedStorageEncryption);
+    }
+
+    let _lock = lock_storage()?;
+    let curr_storage = read_storage()?;
+    let encrypt = passphrase.is_some();
+    set_storage_passphrase(passphrase);
+    let store = JsonFileStore::new(cfg.storage_path.clone(), encrypt);
+    // The backups are replaced too, so no copy is left in the former format.
+    store.save_erasing(&curr_storage)?;
+
+    cfg.encrypt_storage = encrypt;
+    write_config_file(&cfg_path, &cfg)?;
+    use_key_store(Arc::new(store), cfg);
+    Ok(())
+}
+
+pub fn get_key_exchange_names() -> Result<Vec<String>, Error> {
+    let curr_storage = read_storage()?;
+    Ok(curr_storage
         .exchange_map
         .keys()
         .cloned()
-        .collect::<Vec<String>>()
+        .collect::<Vec<String>>())
 }
 
-pub fn get_exchange_dh_public(exchange_name: String) -> String {
-    let curr_storage = read_storage();
-    let public = curr_storage
-        .get_exchange(&exchange_name.to_owned())
-        .unwrap()
-        .get_your_public_key();
-    key_to_string(public)
+/// Returns the names of the exchanges messages can be exchanged over: the
+/// completed ones whose keys were confirmed, if that was asked.
+pub fn get_usable_exchange_names() -> Result<Vec<String>, Error> {
+    let curr_storage = read_storage()?;
+    Ok(curr_storage
+        .exchange_map
+        .iter()
+        .filter(|(_, (exchange, _))| {
+            exchange.is_completed() && !exchange.is_awaiting_confirmation()
+        })
+        .map(|(name, _)| name.clone())
+        .collect())
 }
 
-pub fn get_exchange_dh_secret(exchange_name: String) -> [u8; 32] {
-    let curr_storage = read_storage();
-    curr_storage
-        .get_exchange(&exchange_name.to_owned())
-        .unwrap()
-        .get_your_static_secret()
+/// Finds the exchange whose receive key has one of `key_ids`, including the
+/// keys replaced by a rotation that are still in their grace period.
+pub fn find_exchange_by_key_id(key_ids: &[[u8; KEY_ID_SIZE]]) -> Result<Option<String>, Error> {
+    let curr_storage = read_storage()?;
+    let now = unix_time();
+
+    let found = curr_storage
+        .exchange_map
+        .iter()
+        .find(|(_, (exchange, _))| {
+            if !exchange.is_completed() || exchange.is_awaiting_confirmation() {
+                return false;
+            }
+            let previous_key = exchange
+                .get_previous_keys(now)
+                .map(|previous| previous.receive_key);
+            std::iter::once(exchange.get_receive_key())
+                .chain(previous_key)
+                .any(|key| key_ids.contains(&key_id(&key)))
+        })
+        .map(|(name, _)| name.clone());
+    Ok(found)
 }
 
-pub fn get_exchange_encryption_key(exchange_name: String) -> [u8; 32] {
-    let curr_storage = read_storage();
+pub fn get_exchange_dh_public(exchange_name: String) -> Result<[u8; 32], Error> {
+    let curr_storage = read_storage()?;
+    Ok(curr_storage
+        .get_exchange(&exchange_name)?
+        .get_your_public_key())
+}
+
+pub fn get_exchange_kind(exchange_name: String) -> Result<ExchangeKind, Error> {
+    let curr_storage = read_storage()?;
+    Ok(curr_storage.get_exchange(&exchange_name)?.get_kind())
+}
+
+pub fn get_exchange_dh_secret(exchange_name: String) -> Result<Zeroizing<[u8; 32]>, Error> {
+    let curr_storage = read_storage()?;
+    Ok(Zeroizing::new(
+        curr_storage
+            .get_exchange(&exchange_name)?
+            .get_your_static_secret(),
+    ))
+}
+
+// Secret keys are handed out wrapped, so the copies are wiped once used.
+fn get_completed_exchange_key(
+    exchange_name: String,
+    key: fn(&KeyExchange) -> [u8; 32],
+) -> Result<Zeroizing<[u8; 32]>, Error> {
+    let curr_storage = read_storage()?;
+    let curr_exchange = curr_storage.get_exchange(&exchange_name)?;
+    if curr_exchange.is_awaiting_confirmation() {
+        return Err(Error::ExchangeNotConfirmed(exchange_name));
+    }
+    let exchange_key = Zeroizing::new(key(curr_exchange));
+
+    if *exchange_key == [0u8; 32] {
+        return Err(Error::ExchangeNotCompleted(exchange_name));
+    }
+    Ok(exchange_key)
+}
+
+pub fn get_exchange_send_key(exchange_name: String) -> Result<Zeroizing<[u8; 32]>, Error> {
+    get_completed_exchange_key(exchange_name, KeyExchange::get_send_key)
+}
+
+pub fn get_exchange_receive_key(exchange_name: String) -> Result<Zeroizing<[u8; 32]>, Error> {
+    get_completed_exchange_key(exchange_name, KeyExchange::get_receive_key)
+}
+
+pub fn next_send_counter(exchange_name: String) -> Result<u64, Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let (curr_exchange, _) = curr_storage
+        .exchange_map
+        .get_mut(&exchange_name)
+        .ok_or(Error::UnknownExchange(exchange_name.clone()))?;
+
+    // Written before the message is sent, so a counter is never used twice.
+    let counter = curr_exchange.next_send_counter();
+    write_storage(curr_storage)?;
+    Ok(counter)
+}
+
+pub fn record_received_counter(exchange_name: String, counter: u64) -> Result<MessageOrder, Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let (curr_exchange, _) = curr_storage
+        .exchange_map
+        .get_mut(&exchange_name)
+        .ok_or(Error::UnknownExchange(exchange_name.clone()))?;
+
+    let order = curr_exchange.record_received_counter(counter);
+    write_storage(curr_storage)?;
+    Ok(order)
+}
+
+/// Returns the Double Ratchet session of the exchange, or a new one started
+/// from the completed exchange. A new session is only stored once it's saved
+/// with `save_ratchet_session`, so trying an exchange changes nothing.
+pub fn get_ratchet_session(exchange_name: String) -> Result<RatchetState, Error> {
+    let curr_storage = read_storage()?;
+    let curr_exchange = curr_storage.get_exchange(&exchange_name)?;
+
+    if curr_exchange.is_awaiting_confirmation() {
+        return Err(Error::ExchangeNotConfirmed(exchange_name));
+    }
+    if let Some(session) = curr_exchange.get_ratchet() {
+        return Ok(session.clone());
+    }
+    if !curr_exchange.is_completed() {
+        return Err(Error::ExchangeNotCompleted(exchange_name));
+    }
+
+    Ok(RatchetState::new(
+        curr_exchange.shared_secret,
+        curr_exchange.get_your_public_key(),
+        curr_exchange.get_your_static_secret(),
+        curr_exchange.other_person_public_key,
+    ))
+}
+
+pub fn save_ratchet_session(exchange_name: String, session: RatchetState) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let (curr_exchange, _) = curr_storage
+        .exchange_map
+        .get_mut(&exchange_name)
+        .ok_or(Error::UnknownExchange(exchange_name.clone()))?;
+
+    curr_exchange.set_ratchet(Some(session));
+    write_storage_erasing(curr_storage)
+}
+
+pub fn get_exchange_safety_number(exchange_name: String) -> Result<String, Error> {
+    let curr_storage = read_storage()?;
     curr_storage
-        .get_exchange(&exchange_name.to_owned())
-        .unwrap()
-        .get_encryption_key()
+        .get_exchange(&exchange_name)?
+        .get_safety_number()
+        .ok_or(Error::ExchangeNotCompleted(exchange_name))
+}
+
+pub fn is_exchange_verified(exchange_name: String) -> Result<bool, Error> {
+    let curr_storage = read_storage()?;
+    Ok(curr_storage.get_exchange(&exchange_name)?.is_verified())
 }
 
-pub fn validate_new_exchange_name(exchange_name: String) -> Result<(), &'static str> {
-    let curr_storage = read_storage();
-    if !curr_storage.name_exists(exchange_name) {
+pub fn set_exchange_verified(exchange_name: String, verified: bool) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let (curr_exchange, _) = curr_storage
+        .exchange_map
+        .get_mut(&exchange_name)
+        .ok_or(Error::UnknownExchange(exchange_name.clone()))?;
+
+    if !curr_exchange.is_completed() {
+        return Err(Error::ExchangeNotCompleted(exchange_name));
+    }
+    curr_exchange.set_verified(verified);
+    write_storage(curr_storage)
+}
+
+/// Holds the exchange back until the token of the other party was checked, and
+/// returns the token to send them.
+pub fn start_key_confirmation(exchange_name: String) -> Result<String, Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let (curr_exchange, _) = curr_storage
+        .exchange_map
+        .get_mut(&exchange_name)
+        .ok_or(Error::UnknownExchange(exchange_name.clone()))?;
+
+    let token = curr_exchange
+        .get_confirmation_token()
+        .ok_or(Error::ExchangeNotCompleted(exchange_name))?;
+    curr_exchange.set_awaiting_confirmation(true);
+    write_storage(curr_storage)?;
+    Ok(token)
+}
+
+pub fn confirm_exchange(exchange_name: String, token: &str) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let (curr_exchange, _) = curr_storage
+        .exchange_map
+        .get_mut(&exchange_name)
+        .ok_or(Error::UnknownExchange(exchange_name.clone()))?;
+
+    if !curr_exchange.is_completed() {
+        return Err(Error::ExchangeNotCompleted(exchange_name));
+    }
+    curr_exchange.check_confirmation_token(token)?;
+    curr_exchange.set_awaiting_confirmation(false);
+    write_storage(curr_storage)
+}
+
+pub fn get_exchange_signing_public(exchange_name: String) -> Result<[u8; 32], Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let (_, signature) = curr_storage
+        .exchange_map
+        .get_mut(&exchange_name)
+        .ok_or(Error::UnknownExchange(exchange_name.clone()))?;
+
+    if !signature.has_signing_kp() {
+        signature.add_your_signing_kp(gen_signing_kp());
+        let public = signature.get_your_public_key();
+        write_storage(curr_storage)?;
+        return Ok(public);
+    }
+
+    Ok(signature.get_your_public_key())
+}
+
+pub fn get_exchange_signing_secret(
+    exchange_name: String,
+) -> Result<Option<Zeroizing<[u8; 32]>>, Error> {
+    let curr_storage = read_storage()?;
+    let signature = curr_storage.get_signature(&exchange_name)?;
+
+    if !signature.has_signing_kp() {
+        return Ok(None);
+    }
+    Ok(Some(Zeroizing::new(signature.get_your_private_key())))
+}
+
+pub fn get_exchange_other_signing_public(exchange_name: String) -> Result<Option<[u8; 32]>, Error> {
+    let curr_storage = read_storage()?;
+    let public = curr_storage
+        .get_signature(&exchange_name)?
+        .get_other_person_public_key();
+
+    if public == [0u8; 32] {
+        return Ok(None);
+    }
+    Ok(Some(public))
+}
+
+pub fn validate_new_exchange_name(exchange_name: String) -> Result<(), Error> {
+    let curr_storage = read_storage()?;
+    if !curr_storage.name_exists(exchange_name.clone()) {
         return Ok(());
     }
-    Err("Exchange name already exists in key storage!")
+    Err(Error::ExchangeExists(exchange_name))
+}
+
+pub fn new_exchange(exchange_name: String, kind: ExchangeKind) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut new_key_exchange = KeyExchange::new();
+    new_key_exchange.add_your_dh_kp(gen_static_kp());
+    if kind == ExchangeKind::Hybrid {
+        new_key_exchange.add_kem_kp(gen_kem_kp()?);
+    }
+
+    let mut new_key_signature = None;
+    if is_signature_enabled()? {
+        let mut key_signature = KeySignature::new();
+        key_signature.add_your_signing_kp(gen_signing_kp());
+        new_key_signature = Some(key_signature);
+    }
+
+    let mut curr_storage = read_storage()?;
+    curr_storage.create_exchange(
+        &exchange_name,
+        false,
+        Some(new_key_exchange),
+        new_key_signature,
+    )?;
+    write_storage(curr_storage)
+}
+
+fn derive_exchange_keys(
+    exchange: &mut KeyExchange,
+    dh_public: [u8; 32],
+    shared_secret: [u8; 32],
+) -> Result<(), Error> {
+    match exchange.get_kdf_version() {
+        KDF_V1 => exchange.add_encryption_key(gen_encryption_key(shared_secret), false),
+        KDF_V2 => exchange.add_channel_keys(derive_channel_keys(
+            shared_secret,
+            exchange.get_your_public_key(),
+            dh_public,
+        )),
+        version => return Err(Error::UnsupportedVersion(version)),
+    }
+
+    exchange.add_other_person_public_key(dh_public);
+    exchange.add_shared_secret(shared_secret, false);
+    exchange.set_ratchet(None);
+    Ok(())
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn get_rotatable_exchange<'a>(
+    curr_storage: &'a mut KeyStorage,
+    exchange_name: &str,
+) -> Result<&'a mut KeyExchange, Error> {
+    let (curr_exchange, _) = curr_storage
+        .exchange_map
+        .get_mut(exchange_name)
+        .ok_or(Error::UnknownExchange(exchange_name.to_string()))?;
+
+    if curr_exchange.is_awaiting_confirmation() {
+        return Err(Error::ExchangeNotConfirmed(exchange_name.to_string()));
+    }
+    if !curr_exchange.is_completed() {
+        return Err(Error::ExchangeNotCompleted(exchange_name.to_string()));
+    }
+    Ok(curr_exchange)
+}
+
+// Rotations only replace X25519 keys, so hybrid exchanges mix the previous
+// secret in, in place of a new ML-KEM secret, to stay post-quantum.
+fn rotated_shared_secret(
+    kind: ExchangeKind,
+    dh_public: [u8; 32],
+    dh_secret: [u8; 32],
+    previous_secret: [u8; 32],
+) -> [u8; 32] {
+    let shared_secret = gen_shared_secret(dh_public, dh_secret);
+    match kind {
+        ExchangeKind::Classic => shared_secret,
+        ExchangeKind::Hybrid => hybrid_shared_secret(shared_secret, previous_secret, &[]),
+    }
+}
+
+/// Replaces your key pair of the exchange. Both parties move to the current
+/// KDF version, and the exchange stays verified, as the new Public Key is sent
+/// in a message authenticated by the current keys.
+pub fn rotate_exchange_keys(exchange_name: String, kp: DhKeyPair) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let curr_exchange = get_rotatable_exchange(&mut curr_storage, &exchange_name)?;
+
+    let other_public = curr_exchange.other_person_public_key;
+    let shared_secret = rotated_shared_secret(
+        curr_exchange.get_kind(),
+        other_public,
+        kp.secret,
+        curr_exchange.shared_secret,
+    );
+    curr_exchange.start_grace_period(unix_time() + ROTATION_GRACE_PERIOD);
+    curr_exchange.add_your_dh_kp(kp);
+    curr_exchange.set_kdf_version(CURRENT_KDF_VERSION);
+    derive_exchange_keys(curr_exchange, other_public, shared_secret)?;
+    write_storage(curr_storage)
+}
+
+/// Switches to the new Public Key sent by the other party in a rotation message.
+/// `with_previous_keys` tells the message was decrypted with the keys replaced
+/// by your last rotation: both parties rotated before they knew of each other's
+/// rotation.
+pub fn accept_rotated_key(
+    exchange_name: String,
+    dh_public: [u8; 32],
+    with_previous_keys: bool,
+) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let curr_exchange = get_rotatable_exchange(&mut curr_storage, &exchange_name)?;
+
+    // The same rotation message can be decrypted twice.
+    if curr_exchange.other_person_public_key == dh_public {
+        return Ok(());
+    }
+
+    // The other party chained their new secret from the one your rotation
+    // replaced, so both end up with the same one once each got the other's
+    // rotation. Your replaced keys are still the ones they sent with.
+    let previous_secret = match with_previous_keys {
+        true => {
+            curr_exchange
+                .get_previous_keys(unix_time())
+                .ok_or(Error::MessageKeyUnavailable)?
+                .shared_secret
+        }
+        false => curr_exchange.shared_secret,
+    };
+    let shared_secret = rotated_shared_secret(
+        curr_exchange.get_kind(),
+        dh_public,
+        curr_exchange.get_your_static_secret(),
+        previous_secret,
+    );
+    if !with_previous_keys {
+        curr_exchange.start_grace_period(unix_time() + ROTATION_GRACE_PERIOD);
+    }
+    curr_exchange.set_kdf_version(CURRENT_KDF_VERSION);
+    derive_exchange_keys(curr_exchange, dh_public, shared_secret)?;
+    write_storage(curr_storage)
+}
+
+/// Returns the keys replaced by the last rotation, and deletes them once their
+/// grace period is over.
+pub fn get_previous_keys(exchange_name: String) -> Result<Option<PreviousKeys>, Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let (curr_exchange, _) = curr_storage
+        .exchange_map
+        .get_mut(&exchange_name)
+        .ok_or(Error::UnknownExchange(exchange_name.clone()))?;
+
+    if curr_exchange.previous_keys.is_none() {
+        return Ok(None);
+    }
+    if let Some(previous) = curr_exchange.get_previous_keys(unix_time()) {
+        return Ok(Some(previous.clone()));
+    }
+    curr_exchange.set_previous_keys(None);
+    write_storage(curr_storage)?;
+    Ok(None)
+}
+
+pub fn save_previous_ratchet_session(
+    exchange_name: String,
+    session: RatchetState,
+) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let (curr_exchange, _) = curr_storage
+        .exchange_map
+        .get_mut(&exchange_name)
+        .ok_or(Error::UnknownExchange(exchange_name.clone()))?;
+
+    if let Some(previous) = curr_exchange.previous_keys.as_mut() {
+        previous.ratchet = Some(session);
+    }
+    write_storage_erasing(curr_storage)
+}
+
+pub fn complete_exchange(
+    exchange_name: String,
+    peer_key: PeerKey,
+    signing_public: Option<[u8; 32]>,
+) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let (curr_exchange, curr_signature) = curr_storage
+        .exchange_map
+        .get_mut(&exchange_name)
+        .ok_or(Error::UnknownExchange(exchange_name.clone()))?;
+
+    // Both parties derive with the older of their KDF versions, each tells its
+    // own with the format of the key it sends.
+    let kdf_version = curr_exchange.get_kdf_version().min(peer_key.kdf_version());
+    curr_exchange.set_kdf_version(kdf_version);
+
+    let your_secret = curr_exchange.get_your_static_secret();
+    match (curr_exchange.get_kind(), peer_key) {
+        (ExchangeKind::Classic, PeerKey::Classic(dh_public) | PeerKey::Legacy(dh_public)) => {
+            derive_exchange_keys(
+                curr_exchange,
+                dh_public,
+                gen_shared_secret(dh_public, your_secret),
+            )?;
+        }
+        // The ML-KEM secret is created for the other party, who gets it from
+        // the ciphertext sent back to them. Only the party with the higher
+        // X25519 Public Key does it, so both don't create one each when they
+        // swap Public Keys at the same time.
+        (ExchangeKind::Hybrid, PeerKey::HybridPublic(dh_public, kem_public)) => {
+            if curr_exchange.get_your_public_key() < dh_public {
+                return Err(Error::HybridResponseExpected(exchange_name));
+            }
+            let (ciphertext, kem_secret) = kem_encapsulate(&kem_public)?;
+            let shared_secret = hybrid_shared_secret(
+                gen_shared_secret(dh_public, your_secret),
+                kem_secret,
+                &ciphertext,
+            );
+            derive_exchange_keys(curr_exchange, dh_public, shared_secret)?;
+            curr_exchange.add_kem_ciphertext(ciphertext);
+            curr_exchange.remove_kem_secret_key();
+        }
+        (ExchangeKind::Hybrid, PeerKey::HybridResponse(dh_public, ciphertext)) => {
+            let kem_secret = kem_decapsulate(curr_exchange.get_kem_secret_key(), &ciphertext)?;
+            let shared_secret = hybrid_shared_secret(
+                gen_shared_secret(dh_public, your_secret),
+                kem_secret,
+                &ciphertext,
+            );
+            derive_exchange_keys(curr_exchange, dh_public, shared_secret)?;
+            curr_exchange.remove_kem_secret_key();
+        }
+        _ => return Err(Error::WrongExchangeKind(exchange_name)),
+    }
+
+    // A new public key has to be verified and confirmed again.
+    curr_exchange.set_verified(false);
+    curr_exchange.set_awaiting_confirmation(false);
+
+    if let Some(pub_k) = signing_public {
+        curr_signature.add_other_person_public_key(pub_k);
+    }
+
+    write_storage(curr_storage)
+}
+
+/// Returns the key to send to the other party: your Public Key, or for hybrid
+/// exchanges you completed first, the response with the ML-KEM ciphertext.
+/// Exchanges derived with KDF v1 show it in the base64 format of the versions
+/// that only know v1, so the other party derives the same keys.
+pub fn get_exchange_public_key(exchange_name: String) -> Result<String, Error> {
+    let curr_storage = read_storage()?;
+    let curr_exchange = curr_storage.get_exchange(&exchange_name)?;
+    let dh_public = curr_exchange.get_your_public_key();
+
+    Ok(match curr_exchange.get_kind() {
+        ExchangeKind::Classic if curr_exchange.get_kdf_version() == KDF_V1 => {
+            key_to_string(dh_public)
+        }
+        ExchangeKind::Classic => encode_public_key(dh_public, PUBLIC_KEY_PREFIX),
+        ExchangeKind::Hybrid => match curr_exchange.get_kem_ciphertext() {
+            Some(ciphertext) => encode_hybrid_key(dh_public, ciphertext, HYBRID_RESPONSE_PREFIX),
+            None => encode_hybrid_key(
+                dh_public,
+                curr_exchange.get_kem_public_key(),
+                HYBRID_PUBLIC_KEY_PREFIX,
+            ),
+        },
+    })
 }
 
-pub fn write_storage(to_write: KeyStorage) -> Result<(), &'static str> {
-    let storage_path = get_storage_path();
-    let j = serde_json::to_string(&to_write).unwrap();
+pub fn get_group_names() -> Result<Vec<String>, Error> {
+    let curr_storage = read_storage()?;
+    Ok(curr_storage.group_map.keys().cloned().collect())
+}
 
-    match fs::write(storage_path, j) {
-        Ok(_) => Ok(()),
-        Err(_) => Err("Failed to write to Storage"),
+pub fn get_group(group_name: String) -> Result<GroupChannel, Error> {
+    let curr_storage = read_storage()?;
+    curr_storage.get_group(&group_name).cloned()
+}
+
+pub fn new_group(group_name: String, members: Vec<String>) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    if curr_storage.group_map.contains_key(&group_name) {
+        return Err(Error::GroupExists(group_name));
+    }
+    for member in &members {
+        curr_storage.get_exchange(member)?;
     }
+
+    curr_storage
+        .group_map
+        .insert(group_name, GroupChannel::new(members));
+    write_storage(curr_storage)
+}
+
+/// Returns the group with the member added and a new sender key. It's only
+/// saved with `save_group`, once the new key was sealed for every member.
+pub fn add_group_member(group_name: String, exchange_name: String) -> Result<GroupChannel, Error> {
+    let curr_storage = read_storage()?;
+    curr_storage.get_exchange(&exchange_name)?;
+    let mut group = curr_storage.get_group(&group_name)?.clone();
+
+    if !group.is_member(&exchange_name) {
+        group.members.push(exchange_name);
+    }
+    group.rekey();
+    Ok(group)
+}
+
+/// Returns the group without the member and with a new sender key. It's only
+/// saved with `save_group`, once the new key was sealed for every member.
+pub fn remove_group_member(
+    group_name: String,
+    exchange_name: String,
+) -> Result<GroupChannel, Error> {
+    let curr_storage = read_storage()?;
+    let mut group = curr_storage.get_group(&group_name)?.clone();
+
+    if !group.is_member(&exchange_name) {
+        return Err(Error::NotGroupMember(exchange_name, group_name));
+    }
+    group.members.retain(|member| *member != exchange_name);
+    group.member_keys.remove(&exchange_name);
+    group.rekey();
+    Ok(group)
+}
+
+pub fn save_group(group_name: String, group: GroupChannel) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    *curr_storage.get_group_mut(&group_name)? = group;
+    write_storage(curr_storage)
+}
+
+pub fn delete_group(group_name: String) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    if curr_storage.group_map.remove(&group_name).is_none() {
+        return Err(Error::UnknownGroup(group_name));
+    }
+    write_storage_erasing(curr_storage)
+}
+
+/// Returns your sender key for the group and the counter of the next message.
+pub fn next_group_send_counter(group_name: String) -> Result<(SenderKey, u64), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let group = curr_storage.get_group_mut(&group_name)?;
+
+    // Written before the message is sent, so a counter is never used twice.
+    let counter = group.sender_key.next_send_counter();
+    let sender_key = group.sender_key.clone();
+    write_storage(curr_storage)?;
+    Ok((sender_key, counter))
+}
+
+pub fn record_group_counter(
+    group_name: String,
+    exchange_name: String,
+    counter: u64,
+) -> Result<MessageOrder, Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let sender_key = curr_storage
+        .get_group_mut(&group_name)?
+        .member_keys
+        .get_mut(&exchange_name)
+        .ok_or(Error::NotGroupMember(exchange_name, group_name))?;
+
+    let order = sender_key.receive_window.record(counter);
+    write_storage(curr_storage)?;
+    Ok(order)
+}
+
+/// Stores the sender key a member sent over your Key Exchange with them. A
+/// group you don't have yet is created, with the sender as its only member.
+/// Keys of other parties are kept until you add them to the group.
+pub fn accept_sender_key(
+    exchange_name: String,
+    group_id: [u8; GROUP_ID_SIZE],
+    group_name: String,
+    sender_key: SenderKey,
+) -> Result<SenderKeyUpdate, Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let known_name = curr_storage
+        .group_map
+        .iter()
+        .find(|(_, group)| group.group_id == group_id)
+        .map(|(name, _)| name.clone());
+
+    let group_name = match known_name {
+        Some(name) => name,
+        None => {
+            let name = match curr_storage.group_map.contains_key(&group_name) {
+                true => format!("{} ({})", group_name, exchange_name),
+                false => group_name,
+            };
+            let mut group = GroupChannel::new(vec![exchange_name.clone()]);
+            group.group_id = group_id;
+            curr_storage.group_map.insert(name.clone(), group);
+            name
+        }
+    };
+
+    let group = curr_storage.get_group_mut(&group_name)?;
+    let update = match (
+        group.add_member_key(&exchange_name, sender_key),
+        group.is_member(&exchange_name),
+    ) {
+        (false, _) => SenderKeyUpdate::Outdated(group_name),
+        (true, true) => SenderKeyUpdate::Stored(group_name),
+        (true, false) => SenderKeyUpdate::NotMember(group_name),
+    };
+    write_storage(curr_storage)?;
+    Ok(update)
+}
+
+pub fn delete_exchange(exchange_name: String) -> Result<(), Error> {
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    // Removing them from a group replaces your sender key, which has to be
+    // sent to the other members over their own exchanges.
+    if let Some((group_name, _)) = curr_storage
+        .group_map
+        .iter()
+        .find(|(_, group)| group.is_member(&exchange_name))
+    {
+        return Err(Error::ExchangeInGroup(exchange_name, group_name.clone()));
+    }
+    if curr_storage.exchange_map.remove(&exchange_name).is_none() {
+        return Err(Error::UnknownExchange(exchange_name));
+    }
+    write_storage_erasing(curr_storage)
+}
+
+/// Returns a backup of the Key Exchanges in `names`, or of the whole key
+/// storage if it's empty, encrypted with `passphrase`.
+pub fn export_backup(names: &[String], passphrase: &str) -> Result<String, Error> {
+    let curr_storage = read_storage()?;
+    seal_backup(&select_exchanges(curr_storage, names)?, passphrase)
+}
+
+pub fn import_backup(
+    contents: &str,
+    passphrase: &str,
+    conflict: ImportConflict,
+) -> Result<ImportSummary, Error> {
+    let backup = open_backup(contents, passphrase)?;
+
+    let _lock = lock_storage()?;
+    let mut curr_storage = read_storage()?;
+    let summary = import_into(&mut curr_storage, backup, conflict)?;
+    write_storage(curr_storage)?;
+    Ok(summary)
+}
+
+pub fn write_storage(to_write: KeyStorage) -> Result<(), Error> {
+    let store = key_store()?;
+    let _lock = store.lock()?;
+    store.save(&to_write)
+}
+
+/// Writes the storage after keys were deleted from it, replacing the copies
+/// kept next to it too.
+pub fn write_storage_erasing(to_write: KeyStorage) -> Result<(), Error> {
+    let store = key_store()?;
+    let _lock = store.lock()?;
+    store.save_erasing(&to_write)
 }
 
 pub fn test() {
@@ -1,18 +1,108 @@
+use rand_core::{OsRng, RngCore};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
-use crate::key_exchange::{self, DhKeyPair};
+use crate::envelope::{key_id as envelope_key_id, KEY_ID_SIZE};
+use crate::error::Error;
+use crate::hybrid_kem::KemKeyPair;
+use crate::key_exchange::{
+    check_confirmation_token, confirmation_token, gen_confirmation_key, safety_number, ChannelKeys,
+    DhKeyPair, CURRENT_KDF_VERSION, KDF_V1,
+};
+use crate::ratchet::RatchetState;
+use crate::signature::{gen_signing_kp, SigningKeyPair};
+use crate::storage_migration::CURRENT_SCHEMA_VERSION;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Every key is wiped from memory when the exchange is dropped.
+#[derive(Serialize, Deserialize, Debug, Zeroize, ZeroizeOnDrop)]
 pub struct KeyExchange {
     pub your_public_key: [u8; 32],
     pub your_static_secret: [u8; 32],
     pub other_person_public_key: [u8; 32],
     pub shared_secret: [u8; 32],
     pub encryption_key: [u8; 32],
+    #[serde(default)]
+    pub receive_key: [u8; 32],
+    #[serde(default)]
+    pub mac_key: [u8; 32],
+    #[serde(default = "legacy_kdf_version")]
+    pub kdf_version: u8,
+    #[serde(default)]
+    pub send_counter: u64,
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub receive_window: ReceiveWindow,
+    #[serde(default)]
+    pub ratchet: Option<RatchetState>,
+    #[serde(default)]
+    pub verified: bool,
+    #[serde(default)]
+    pub awaiting_confirmation: bool,
+    #[serde(default)]
+    pub previous_keys: Option<PreviousKeys>,
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub kind: ExchangeKind,
+    #[serde(default)]
+    pub kem_public_key: Vec<u8>,
+    #[serde(default)]
+    pub kem_secret_key: Vec<u8>,
+    #[serde(default)]
+    pub kem_ciphertext: Vec<u8>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum ExchangeKind {
+    /// X25519 only.
+    #[default]
+    Classic,
+    /// X25519 combined with ML-KEM-768, so recorded messages stay private even
+    /// if X25519 is broken later on.
+    Hybrid,
+}
+
+/// How long the keys replaced by a rotation can still decrypt messages, in seconds.
+pub const ROTATION_GRACE_PERIOD: u64 = 7 * 24 * 60 * 60;
+
+/// Keys replaced by a rotation, kept to decrypt the messages that were already
+/// on their way until `expires_at`, in seconds since the Unix epoch.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Zeroize, ZeroizeOnDrop)]
+pub struct PreviousKeys {
+    pub receive_key: [u8; 32],
+    /// Hybrid exchanges chain the secret of a rotation from this one, when
+    /// the other party rotated before they knew of your rotation.
+    #[serde(default)]
+    pub shared_secret: [u8; 32],
+    pub ratchet: Option<RatchetState>,
+    pub expires_at: u64,
+}
+
+pub const RECEIVE_WINDOW_SIZE: u64 = 64;
+
+/// Sliding window over the counters of received messages. Bit `i` of `seen`
+/// is set when the message numbered `highest - i` was already decrypted.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct ReceiveWindow {
+    pub highest: u64,
+    pub seen: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MessageOrder {
+    InOrder,
+    OutOfOrder,
+    Replayed,
+    TooOld,
+    Untracked,
+}
+
+// Exchanges stored before the KDF was versioned were all derived with v1.
+fn legacy_kdf_version() -> u8 {
+    KDF_V1
+}
+
+#[derive(Serialize, Deserialize, Debug, Zeroize, ZeroizeOnDrop)]
 pub struct KeySignature {
     pub your_public_key: [u8; 32],
     pub your_private_key: [u8; 32],
@@ -21,13 +111,46 @@ pub struct KeySignature {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct KeyStorage {
+    #[serde(default)]
+    pub schema_version: u32,
     pub exchange_map: HashMap<String, (KeyExchange, KeySignature)>,
+    #[serde(default)]
+    pub group_map: HashMap<String, GroupChannel>,
+}
+
+/// A group of Key Exchanges sharing one ciphertext per message. Every member
+/// encrypts with their own sender key, sent to the others over the pairwise
+/// exchanges, so members are named after your Key Exchange with them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupChannel {
+    pub group_id: [u8; GROUP_ID_SIZE],
+    pub members: Vec<String>,
+    pub sender_key: SenderKey,
+    pub member_keys: HashMap<String, SenderKey>,
+}
+
+pub const GROUP_ID_SIZE: usize = 16;
+
+/// Messages are encrypted with `key` and signed with the signing key, so the
+/// members can't send messages on behalf of each other. The secret signing
+/// key is only known for your own sender key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Zeroize, ZeroizeOnDrop)]
+pub struct SenderKey {
+    pub generation: u32,
+    pub key: [u8; 32],
+    pub signing_public: [u8; 32],
+    pub signing_secret: [u8; 32],
+    pub send_counter: u64,
+    #[zeroize(skip)]
+    pub receive_window: ReceiveWindow,
 }
 
 impl KeyStorage {
     pub fn new() -> Self {
         KeyStorage {
+            schema_version: CURRENT_SCHEMA_VERSION,
             exchange_map: HashMap::new(),
+            group_map: HashMap::new(),
         }
     }
 
@@ -37,9 +160,9 @@ impl KeyStorage {
         overwrite: bool,
         key_exchange: Option<KeyExchange>,
         key_signature: Option<KeySignature>,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), Error> {
         if self.exchange_map.contains_key(exchange_name) && !overwrite {
-            return Err("Key Exchange already exists in Key Storage");
+            return Err(Error::ExchangeExists(exchange_name.to_string()));
         }
 
         let mut local_key_exchange = KeyExchange::new();
@@ -54,7 +177,7 @@ impl KeyStorage {
                 local_key_exchange = ex;
             }
             (None, Some(_)) => {
-                return Err("Invalid constructor with only key signature");
+                return Err(Error::MissingKeyExchange);
             }
             (None, None) => {}
         }
@@ -74,21 +197,39 @@ impl KeyStorage {
         self.exchange_map
     }
 
-    pub fn get_exchange(&self, exchange_name: &str) -> Result<&KeyExchange, &'static str> {
+    pub fn get_exchange(&self, exchange_name: &str) -> Result<&KeyExchange, Error> {
         match self.exchange_map.get(exchange_name) {
-            None => Err("Key Exchange doesn't exist in Key Storage"),
+            None => Err(Error::UnknownExchange(exchange_name.to_string())),
             Some((exchange, _)) => Ok(exchange),
         }
     }
 
-    pub fn get_signature(&self, exchange_name: &str) -> Result<&KeySignature, &'static str> {
+    pub fn get_group(&self, group_name: &str) -> Result<&GroupChannel, Error> {
+        self.group_map
+            .get(group_name)
+            .ok_or(Error::UnknownGroup(group_name.to_string()))
+    }
+
+    pub fn get_group_mut(&mut self, group_name: &str) -> Result<&mut GroupChannel, Error> {
+        self.group_map
+            .get_mut(group_name)
+            .ok_or(Error::UnknownGroup(group_name.to_string()))
+    }
+
+    pub fn get_signature(&self, exchange_name: &str) -> Result<&KeySignature, Error> {
         match self.exchange_map.get(exchange_name) {
-            None => Err("Key Exchange doesn't exist in Key Storage"),
+            None => Err(Error::UnknownExchange(exchange_name.to_string())),
             Some((_, signature)) => Ok(signature),
         }
     }
 }
 
+impl Default for KeyStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl KeyExchange {
     pub fn new() -> Self {
         KeyExchange {
@@ -97,6 +238,19 @@ impl KeyExchange {
             other_person_public_key: Default::default(),
             shared_secret: Default::default(),
             encryption_key: Default::default(),
+            receive_key: Default::default(),
+            mac_key: Default::default(),
+            kdf_version: CURRENT_KDF_VERSION,
+            send_counter: 0,
+            receive_window: ReceiveWindow::default(),
+            ratchet: None,
+            verified: false,
+            awaiting_confirmation: false,
+            previous_keys: None,
+            kind: ExchangeKind::Classic,
+            kem_public_key: Vec::new(),
+            kem_secret_key: Vec::new(),
+            kem_ciphertext: Vec::new(),
         }
     }
 
@@ -112,32 +266,197 @@ impl KeyExchange {
         self.encryption_key
     }
 
+    pub fn get_kdf_version(&self) -> u8 {
+        self.kdf_version
+    }
+
+    pub fn set_kdf_version(&mut self, kdf_version: u8) {
+        self.kdf_version = kdf_version;
+    }
+
+    pub fn get_send_key(&self) -> [u8; 32] {
+        self.encryption_key
+    }
+
+    pub fn get_receive_key(&self) -> [u8; 32] {
+        if self.kdf_version == KDF_V1 {
+            return self.encryption_key;
+        }
+        self.receive_key
+    }
+
+    pub fn get_mac_key(&self) -> [u8; 32] {
+        self.mac_key
+    }
+
     pub fn add_your_dh_kp(&mut self, kp: DhKeyPair) {
         self.your_public_key = kp.public;
         self.your_static_secret = kp.secret;
     }
 
     pub fn add_your_public_key(&mut self, pub_k: [u8; 32]) {
-        self.your_public_key = pub_k.clone();
+        self.your_public_key = pub_k;
     }
 
     pub fn add_your_static_secret(&mut self, static_secret: [u8; 32]) {
-        self.your_static_secret = static_secret.clone();
+        self.your_static_secret = static_secret;
     }
 
     pub fn add_other_person_public_key(&mut self, pub_k: [u8; 32]) {
-        self.other_person_public_key = pub_k.clone();
+        self.other_person_public_key = pub_k;
     }
 
     pub fn add_shared_secret(&mut self, shared: [u8; 32], remove_static_secret: bool) {
-        self.shared_secret = shared.clone();
+        self.shared_secret = shared;
         if remove_static_secret {
             self.your_static_secret = Default::default();
         }
     }
 
+    pub fn next_send_counter(&mut self) -> u64 {
+        self.send_counter += 1;
+        self.send_counter
+    }
+
+    pub fn record_received_counter(&mut self, counter: u64) -> MessageOrder {
+        self.receive_window.record(counter)
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.shared_secret != [0u8; 32] && self.other_person_public_key != [0u8; 32]
+    }
+
+    pub fn get_safety_number(&self) -> Option<String> {
+        if !self.is_completed() {
+            return None;
+        }
+        Some(safety_number(
+            self.your_public_key,
+            self.other_person_public_key,
+        ))
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+
+    pub fn set_verified(&mut self, verified: bool) {
+        self.verified = verified;
+    }
+
+    pub fn is_awaiting_confirmation(&self) -> bool {
+        self.awaiting_confirmation
+    }
+
+    pub fn set_awaiting_confirmation(&mut self, awaiting: bool) {
+        self.awaiting_confirmation = awaiting;
+    }
+
+    fn get_confirmation_key(&self) -> [u8; 32] {
+        if self.mac_key != [0u8; 32] {
+            return self.mac_key;
+        }
+        gen_confirmation_key(self.shared_secret)
+    }
+
+    pub fn get_confirmation_token(&self) -> Option<String> {
+        if !self.is_completed() {
+            return None;
+        }
+        Some(confirmation_token(
+            &self.get_confirmation_key(),
+            self.your_public_key,
+            self.other_person_public_key,
+            self.get_send_key(),
+            self.get_receive_key(),
+        ))
+    }
+
+    pub fn check_confirmation_token(&self, token: &str) -> Result<(), Error> {
+        check_confirmation_token(
+            token,
+            &self.get_confirmation_key(),
+            self.your_public_key,
+            self.other_person_public_key,
+            self.get_send_key(),
+            self.get_receive_key(),
+        )
+    }
+
+    pub fn get_ratchet(&self) -> Option<&RatchetState> {
+        self.ratchet.as_ref()
+    }
+
+    pub fn set_ratchet(&mut self, ratchet: Option<RatchetState>) {
+        self.ratchet = ratchet;
+    }
+
+    pub fn get_kind(&self) -> ExchangeKind {
+        self.kind
+    }
+
+    pub fn add_kem_kp(&mut self, mut kp: KemKeyPair) {
+        self.kind = ExchangeKind::Hybrid;
+        self.kem_public_key = std::mem::take(&mut kp.public);
+        self.kem_secret_key = std::mem::take(&mut kp.secret);
+    }
+
+    pub fn get_kem_public_key(&self) -> &[u8] {
+        &self.kem_public_key
+    }
+
+    pub fn get_kem_secret_key(&self) -> &[u8] {
+        &self.kem_secret_key
+    }
+
+    /// Once the ML-KEM secret is known, the secret key is not needed anymore.
+    pub fn remove_kem_secret_key(&mut self) {
+        self.kem_secret_key = Vec::new();
+    }
+
+    /// Ciphertext to send back to the other party, when you completed the
+    /// hybrid exchange with their Public Key.
+    pub fn get_kem_ciphertext(&self) -> Option<&[u8]> {
+        match self.kem_ciphertext.is_empty() {
+            true => None,
+            false => Some(&self.kem_ciphertext),
+        }
+    }
+
+    pub fn add_kem_ciphertext(&mut self, ciphertext: Vec<u8>) {
+        self.kem_ciphertext = ciphertext;
+    }
+
+    /// Returns the keys replaced by the last rotation, unless their grace period is over.
+    pub fn get_previous_keys(&self, now: u64) -> Option<&PreviousKeys> {
+        self.previous_keys
+            .as_ref()
+            .filter(|previous| now < previous.expires_at)
+    }
+
+    pub fn set_previous_keys(&mut self, previous_keys: Option<PreviousKeys>) {
+        self.previous_keys = previous_keys;
+    }
+
+    /// Keeps the current receive key and session until `expires_at`, before
+    /// they are replaced by the keys of a rotation.
+    pub fn start_grace_period(&mut self, expires_at: u64) {
+        self.previous_keys = Some(PreviousKeys {
+            receive_key: self.get_receive_key(),
+            shared_secret: self.shared_secret,
+            ratchet: self.ratchet.take(),
+            expires_at,
+        });
+    }
+
+    pub fn add_channel_keys(&mut self, keys: ChannelKeys) {
+        self.encryption_key = keys.send_key;
+        self.receive_key = keys.receive_key;
+        self.mac_key = keys.mac_key;
+    }
+
     pub fn add_encryption_key(&mut self, encryption_key: [u8; 32], remove_all: bool) {
-        self.encryption_key = encryption_key.clone();
+        self.encryption_key = encryption_key;
         if remove_all {
             self.your_public_key = Default::default();
             self.your_static_secret = Default::default();
@@ -147,6 +466,124 @@ impl KeyExchange {
     }
 }
 
+impl Default for KeyExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReceiveWindow {
+    pub fn record(&mut self, counter: u64) -> MessageOrder {
+        if counter > self.highest {
+            let shift = counter - self.highest;
+            let in_order = shift == 1;
+            self.seen = match shift {
+                s if s >= RECEIVE_WINDOW_SIZE => 0,
+                s => self.seen << s,
+            } | 1;
+            self.highest = counter;
+
+            return match in_order {
+                true => MessageOrder::InOrder,
+                false => MessageOrder::OutOfOrder,
+            };
+        }
+
+        let age = self.highest - counter;
+        if age >= RECEIVE_WINDOW_SIZE {
+            return MessageOrder::TooOld;
+        }
+
+        let bit = 1u64 << age;
+        if self.seen & bit != 0 {
+            return MessageOrder::Replayed;
+        }
+        self.seen |= bit;
+        MessageOrder::OutOfOrder
+    }
+}
+
+impl GroupChannel {
+    pub fn new(members: Vec<String>) -> Self {
+        let mut group_id = [0u8; GROUP_ID_SIZE];
+        OsRng.fill_bytes(&mut group_id);
+
+        GroupChannel {
+            group_id,
+            members,
+            sender_key: SenderKey::new(0),
+            member_keys: HashMap::new(),
+        }
+    }
+
+    pub fn is_member(&self, exchange_name: &str) -> bool {
+        self.members.iter().any(|member| member == exchange_name)
+    }
+
+    /// Replaces your sender key, so members added later can't read the past
+    /// messages, and removed ones can't read the next.
+    pub fn rekey(&mut self) {
+        self.sender_key = SenderKey::new(self.sender_key.generation + 1);
+    }
+
+    /// Stores the sender key of a member, unless a newer one is already known,
+    /// so a replayed distribution can't bring back an old key.
+    pub fn add_member_key(&mut self, exchange_name: &str, sender_key: SenderKey) -> bool {
+        match self.member_keys.get(exchange_name) {
+            Some(known) if known.generation >= sender_key.generation => known.key == sender_key.key,
+            _ => {
+                self.member_keys
+                    .insert(exchange_name.to_string(), sender_key);
+                true
+            }
+        }
+    }
+
+    /// Finds the member whose sender key encrypted a message.
+    pub fn find_sender(&self, key_id: &[u8; KEY_ID_SIZE]) -> Option<&str> {
+        self.member_keys
+            .iter()
+            .find(|(name, sender_key)| {
+                self.is_member(name) && envelope_key_id(&sender_key.key) == *key_id
+            })
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+impl SenderKey {
+    pub fn new(generation: u32) -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        let signing_kp = gen_signing_kp();
+
+        SenderKey {
+            generation,
+            key,
+            signing_public: signing_kp.public,
+            signing_secret: signing_kp.secret,
+            send_counter: 0,
+            receive_window: ReceiveWindow::default(),
+        }
+    }
+
+    /// The sender key of another member, as received from them.
+    pub fn from_member(generation: u32, key: [u8; 32], signing_public: [u8; 32]) -> Self {
+        SenderKey {
+            generation,
+            key,
+            signing_public,
+            signing_secret: [0u8; 32],
+            send_counter: 0,
+            receive_window: ReceiveWindow::default(),
+        }
+    }
+
+    pub fn next_send_counter(&mut self) -> u64 {
+        self.send_counter += 1;
+        self.send_counter
+    }
+}
+
 impl KeySignature {
     pub fn new() -> Self {
         KeySignature {
@@ -156,15 +593,42 @@ impl KeySignature {
         }
     }
 
+    pub fn get_your_public_key(&self) -> [u8; 32] {
+        self.your_public_key
+    }
+
+    pub fn get_your_private_key(&self) -> [u8; 32] {
+        self.your_private_key
+    }
+
+    pub fn get_other_person_public_key(&self) -> [u8; 32] {
+        self.other_person_public_key
+    }
+
+    pub fn has_signing_kp(&self) -> bool {
+        self.your_private_key != [0u8; 32]
+    }
+
+    pub fn add_your_signing_kp(&mut self, kp: SigningKeyPair) {
+        self.your_public_key = kp.public;
+        self.your_private_key = kp.secret;
+    }
+
     pub fn add_your_public_key(&mut self, pub_k: [u8; 32]) {
-        self.your_public_key = pub_k.clone();
+        self.your_public_key = pub_k;
     }
 
     pub fn add_your_private_key(&mut self, priv_k: [u8; 32]) {
-        self.your_public_key = priv_k.clone();
+        self.your_private_key = priv_k;
     }
 
     pub fn add_other_person_public_key(&mut self, pub_k: [u8; 32]) {
-        self.your_public_key = pub_k.clone();
+        self.other_person_public_key = pub_k;
+    }
+}
+
+impl Default for KeySignature {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -1,6 +1,25 @@
+pub mod backup;
+pub mod cli;
 pub mod config;
 pub mod encryption;
+pub mod envelope;
+pub mod error;
+pub mod file_encryption;
+pub mod group;
+pub mod hybrid_kem;
+pub mod key_encoding;
 pub mod key_exchange;
 pub mod key_store;
 pub mod key_store_classes;
+pub mod message;
+pub mod message_io;
 pub mod prompt;
+pub mod qr;
+pub mod ratchet;
+pub mod signature;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
+pub mod storage_backend;
+pub mod storage_encryption;
+pub mod storage_file;
+pub mod storage_migration;
@@ -0,0 +1,330 @@
+use crate::encryption::{encrypted_to_str, str_to_encrypted};
+use crate::envelope::{
+    open_envelope, open_multi_recipient_envelope, seal_envelope, seal_multi_recipient_envelope,
+    seal_ratchet_envelope, Envelope, FLAG_KEY_ROTATION, FLAG_SIGNED,
+};
+use crate::error::Error;
+use crate::group::{SenderKeyDistribution, SenderKeyUpdate};
+use crate::key_encoding::{encode_public_key, parse_public_key, PUBLIC_KEY_PREFIX};
+use crate::key_exchange::gen_static_kp;
+use crate::key_store::{
+    accept_rotated_key, accept_sender_key, find_exchange_by_key_id,
+    get_exchange_other_signing_public, get_exchange_receive_key, get_exchange_send_key,
+    get_exchange_signing_secret, get_previous_keys, get_ratchet_session, get_usable_exchange_names,
+    is_ratchet_enabled, is_signature_enabled, lock_storage, next_send_counter,
+    record_received_counter, rotate_exchange_keys, save_previous_ratchet_session,
+    save_ratchet_session,
+};
+use crate::key_store_classes::{MessageOrder, PreviousKeys};
+use crate::signature::{sign_message, verify_signature};
+
+use zeroize::Zeroizing;
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureStatus {
+    Disabled,
+    Signed,
+    Verified,
+    Unsigned,
+    UnknownSigner,
+}
+
+#[derive(Debug)]
+pub struct DecryptedMessage {
+    /// Wiped from memory once dropped, like the keys.
+    pub message: Zeroizing<Vec<u8>>,
+    pub signature: SignatureStatus,
+    pub order: MessageOrder,
+    /// The message was a key rotation, the exchange now uses the new Public Key
+    /// of the other party, unless it was replayed.
+    pub key_rotated: bool,
+    /// The message was the sender key of the other party for a group.
+    pub sender_key: Option<SenderKeyUpdate>,
+}
+
+pub fn encrypt_for_exchange(
+    exchange_name: String,
+    message: &[u8],
+) -> Result<(String, SignatureStatus), Error> {
+    seal_for_exchange(exchange_name, message, 0)
+}
+
+/// Encrypts the message once for several exchanges. It isn't signed: each
+/// recipient knows it comes from you, since only you share their exchange key.
+/// Ratchet sessions have a key per message and recipient, so it's refused
+/// while the ratchet is on.
+pub fn encrypt_for_exchanges(
+    mut exchange_names: Vec<String>,
+    message: &[u8],
+) -> Result<(String, SignatureStatus), Error> {
+    if is_ratchet_enabled()? {
+        return Err(Error::MultiRecipientWithRatchet);
+    }
+    exchange_names.sort();
+    exchange_names.dedup();
+
+    let _lock = lock_storage()?;
+    let keys = exchange_names
+        .iter()
+        .map(|name| get_exchange_send_key(name.clone()))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let recipients = exchange_names
+        .into_iter()
+        .zip(keys)
+        .map(|(name, key)| Ok((key, next_send_counter(name)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let recipients: Vec<_> = recipients
+        .iter()
+        .map(|(key, counter)| (&**key, *counter))
+        .collect();
+    let envelope = seal_multi_recipient_envelope(message, &recipients)?;
+    let status = match is_signature_enabled()? {
+        true => SignatureStatus::Unsigned,
+        false => SignatureStatus::Disabled,
+    };
+    Ok((encrypted_to_str(&envelope), status))
+}
+
+/// Decrypts a message without knowing which exchange it was sent over, and
+/// returns the name of that exchange with it. The key id of the message
+/// usually tells, otherwise each exchange is tried in turn.
+pub fn decrypt_from_any_exchange(encoded_msg: String) -> Result<(String, DecryptedMessage), Error> {
+    let envelope = str_to_encrypted(encoded_msg.clone())?;
+    let hinted = find_exchange_by_hint(&envelope)?;
+    if let Some(exchange_name) = &hinted {
+        return Ok((
+            exchange_name.clone(),
+            decrypt_for_exchange(exchange_name.clone(), encoded_msg)?,
+        ));
+    }
+
+    // Messages of ratchet sessions only carry the id of a message key, and
+    // older ones none. Trying an exchange saves nothing, not even a new ratchet
+    // session, unless the message authenticates.
+    let mut exchange_names = get_usable_exchange_names()?;
+    exchange_names.sort();
+    let mut error = Error::NoMatchingExchange;
+    for exchange_name in exchange_names {
+        match decrypt_for_exchange(exchange_name.clone(), encoded_msg.clone()) {
+            Ok(decrypted) => return Ok((exchange_name, decrypted)),
+            // Most likely the right exchange, with a message decrypted before.
+            Err(e @ Error::MessageKeyUnavailable) => error = e,
+            // Encrypted or signed for another exchange.
+            Err(Error::KeyMismatch | Error::AuthenticationFailed | Error::InvalidSignature) => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(error)
+}
+
+fn find_exchange_by_hint(envelope: &Envelope) -> Result<Option<String>, Error> {
+    let key_ids: Vec<_> = match envelope.is_multi_recipient() {
+        true => envelope
+            .recipients
+            .iter()
+            .map(|recipient| recipient.key_id)
+            .collect(),
+        false if envelope.is_legacy() => return Ok(None),
+        false => vec![envelope.key_id],
+    };
+
+    find_exchange_by_key_id(&key_ids)
+}
+
+/// Replaces your key pair of the exchange, and returns the message that sends
+/// the new Public Key to the other party. It's encrypted with the current keys,
+/// so they know it comes from you.
+pub fn rotate_keys_for_exchange(exchange_name: String) -> Result<(String, SignatureStatus), Error> {
+    let kp = gen_static_kp();
+    let new_public = encode_public_key(kp.public, PUBLIC_KEY_PREFIX);
+
+    // Held from sealing the rotation message until the new keys are stored, so
+    // no other message is encrypted or decrypted in between.
+    let _lock = lock_storage()?;
+    let sealed = seal_for_exchange(
+        exchange_name.clone(),
+        new_public.as_bytes(),
+        FLAG_KEY_ROTATION,
+    )?;
+    rotate_exchange_keys(exchange_name, kp)?;
+    Ok(sealed)
+}
+
+/// Encrypts `message` for the exchange, `flags` tell the other party what it carries.
+pub fn seal_for_exchange(
+    exchange_name: String,
+    message: &[u8],
+    mut flags: u8,
+) -> Result<(String, SignatureStatus), Error> {
+    let signature_enabled = is_signature_enabled()?;
+    let signing_secret = match signature_enabled {
+        true => get_exchange_signing_secret(exchange_name.clone())?,
+        false => None,
+    };
+    if signing_secret.is_some() {
+        flags |= FLAG_SIGNED;
+    }
+
+    // Held from loading the session or counter until it's saved, so another
+    // CryptoChannel running at the same time can't take the same message key.
+    let _lock = lock_storage()?;
+    let mut envelope = if is_ratchet_enabled()? {
+        // The session is saved before sending, so a message key is never reused.
+        let mut session = get_ratchet_session(exchange_name.clone())?;
+        let (header, message_key) = session.next_send_key();
+        save_ratchet_session(exchange_name, session)?;
+        seal_ratchet_envelope(message, &message_key, flags, header)?
+    } else {
+        let encryption_key = get_exchange_send_key(exchange_name.clone())?;
+        let counter = next_send_counter(exchange_name)?;
+        seal_envelope(message, &encryption_key, flags, Some(counter))?
+    };
+
+    let status = match signing_secret {
+        Some(secret) => {
+            let signature = sign_message(&envelope.signed_bytes(), &secret);
+            envelope.signature = Some(signature.to_vec());
+            SignatureStatus::Signed
+        }
+        None if signature_enabled => SignatureStatus::Unsigned,
+        None => SignatureStatus::Disabled,
+    };
+
+    Ok((encrypted_to_str(&envelope), status))
+}
+
+pub fn decrypt_for_exchange(
+    exchange_name: String,
+    encoded_msg: String,
+) -> Result<DecryptedMessage, Error> {
+    let envelope = str_to_encrypted(encoded_msg)?;
+
+    let status = match (
+        &envelope.signature,
+        get_exchange_other_signing_public(exchange_name.clone())?,
+    ) {
+        (Some(signature), Some(signing_public)) => {
+            verify_signature(&envelope.signed_bytes(), signature, &signing_public)?;
+            SignatureStatus::Verified
+        }
+        (Some(_), None) => SignatureStatus::UnknownSigner,
+        (None, _) if is_signature_enabled()? => SignatureStatus::Unsigned,
+        (None, _) => SignatureStatus::Disabled,
+    };
+
+    // The session and counters are only updated once the message authenticated,
+    // a forged one could otherwise block real messages. The lock is held from
+    // loading them until they're saved, with the rotated key if there is one.
+    let _lock = lock_storage()?;
+    let ((decrypted_msg, order), with_previous_keys) =
+        match open_with_current_keys(&exchange_name, &envelope) {
+            Err(
+                e @ (Error::KeyMismatch
+                | Error::AuthenticationFailed
+                | Error::MessageKeyUnavailable),
+            ) => match get_previous_keys(exchange_name.clone())? {
+                // It might have been sent before the last rotation.
+                Some(previous) => (
+                    open_with_previous_keys(&exchange_name, &envelope, previous).map_err(|_| e)?,
+                    true,
+                ),
+                None => return Err(e),
+            },
+            result => (result?, false),
+        };
+
+    // A replayed rotation message must not bring back an older key.
+    let key_rotated = envelope.is_key_rotation();
+    if key_rotated && order != MessageOrder::Replayed && order != MessageOrder::TooOld {
+        let new_public = std::str::from_utf8(&decrypted_msg).map_err(|_| Error::BadEncoding)?;
+        accept_rotated_key(
+            exchange_name.clone(),
+            parse_public_key(new_public, PUBLIC_KEY_PREFIX)?,
+            with_previous_keys,
+        )?;
+    }
+
+    let sender_key = match envelope.is_sender_key() {
+        true => {
+            let distribution = SenderKeyDistribution::from_bytes(&decrypted_msg)?;
+            Some(accept_sender_key(
+                exchange_name,
+                distribution.group_id,
+                distribution.group_name.clone(),
+                distribution.to_sender_key(),
+            )?)
+        }
+        false => None,
+    };
+
+    Ok(DecryptedMessage {
+        message: Zeroizing::new(decrypted_msg),
+        signature: status,
+        order,
+        key_rotated,
+        sender_key,
+    })
+}
+
+fn open_with_current_keys(
+    exchange_name: &str,
+    envelope: &Envelope,
+) -> Result<(Vec<u8>, MessageOrder), Error> {
+    match envelope.ratchet {
+        Some(header) => {
+            let mut session = get_ratchet_session(exchange_name.to_string())?;
+            let (message_key, order) = session.receive_key(&header)?;
+            let decrypted_msg = open_envelope(envelope, &message_key)?;
+            save_ratchet_session(exchange_name.to_string(), session)?;
+            Ok((decrypted_msg, order))
+        }
+        None => {
+            let encryption_key = get_exchange_receive_key(exchange_name.to_string())?;
+            open_with_receive_key(exchange_name, envelope, &encryption_key)
+        }
+    }
+}
+
+fn open_with_previous_keys(
+    exchange_name: &str,
+    envelope: &Envelope,
+    mut previous: PreviousKeys,
+) -> Result<(Vec<u8>, MessageOrder), Error> {
+    match envelope.ratchet {
+        Some(header) => {
+            let mut session = previous
+                .ratchet
+                .take()
+                .ok_or(Error::MessageKeyUnavailable)?;
+            let (message_key, order) = session.receive_key(&header)?;
+            let decrypted_msg = open_envelope(envelope, &message_key)?;
+            save_previous_ratchet_session(exchange_name.to_string(), session)?;
+            Ok((decrypted_msg, order))
+        }
+        None => open_with_receive_key(exchange_name, envelope, &previous.receive_key),
+    }
+}
+
+fn open_with_receive_key(
+    exchange_name: &str,
+    envelope: &Envelope,
+    key: &[u8; 32],
+) -> Result<(Vec<u8>, MessageOrder), Error> {
+    if envelope.is_multi_recipient() {
+        let (decrypted_msg, counter) = open_multi_recipient_envelope(envelope, key)?;
+        let order = record_received_counter(exchange_name.to_string(), counter)?;
+        return Ok((decrypted_msg, order));
+    }
+
+    let decrypted_msg = open_envelope(envelope, key)?;
+    Ok((decrypted_msg, record_counter(exchange_name, envelope)?))
+}
+
+// Counters keep going across rotations, so a single window covers both keys.
+fn record_counter(exchange_name: &str, envelope: &Envelope) -> Result<MessageOrder, Error> {
+    match envelope.counter {
+        Some(counter) => record_received_counter(exchange_name.to_string(), counter),
+        None => Ok(MessageOrder::Untracked),
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use arboard::Clipboard;
+
+use crate::error::Error;
+use crate::file_encryption::write_through_partial_file;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageSource {
+    Clipboard,
+    Stdin,
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageSink {
+    Clipboard,
+    Stdout,
+    File(PathBuf),
+}
+
+impl FromStr for MessageSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err(String::from("The message source can't be empty")),
+            "clipboard" => Ok(MessageSource::Clipboard),
+            "-" | "stdin" => Ok(MessageSource::Stdin),
+            path => Ok(MessageSource::File(PathBuf::from(path))),
+        }
+    }
+}
+
+impl FromStr for MessageSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err(String::from("The message destination can't be empty")),
+            "clipboard" => Ok(MessageSink::Clipboard),
+            "-" | "stdout" => Ok(MessageSink::Stdout),
+            path => Ok(MessageSink::File(PathBuf::from(path))),
+        }
+    }
+}
+
+impl fmt::Display for MessageSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageSource::Clipboard => write!(f, "clipboard"),
+            MessageSource::Stdin => write!(f, "stdin"),
+            MessageSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl fmt::Display for MessageSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageSink::Clipboard => write!(f, "clipboard"),
+            MessageSink::Stdout => write!(f, "stdout"),
+            MessageSink::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+pub fn read_message(source: &MessageSource) -> Result<Vec<u8>, Error> {
+    match source {
+        MessageSource::Clipboard => {
+            let mut clipboard = Clipboard::new().map_err(Error::Clipboard)?;
+            let text = clipboard.get_text().map_err(Error::Clipboard)?;
+            Ok(text.into_bytes())
+        }
+        MessageSource::Stdin => {
+            let mut message = Vec::new();
+            io::stdin()
+                .read_to_end(&mut message)
+                .map_err(Error::MessageIo)?;
+            Ok(message)
+        }
+        MessageSource::File(path) => fs::read(path).map_err(Error::MessageIo),
+    }
+}
+
+pub fn write_message(sink: &MessageSink, message: &[u8]) -> Result<(), Error> {
+    match sink {
+        MessageSink::Clipboard => {
+            let text = std::str::from_utf8(message).map_err(|_| Error::BadEncoding)?;
+            let mut clipboard = Clipboard::new().map_err(Error::Clipboard)?;
+            clipboard.set_text(text).map_err(Error::Clipboard)
+        }
+        MessageSink::Stdout => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(message).map_err(Error::MessageIo)?;
+            stdout.flush().map_err(Error::MessageIo)
+        }
+        // Decrypted messages are written like decrypted files.
+        MessageSink::File(path) => write_through_partial_file(path, |mut writer| {
+            writer.write_all(message).map_err(Error::MessageIo)?;
+            writer.flush().map_err(Error::MessageIo)
+        }),
+    }
+}
@@ -1,16 +1,39 @@
-use std::borrow::Borrow;
-
-use crate::encryption::{decrypt_message, encrypt_message, encrypted_to_str, str_to_encrypted};
-use crate::key_exchange::{gen_encryption_key, gen_shared_secret, gen_static_kp, string_to_key};
+use crate::backup::{ImportConflict, RESTORED_STATE_WARNING};
+use crate::file_encryption::{decrypt_file, encrypt_file};
+use crate::group::{
+    add_member, create_group, decrypt_for_group, encrypt_for_group, remove_member,
+    share_sender_key, SenderKeyUpdate,
+};
+use crate::key_encoding::{
+    encode_public_key, parse_peer_key, parse_public_key, PeerKey, LEGACY_KEY_WARNING,
+    PUBLIC_KEY_PREFIX, SIGNING_KEY_PREFIX,
+};
+use crate::key_exchange::key_to_string;
 use crate::key_store::{
-    get_exchange_dh_public, get_exchange_dh_secret, get_exchange_encryption_key,
-    get_key_exchange_names, read_storage, validate_new_exchange_name, write_storage,
+    complete_exchange, confirm_exchange, delete_exchange, delete_group, export_backup,
+    find_exposed_files, get_exchange_dh_public, get_exchange_kind, get_exchange_public_key,
+    get_exchange_receive_key, get_exchange_safety_number, get_exchange_send_key,
+    get_exchange_signing_public, get_group, get_group_names, get_key_exchange_names, import_backup,
+    is_exchange_verified, is_signature_enabled, is_storage_encrypted, lock_storage, new_exchange,
+    restrict_file_permissions, set_exchange_verified, set_storage_encryption,
+    start_key_confirmation, unlock_storage, validate_new_exchange_name,
+};
+use crate::key_store_classes::{ExchangeKind, MessageOrder};
+use crate::message::{
+    decrypt_from_any_exchange, encrypt_for_exchange, encrypt_for_exchanges,
+    rotate_keys_for_exchange, SignatureStatus,
 };
-use crate::key_store_classes::{KeyExchange, KeySignature};
+use crate::message_io::{read_message, write_message, MessageSink, MessageSource};
+use crate::qr::{export_qr, key_qr_data, qr_to_terminal, MAX_QR_MESSAGE_LEN};
+use crate::storage_file::write_atomically;
 
-use arboard::Clipboard;
 use colored::Colorize;
-use inquire::{Confirm, Select, Text};
+use inquire::list_option::ListOption;
+use inquire::validator::Validation;
+use inquire::{Confirm, MultiSelect, Password, Select, Text};
+use std::fs;
+use std::path::PathBuf;
+use zeroize::Zeroizing;
 
 pub fn prompt_exchange_name() -> Result<String, &'static str> {
     let name = Text::new("Please enter a name for the key pair:")
@@ -30,7 +53,13 @@ pub fn prompt_exchange_name() -> Result<String, &'static str> {
 }
 
 pub fn select_exchange() -> Result<String, &'static str> {
-    let options = get_key_exchange_names();
+    let options = match get_key_exchange_names() {
+        Ok(options) => options,
+        Err(e) => {
+            println!("Error: {}", e);
+            return Err("Failed to read the key storage");
+        }
+    };
     let ans = Select::new(
         "Please select a key exchange from the following options:",
         options,
@@ -43,14 +72,55 @@ pub fn select_exchange() -> Result<String, &'static str> {
     }
 }
 
+pub fn select_exchanges() -> Result<Vec<String>, &'static str> {
+    let mut options = match get_key_exchange_names() {
+        Ok(options) => options,
+        Err(e) => {
+            println!("Error: {}", e);
+            return Err("Failed to read the key storage");
+        }
+    };
+    options.sort();
+    let ans = MultiSelect::new(
+        "Please select one or more key exchanges from the following options:",
+        options,
+    )
+    .with_help_message(
+        "Use space to select, enter to confirm. With several key exchanges, \
+         the message is encrypted once and each of them can decrypt it.",
+    )
+    .with_validator(
+        |exchanges: &[ListOption<&String>]| match exchanges.is_empty() {
+            true => Ok(Validation::Invalid(
+                "Select at least one key exchange.".into(),
+            )),
+            false => Ok(Validation::Valid),
+        },
+    )
+    .prompt();
+
+    match ans {
+        Ok(choices) => Ok(choices),
+        Err(_) => Err("There was an error, please try again"),
+    }
+}
+
 pub fn select_menu_action() -> Result<String, &'static str> {
     let options = vec![
         "Create New Key Exchange",
         "Complete Key Exchange",
         "Delete Key Exchange",
         "Send Public Key",
+        "Verify Key Exchange",
+        "Confirm Key Exchange",
+        "Rotate Keys",
         "Encrypt Message",
         "Decrypt Message",
+        "Encrypt File",
+        "Decrypt File",
+        "Group Channels",
+        "Backup",
+        "Storage Encryption",
         "Exit",
     ];
     let ans = Select::new(
@@ -78,7 +148,16 @@ pub fn select_menu_action() -> Result<String, &'static str> {
     }
 }
 
-pub fn other_party_public_key_prompt() -> Result<String, &'static str> {
+fn key_validator(
+    prefix: &'static str,
+) -> impl Fn(&str) -> Result<Validation, inquire::CustomUserError> + Clone {
+    move |input: &str| match parse_public_key(input, prefix) {
+        Ok(_) => Ok(Validation::Valid),
+        Err(e) => Ok(Validation::Invalid(e.to_string().into())),
+    }
+}
+
+pub fn other_party_public_key_prompt() -> Result<PeerKey, &'static str> {
     let name =
         Text::new("Please enter the Public Key of the other party you wish to communicate with:")
             .with_help_message(
@@ -88,20 +167,217 @@ pub fn other_party_public_key_prompt() -> Result<String, &'static str> {
              To obtain the Public Key: \n \
              1. Request the other party to generate a Public Key using the menu \
              option `Create New Key Exchange`. \n \
-             2. The Public Key is represented as a series of alphanumeric \
-             characters, in base64 encoding. \n \n \
+             2. The Public Key starts with `ccpub1`, followed by letters and \
+             digits that include a checksum, so typos are detected. Keys in \
+             the base64 format of older versions are accepted too. For hybrid \
+             Key Exchanges, it starts with `cchpub1`, or with `cchct1` when the \
+             other party completed the exchange first. \n \n \
              Remember to: \n \
              - Enter the Public Key accurately, without typos or omissions. \n \
              - Even a small error can prevent successful key generation and communication. \n \
              - Copy the Public Key correctly and ensure its integrity. \n \n \
              Please enter the Public Key of the other party you wish to communicate with:",
             )
-            .with_placeholder("C02CYYKtZJ6qvUtux2YKw5jM/+PHs3q2iOcHN3K/EXU=")
+            .with_placeholder("ccpub1...")
+            .with_validator(|input: &str| match parse_peer_key(input) {
+                Ok(_) => Ok(Validation::Valid),
+                Err(e) => Ok(Validation::Invalid(e.to_string().into())),
+            })
             .prompt();
 
-    match name {
-        Ok(name) => Ok(name),
-        Err(_) => Err("An error happened."),
+    match name.map(|key| parse_peer_key(&key)) {
+        Ok(Ok(key)) => Ok(key),
+        _ => Err("An error happened."),
+    }
+}
+
+pub fn other_party_signing_key_prompt() -> Result<[u8; 32], &'static str> {
+    let key = Text::new("Please enter the Signing Key of the other party:")
+        .with_help_message(
+            "Signatures are enabled (`use_signature` in the config file), so \
+             every message is signed by its sender. The Signing Key of the \
+             other party is shown next to their Public Key when they use the \
+             `Send Public Key` menu option. It is used to verify that the \
+             messages you decrypt were really sent by them.",
+        )
+        .with_placeholder("ccsig1...")
+        .with_validator(key_validator(SIGNING_KEY_PREFIX))
+        .prompt();
+
+    match key.map(|key| parse_public_key(&key, SIGNING_KEY_PREFIX)) {
+        Ok(Ok(key)) => Ok(key),
+        _ => Err("An error happened."),
+    }
+}
+
+pub fn new_passphrase_prompt() -> Result<String, &'static str> {
+    let passphrase = Password::new("Please enter a passphrase for the key storage:")
+        .with_help_message(
+            "The passphrase is used to derive the key that encrypts \
+             your key storage file. It is never written to disk, so \
+             if you forget it your Key Exchanges cannot be recovered.",
+        )
+        .with_custom_confirmation_message("Please confirm the passphrase:")
+        .with_custom_confirmation_error_message("The passphrases don't match.")
+        .prompt();
+
+    match passphrase {
+        Ok(passphrase) if passphrase.is_empty() => Err("The passphrase can't be empty."),
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => Err("An error happened when asking for the passphrase."),
+    }
+}
+
+pub fn check_permissions_prompt(allow_insecure_permissions: bool) -> Result<(), &'static str> {
+    let exposed = match find_exposed_files() {
+        Ok(exposed) => exposed,
+        Err(e) => {
+            println!("Error: {}", e);
+            return Err("Failed to check the permissions of the key storage.");
+        }
+    };
+    if exposed.is_empty() {
+        return Ok(());
+    }
+
+    println!("{}", "Other users can access these files:".yellow());
+    for path in &exposed {
+        println!("{}", format!("  {}", path.display()).yellow());
+    }
+
+    let ans = Confirm::new("Do you want to make them accessible to you only?")
+        .with_default(true)
+        .with_help_message("Anyone who can read the key storage can decrypt your messages.")
+        .prompt();
+    match ans {
+        Ok(true) => match restrict_file_permissions(&exposed) {
+            Ok(_) => {
+                println!("{}", "The permissions were fixed!".green());
+                Ok(())
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                Err("Failed to fix the permissions of the key storage.")
+            }
+        },
+        Ok(false) if allow_insecure_permissions => Ok(()),
+        Ok(false) => Err("The key storage is not safe to use. Run with \
+             --allow-insecure-permissions to use it anyway."),
+        Err(_) => Err("Error with questionnaire, try again later"),
+    }
+}
+
+pub fn backup_passphrase_prompt(confirm: bool) -> Result<String, &'static str> {
+    let mut passphrase = Password::new("Please enter the passphrase of the backup:")
+        .with_help_message(
+            "The backup is encrypted with a key derived from this passphrase. \
+             It is needed to import the backup, so keep it somewhere else \
+             than the backup itself.",
+        )
+        .with_custom_confirmation_message("Please confirm the passphrase:")
+        .with_custom_confirmation_error_message("The passphrases don't match.");
+    if !confirm {
+        passphrase = passphrase.without_confirmation();
+    }
+
+    match passphrase.prompt() {
+        Ok(passphrase) if passphrase.is_empty() => Err("The passphrase can't be empty."),
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => Err("An error happened when asking for the passphrase."),
+    }
+}
+
+pub fn unlock_storage_prompt() -> Result<(), &'static str> {
+    match is_storage_encrypted() {
+        Ok(false) => return Ok(()),
+        Ok(true) => (),
+        Err(e) => {
+            println!("Error: {}", e);
+            return Err("Failed to read the config file.");
+        }
+    }
+
+    for _ in 0..3 {
+        let passphrase = Password::new("Please enter the passphrase of your key storage:")
+            .without_confirmation()
+            .prompt();
+
+        match passphrase {
+            Ok(passphrase) => match unlock_storage(passphrase) {
+                Ok(_) => return Ok(()),
+                Err(e) => println!("{}", e.to_string().red()),
+            },
+            Err(_) => return Err("An error happened when asking for the passphrase."),
+        }
+    }
+
+    Err("Too many failed attempts to unlock the key storage.")
+}
+
+pub fn storage_encryption_prompt() {
+    let encrypted = match is_storage_encrypted() {
+        Ok(encrypted) => encrypted,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    let options = if encrypted {
+        vec!["Change Passphrase", "Disable Encryption", "Cancel"]
+    } else {
+        vec!["Enable Encryption", "Cancel"]
+    };
+
+    let ans = Select::new(
+        "Please select an action to perform on the key storage:",
+        options,
+    )
+    .with_help_message(
+        "When encryption is enabled, the key storage file is encrypted \
+         with a key derived from your passphrase (Argon2id + AES-GCM), \
+         and the passphrase is asked every time the tool starts.",
+    )
+    .prompt();
+
+    let passphrase = match ans {
+        Ok("Enable Encryption") | Ok("Change Passphrase") => match new_passphrase_prompt() {
+            Ok(passphrase) => Some(passphrase),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        },
+        Ok("Disable Encryption") => {
+            let confirm = Confirm::new("Store your keys unencrypted on disk?")
+                .with_default(false)
+                .prompt();
+            match confirm {
+                Ok(true) => None,
+                Ok(false) => {
+                    println!("Key storage encryption left unchanged");
+                    return;
+                }
+                Err(_) => {
+                    println!("Error with questionnaire, operation aborted");
+                    return;
+                }
+            }
+        }
+        Ok(_) => return,
+        Err(_) => {
+            println!("There was an error, please try again");
+            return;
+        }
+    };
+
+    let enabled = passphrase.is_some();
+    match set_storage_encryption(passphrase) {
+        Ok(_) if enabled => println!(
+            "{}",
+            "Your key storage is now encrypted with your passphrase.".green()
+        ),
+        Ok(_) => println!("{}", "Your key storage is no longer encrypted.".green()),
+        Err(e) => println!("Error: {}", e),
     }
 }
 
@@ -110,29 +386,72 @@ pub fn show_public_key() {
 
     match exchange {
         Ok(exchange_name) => {
-            let public_str = get_exchange_dh_public(exchange_name.clone());
+            let key_str = match get_exchange_public_key(exchange_name.clone()) {
+                Ok(key_str) => key_str,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
             println!(
                 "{}{}{}{}{}",
                 "Your Public Key for the exchange `".green(),
                 exchange_name.green().bold(),
                 "` is: `".green(),
-                public_str.green().bold(),
+                key_str.green().bold(),
                 "`".green()
             );
+            // Exchanges derived with KDF v1 already show it in base64.
+            let is_classic = matches!(
+                get_exchange_kind(exchange_name.clone()),
+                Ok(ExchangeKind::Classic)
+            );
+            if is_classic && key_str.starts_with(PUBLIC_KEY_PREFIX) {
+                if let Ok(public) = get_exchange_dh_public(exchange_name.clone()) {
+                    println!(
+                        "{}{}{}",
+                        "Only if they use an older version of the tool, send it in base64: `"
+                            .dimmed(),
+                        key_to_string(public).dimmed(),
+                        "`".dimmed()
+                    );
+                }
+            }
+
+            show_qr_code(
+                "Show your Public Key as a QR code?",
+                &key_qr_data(&key_str),
+                true,
+            );
+
+            match is_signature_enabled() {
+                Ok(true) => match get_exchange_signing_public(exchange_name.clone()) {
+                    Ok(signing_public) => println!(
+                        "{}{}{}",
+                        "Your Signing Key for this exchange is: `".green(),
+                        encode_public_key(signing_public, SIGNING_KEY_PREFIX)
+                            .green()
+                            .bold(),
+                        "`".green()
+                    ),
+                    Err(e) => println!("Error: {}", e),
+                },
+                Ok(false) => (),
+                Err(e) => println!("Error: {}", e),
+            }
         }
         Err(_) => println!("Failed to get exchange, it might have been deleted"),
     }
 }
 
 pub fn create_new_key_exchange() {
-    let exchange_name;
-    match prompt_exchange_name() {
-        Ok(name) => exchange_name = name,
+    let exchange_name = match prompt_exchange_name() {
+        Ok(name) => name,
         Err(e) => {
             println!("{}", e);
             return;
         }
-    }
+    };
 
     let is_name_valid = validate_new_exchange_name(exchange_name.clone());
 
@@ -144,26 +463,27 @@ pub fn create_new_key_exchange() {
         }
     }
 
-    let kp = gen_static_kp();
-    let mut new_key_exchange = KeyExchange::new();
-    new_key_exchange.add_your_dh_kp(kp);
-
-    let mut curr_storage = read_storage();
-    let new_result =
-        curr_storage.create_exchange(&exchange_name[..], false, Some(new_key_exchange), None);
-
-    match new_result {
-        Ok(_) => println!("New Key Exchange created successfully!"),
-        Err(e) => {
-            println!("{}", e);
+    let hybrid = Confirm::new("Use a post-quantum hybrid Key Exchange?")
+        .with_default(false)
+        .with_help_message(
+            "Combines X25519 with ML-KEM-768, so messages recorded today stay \
+             private even if quantum computers break X25519 later on. The other \
+             party must create a hybrid Key Exchange too, and the keys to send \
+             are much longer.",
+        )
+        .prompt();
+    let kind = match hybrid {
+        Ok(true) => ExchangeKind::Hybrid,
+        Ok(false) => ExchangeKind::Classic,
+        Err(_) => {
+            println!("Error with questionnaire, try again later");
             return;
         }
-    }
+    };
 
-    let write_result = write_storage(curr_storage);
-
-    match write_result {
+    match new_exchange(exchange_name, kind) {
         Ok(_) => {
+            println!("New Key Exchange created successfully!");
             println!(
                 "{}",
                 "Your key pair has been generated and stored \
@@ -176,44 +496,56 @@ pub fn create_new_key_exchange() {
                     .green()
             );
         }
-        Err(e) => println!("{}", e),
+        Err(e) => println!("Error: {}", e),
     }
 }
 
 pub fn complete_key_exchange() {
-    let exchange_name;
-    match select_exchange() {
-        Ok(name) => exchange_name = name,
+    let exchange_name = match select_exchange() {
+        Ok(name) => name,
         Err(_) => {
             println!("Failed to get exchange, it might have been deleted");
             return;
         }
-    }
+    };
 
-    let dh_public;
-    match other_party_public_key_prompt() {
-        Ok(pub_k) => dh_public = string_to_key(pub_k),
+    let peer_key = match other_party_public_key_prompt() {
+        Ok(peer_key) => peer_key,
         Err(e) => {
             println!("{}", e);
             return;
         }
-    }
-
-    let dh_secret = get_exchange_dh_secret(exchange_name.clone());
-    let shared_secret = gen_shared_secret(dh_public, dh_secret);
-    let encryption_key = gen_encryption_key(shared_secret);
+    };
 
-    let mut curr_storage = read_storage();
-    let pair = curr_storage.exchange_map.get_mut(&exchange_name);
-    let curr_exchange = &mut pair.unwrap().0;
-
-    curr_exchange.add_other_person_public_key(dh_public);
-    curr_exchange.add_shared_secret(shared_secret, false);
-    curr_exchange.add_encryption_key(encryption_key, false);
+    let mut signing_public = None;
+    match is_signature_enabled() {
+        Ok(true) => match other_party_signing_key_prompt() {
+            Ok(pub_k) => signing_public = Some(pub_k),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        },
+        Ok(false) => (),
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    }
 
-    println!("{:?}", curr_storage.exchange_map);
+    // The response has to match the keys just stored, so no other
+    // CryptoChannel may change the exchange in between.
+    let lock = match lock_storage() {
+        Ok(lock) => lock,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
 
-    match write_storage(curr_storage) {
+    let is_hybrid_public = matches!(peer_key, PeerKey::HybridPublic(..));
+    let is_legacy = matches!(peer_key, PeerKey::Legacy(_));
+    match complete_exchange(exchange_name.clone(), peer_key, signing_public) {
         Ok(_) => println!(
             "{}{}{}",
             "The Key Exchange `".green(),
@@ -224,82 +556,478 @@ pub fn complete_key_exchange() {
              exchange."
                 .green(),
         ),
-        Err(e) => println!("{}", e),
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
     }
-}
 
-pub fn encrypt_prompt() {
-    let ans = Confirm::new("Encrypt text from clipboard?")
+    if is_hybrid_public {
+        match get_exchange_public_key(exchange_name.clone()) {
+            Ok(response) => println!(
+                "{}{}{}",
+                "Send this response to the other party, so they can complete \
+                 the Key Exchange too (it replaces your Public Key): `"
+                    .green(),
+                response.green().bold(),
+                "`".green()
+            ),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+    if is_legacy {
+        println!("{}", LEGACY_KEY_WARNING.yellow());
+        match get_exchange_public_key(exchange_name.clone()) {
+            Ok(public_key) => println!(
+                "{}{}{}",
+                "Your Public Key: `".green(),
+                public_key.green().bold(),
+                "`".green()
+            ),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+    drop(lock);
+
+    print_safety_number(&exchange_name);
+
+    let ans = Confirm::new("Do you want to confirm the keys with the other party now?")
         .with_default(false)
         .with_help_message(
-            "The clipboard contains text that you have \
-                copied. Encrypting the text will ensure its \
-                confidentiality and security. If you choose \
-                to encrypt the text, the program will access \
-                the data from the clipboard, perform encryption, \
-                and replace the contents in the clipboard with \
-                the encrypted version. This allows you to securely \
-                share the encrypted message with others by simply \
-                pasting it. To proceed, select 'yes' and the \
-                program will handle the encryption process for you.",
+            "You both exchange a short token, to make sure you derived the same keys \
+             before using the Key Exchange. This catches mistyped Public Keys. Swapped \
+             Public Keys are only caught if you compare the tokens over a channel you \
+             trust, such as a call.",
         )
         .prompt();
+    if let Ok(true) = ans {
+        key_confirmation_round(&exchange_name);
+    }
+}
+
+fn confirmation_token_prompt() -> Result<String, &'static str> {
+    let validator = |input: &str| {
+        let digits = input
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .count();
+        match digits {
+            16 => Ok(Validation::Valid),
+            _ => Ok(Validation::Invalid(
+                "The token has 16 characters, such as `1a2b-3c4d-5e6f-7a8b`".into(),
+            )),
+        }
+    };
+
+    let ans = Text::new("Please enter the confirmation token of the other party:")
+        .with_validator(validator)
+        .prompt();
 
     match ans {
-        Ok(true) => (),
-        Ok(false) => {
-            println!("The text copied on the clipboard will remain unchanged");
+        Ok(token) => Ok(token),
+        Err(_) => Err("An error happened when asking for the token, try again later."),
+    }
+}
+
+fn key_confirmation_round(exchange_name: &str) {
+    let token = match start_key_confirmation(exchange_name.to_string()) {
+        Ok(token) => token,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    println!(
+        "{}{}\n{}",
+        "Your confirmation token: ".green(),
+        token.green().bold(),
+        "Send it to the other party, preferably over a channel you trust. The \
+         Key Exchange can't be used until their token is checked."
+            .green()
+    );
+
+    let other_token = match confirmation_token_prompt() {
+        Ok(token) => token,
+        Err(e) => {
+            println!("{}", e);
+            println!("Use the `Confirm Key Exchange` option to finish later.");
+            return;
+        }
+    };
+
+    match confirm_exchange(exchange_name.to_string(), &other_token) {
+        Ok(_) => println!(
+            "{}{}{}",
+            "The Key Exchange `".green(),
+            exchange_name.green().bold(),
+            "` is confirmed, both parties derived the same keys.".green()
+        ),
+        Err(e) => println!("Error: {}", e),
+    }
+}
+
+pub fn confirm_key_exchange() {
+    let exchange_name = match select_exchange() {
+        Ok(name) => name,
+        Err(_) => {
+            println!("Failed to get exchange, it might have been deleted");
+            return;
+        }
+    };
+
+    key_confirmation_round(&exchange_name);
+}
+
+fn print_safety_number(exchange_name: &str) {
+    match get_exchange_safety_number(exchange_name.to_string()) {
+        Ok(number) => println!(
+            "{}{}\n{}",
+            "Safety number: ".green(),
+            number.green().bold(),
+            "Compare it with the other party over a channel you trust, such as \
+             a call or in person. If it matches, use the `Verify Key Exchange` \
+             option to mark the exchange as verified."
+                .green()
+        ),
+        Err(e) => println!("Error: {}", e),
+    }
+}
+
+fn print_verification_status(exchange_name: &str) {
+    match is_exchange_verified(exchange_name.to_string()) {
+        Ok(true) => println!(
+            "{}{}{}",
+            "The Key Exchange `".green(),
+            exchange_name.green().bold(),
+            "` is verified.".green()
+        ),
+        Ok(false) => println!(
+            "{}",
+            format!(
+                "Warning: the Key Exchange `{}` is not verified. Compare the safety \
+                 numbers with the `Verify Key Exchange` option.",
+                exchange_name
+            )
+            .yellow()
+        ),
+        Err(_) => (),
+    }
+}
+
+pub fn rotate_keys_prompt() {
+    let exchange_name = match select_exchange() {
+        Ok(name) => name,
+        Err(_) => {
+            println!("Failed to get exchange, it might have been deleted");
             return;
         }
+    };
+
+    let ans = Confirm::new("Replace your keys for this Key Exchange?")
+        .with_default(false)
+        .with_help_message(
+            "A new key pair is generated, and a message with your new Public Key, \
+             encrypted with the current keys, is created for the other party. \
+             Messages sent with the previous keys can still be decrypted for a \
+             week, but the other party must decrypt the message before they can \
+             read your new messages.",
+        )
+        .prompt();
+    match ans {
+        Ok(true) => (),
+        Ok(false) => return,
         Err(_) => {
             println!("Error with questionnaire, try again later");
             return;
         }
     }
 
-    let exchange_name;
-    match select_exchange() {
-        Ok(name) => exchange_name = name,
+    let sink = match select_message_sink(
+        "Where do you want to write the message with your new Public Key?",
+        vec!["Clipboard", "File"],
+    ) {
+        Ok(sink) => sink,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let encoded_msg = match rotate_keys_for_exchange(exchange_name.clone()) {
+        Ok((encoded_msg, _)) => encoded_msg,
+        Err(e) => {
+            println!("Error: {}. Unable to rotate the keys.", e);
+            return;
+        }
+    };
+
+    match write_message(&sink, encoded_msg.as_bytes()) {
+        Ok(_) => println!(
+            "{}{}{}",
+            "Your keys were replaced. The message with your new Public Key was written to `"
+                .green(),
+            sink.to_string().green().bold(),
+            "`, send it to the other party so they decrypt it and switch to your new keys.".green()
+        ),
+        // The keys were already replaced, so the message must not be lost.
+        Err(e) => println!("Error: {}. The message was: {}", e, encoded_msg),
+    }
+}
+
+pub fn verify_key_exchange() {
+    let exchange_name = match select_exchange() {
+        Ok(name) => name,
+        Err(_) => {
+            println!("Failed to get exchange, it might have been deleted");
+            return;
+        }
+    };
+
+    let number = match get_exchange_safety_number(exchange_name.clone()) {
+        Ok(number) => number,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    println!("{}{}", "Safety number: ".green(), number.green().bold());
+
+    let ans = Confirm::new("Does it match the safety number of the other party?")
+        .with_default(false)
+        .with_help_message(
+            "The other party can see the same number with this option. Compare \
+             them over a channel you trust, such as a call or in person. If they \
+             don't match, someone might have swapped the Public Keys.",
+        )
+        .prompt();
+
+    match ans {
+        Ok(verified) => {
+            if let Err(e) = set_exchange_verified(exchange_name.clone(), verified) {
+                println!("Error: {}", e);
+                return;
+            }
+            if verified {
+                println!(
+                    "{}{}{}",
+                    "The Key Exchange `".green(),
+                    exchange_name.green().bold(),
+                    "` is now verified.".green()
+                );
+            } else {
+                println!(
+                    "{}",
+                    "Warning: the safety numbers don't match. Don't use this Key \
+                     Exchange, create a new one and send the Public Keys again."
+                        .yellow()
+                );
+            }
+        }
+        Err(_) => println!("Error with questionnaire, try again later"),
+    }
+}
+
+fn print_signature_status(status: &SignatureStatus, exchange_name: &str) {
+    match status {
+        SignatureStatus::Verified => println!(
+            "{}{}{}",
+            "Signature verified: the message was sent by `".green(),
+            exchange_name.green().bold(),
+            "`".green()
+        ),
+        SignatureStatus::UnknownSigner => println!(
+            "{}",
+            "Warning: the message is signed, but the Signing Key of the \
+             other party is unknown. The sender can't be verified."
+                .yellow()
+        ),
+        SignatureStatus::Unsigned => println!(
+            "{}",
+            "Warning: the message is not signed. The sender can't be verified.".yellow()
+        ),
+        SignatureStatus::Disabled | SignatureStatus::Signed => (),
+    }
+}
+
+fn print_message_order(order: &MessageOrder) {
+    let warning = match order {
+        MessageOrder::OutOfOrder => {
+            "Warning: the message arrived out of order, earlier messages might be missing."
+        }
+        MessageOrder::Replayed => {
+            "Warning: this message was already decrypted before. Someone might be replaying it."
+        }
+        MessageOrder::TooOld => {
+            "Warning: the message is too old to tell whether it was already decrypted."
+        }
+        MessageOrder::InOrder | MessageOrder::Untracked => return,
+    };
+    println!("{}", warning.yellow());
+}
+
+pub fn select_message_source(message: &str) -> Result<MessageSource, &'static str> {
+    let ans = Select::new(message, vec!["Clipboard", "File"])
+        .with_help_message(
+            "Choose `Clipboard` to use the text you have copied, \
+             or `File` to read the message from a file.",
+        )
+        .prompt();
+
+    match ans {
+        Ok("File") => match file_path_prompt("Please enter the path of the file to read:") {
+            Ok(path) => Ok(MessageSource::File(path)),
+            Err(e) => Err(e),
+        },
+        Ok(_) => Ok(MessageSource::Clipboard),
+        Err(_) => Err("There was an error, please try again"),
+    }
+}
+
+pub fn select_message_sink(
+    message: &str,
+    options: Vec<&'static str>,
+) -> Result<MessageSink, &'static str> {
+    let ans = Select::new(message, options).prompt();
+
+    match ans {
+        Ok("File") => match file_path_prompt("Please enter the path of the file to write:") {
+            Ok(path) => Ok(MessageSink::File(path)),
+            Err(e) => Err(e),
+        },
+        Ok("Clipboard") => Ok(MessageSink::Clipboard),
+        Ok(_) => Ok(MessageSink::Stdout),
+        Err(_) => Err("There was an error, please try again"),
+    }
+}
+
+pub fn file_path_prompt(message: &str) -> Result<PathBuf, &'static str> {
+    let path = Text::new(message)
+        .with_validator(|input: &str| {
+            if input.trim().is_empty() {
+                Ok(Validation::Invalid("The path can't be empty.".into()))
+            } else {
+                Ok(Validation::Valid)
+            }
+        })
+        .prompt();
+
+    match path {
+        Ok(path) => Ok(PathBuf::from(path.trim())),
+        Err(_) => Err("An error happened when asking for the file path."),
+    }
+}
+
+pub fn encrypt_prompt() {
+    let source = match select_message_source("Where is the message you want to encrypt?") {
+        Ok(source) => source,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    if source == MessageSource::Clipboard {
+        let ans = Confirm::new("Encrypt text from clipboard?")
+            .with_default(false)
+            .with_help_message(
+                "The clipboard contains text that you have \
+                    copied. Encrypting the text will ensure its \
+                    confidentiality and security. If you choose \
+                    to encrypt the text, the program will access \
+                    the data from the clipboard, perform encryption, \
+                    and replace the contents in the clipboard with \
+                    the encrypted version. This allows you to securely \
+                    share the encrypted message with others by simply \
+                    pasting it. To proceed, select 'yes' and the \
+                    program will handle the encryption process for you.",
+            )
+            .prompt();
+
+        match ans {
+            Ok(true) => (),
+            Ok(false) => {
+                println!("The text copied on the clipboard will remain unchanged");
+                return;
+            }
+            Err(_) => {
+                println!("Error with questionnaire, try again later");
+                return;
+            }
+        }
+    }
+
+    let exchange_names = match select_exchanges() {
+        Ok(names) => names,
         Err(_) => {
             println!("Failed to get exchange, it might have been deleted");
             return;
         }
+    };
+    for exchange_name in &exchange_names {
+        print_verification_status(exchange_name);
     }
 
-    let encryption_key = get_exchange_encryption_key(exchange_name.clone());
-    if encryption_key == [0u8; 32] {
+    let message = match read_message(&source) {
+        Ok(message) => Zeroizing::new(message),
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    if source == MessageSource::Clipboard {
         println!(
-            "Error: Key Exchange was not completed for `{}`. \
-             Unable to encrypt message.",
-            exchange_name
+            "{}{}{}",
+            "Clipboard text was: `".green(),
+            String::from_utf8_lossy(&message).green().bold(),
+            "`".green()
         );
-        return;
     }
 
-    let mut clipboard = Clipboard::new().unwrap();
-    let text;
-
-    match clipboard.get_text() {
-        Ok(txt) => {
-            text = txt;
+    let several_exchanges = exchange_names.len() > 1;
+    let encrypted = match several_exchanges {
+        false => encrypt_for_exchange(exchange_names[0].clone(), &message),
+        true => encrypt_for_exchanges(exchange_names, &message),
+    };
+    let encoded_msg = match encrypted {
+        Ok((encoded_msg, SignatureStatus::Unsigned)) if several_exchanges => {
             println!(
-                "{}{}{}",
-                "Clipboard text was: `".green(),
-                text.green().bold(),
-                "`".green()
+                "{}",
+                "Warning: messages encrypted for several Key Exchanges are sent unsigned.".yellow()
+            );
+            encoded_msg
+        }
+        Ok((encoded_msg, SignatureStatus::Unsigned)) => {
+            println!(
+                "{}",
+                "Warning: this exchange has no Signing Key, the message \
+                 will be sent unsigned. Use `Send Public Key` to create one."
+                    .yellow()
             );
+            encoded_msg
         }
+        Ok((encoded_msg, _)) => encoded_msg,
         Err(e) => {
-            println!("{}", e);
+            println!("Error: {}. Unable to encrypt message.", e);
             return;
         }
-    }
+    };
 
-    let (encrypted_msg, nonce) = encrypt_message(text.as_bytes(), &encryption_key);
-    let encoded_msg = encrypted_to_str(encrypted_msg, nonce);
+    let sink = match source {
+        MessageSource::Clipboard => MessageSink::Clipboard,
+        _ => match select_message_sink(
+            "Where do you want to write the encrypted message?",
+            vec!["Clipboard", "File"],
+        ) {
+            Ok(sink) => sink,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        },
+    };
 
-    match clipboard.set_text(encoded_msg.clone()) {
-        Ok(_) => println!(
+    match write_message(&sink, encoded_msg.as_bytes()) {
+        Ok(_) if sink == MessageSink::Clipboard => println!(
             "{}{}{}{}",
             "The clipboard contents were encrypted successfully. \
              They should now be: `"
@@ -312,80 +1040,188 @@ pub fn encrypt_prompt() {
              as needed, such as sending it via messaging apps or email."
                 .green()
         ),
-        Err(e) => println!("{}", e),
+        Ok(_) => println!(
+            "{}{}{}",
+            "The encrypted message was written to `".green(),
+            sink.to_string().green().bold(),
+            "`".green()
+        ),
+        Err(e) => println!("Error: {}", e),
+    }
+
+    if encoded_msg.len() <= MAX_QR_MESSAGE_LEN {
+        show_qr_code(
+            "Show the encrypted message as a QR code?",
+            &encoded_msg,
+            false,
+        );
     }
 }
 
-pub fn decrypt_prompt() {
-    let ans = Confirm::new("Decrypt text from clipboard?")
+fn show_qr_code(question: &str, data: &str, offer_export: bool) {
+    let ans = Confirm::new(question)
         .with_default(false)
         .with_help_message(
-            "To decrypt an encrypted message, copy the \
-            encrypted text to your clipboard. Then, proceed \
-            by selecting 'yes'. The program will access the \
-            encrypted text from your clipboard, perform the \
-            decryption process, and display the decrypted \
-            message. This allows you to securely read the \
-            original content of the encrypted message. Make \
-            sure you have the correct decryption key or \
-            access to the necessary decryption method. Keep \
-            the decrypted message confidential and avoid \
-            sharing it with unauthorized individuals.",
+            "The QR code can be scanned by the other party with \
+             their phone, so it doesn't need to be typed.",
         )
         .prompt();
+    if let Ok(true) = ans {
+        match qr_to_terminal(data) {
+            Ok(code) => print!("{}", code),
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        }
+    }
+    if !offer_export {
+        return;
+    }
+
+    let path = Text::new("Save the QR code to an image?")
+        .with_help_message("Enter a path ending in .png or .svg, or leave it empty to skip.")
+        .prompt();
+    match path {
+        Ok(path) if !path.trim().is_empty() => match export_qr(data, &PathBuf::from(path.trim())) {
+            Ok(_) => println!(
+                "{}{}{}",
+                "The QR code was saved to `".green(),
+                path.trim().green().bold(),
+                "`".green()
+            ),
+            Err(e) => println!("Error: {}", e),
+        },
+        _ => {}
+    }
+}
 
-    match ans {
-        Ok(true) => (),
-        Ok(false) => {
-            println!("The text copied on the clipboard will remain unchanged");
+pub fn decrypt_prompt() {
+    let source = match select_message_source("Where is the message you want to decrypt?") {
+        Ok(source) => source,
+        Err(e) => {
+            println!("{}", e);
             return;
         }
-        Err(_) => {
-            println!("Error with questionnaire, try again later");
-            return;
+    };
+
+    if source == MessageSource::Clipboard {
+        let ans = Confirm::new("Decrypt text from clipboard?")
+            .with_default(false)
+            .with_help_message(
+                "To decrypt an encrypted message, copy the \
+                encrypted text to your clipboard. Then, proceed \
+                by selecting 'yes'. The program will access the \
+                encrypted text from your clipboard, perform the \
+                decryption process, and display the decrypted \
+                message. This allows you to securely read the \
+                original content of the encrypted message. Make \
+                sure you have the correct decryption key or \
+                access to the necessary decryption method. Keep \
+                the decrypted message confidential and avoid \
+                sharing it with unauthorized individuals.",
+            )
+            .prompt();
+
+        match ans {
+            Ok(true) => (),
+            Ok(false) => {
+                println!("The text copied on the clipboard will remain unchanged");
+                return;
+            }
+            Err(_) => {
+                println!("Error with questionnaire, try again later");
+                return;
+            }
         }
     }
 
-    let exchange_name;
-    match select_exchange() {
-        Ok(name) => exchange_name = name,
-        Err(_) => {
-            println!("Failed to get exchange, it might have been deleted");
+    let text = match read_message(&source).map(String::from_utf8) {
+        Ok(Ok(txt)) => txt,
+        Ok(Err(_)) => {
+            println!("Error: the encrypted message is not valid text");
             return;
         }
-    }
-
-    let encryption_key = get_exchange_encryption_key(exchange_name.clone());
-    if encryption_key == [0u8; 32] {
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    if source == MessageSource::Clipboard {
         println!(
-            "Error: Key Exchange was not completed for `{}`. \
-             Unable to decrypt message.",
-            exchange_name
+            "{}{}{}",
+            "Clipboard text was: `".green(),
+            text.green().bold(),
+            "`".green()
         );
-        return;
     }
 
-    let mut clipboard = Clipboard::new().unwrap();
-    let text;
+    let (exchange_name, decrypted) = match decrypt_from_any_exchange(text) {
+        Ok(found) => found,
+        Err(e) => {
+            println!("Error: {}. Unable to decrypt message.", e);
+            return;
+        }
+    };
+    println!(
+        "{}{}{}",
+        "The message comes from `".green(),
+        exchange_name.green().bold(),
+        "`.".green()
+    );
+    print_verification_status(&exchange_name);
 
-    match clipboard.get_text() {
-        Ok(txt) => {
-            text = txt;
+    let decrypted_msg = match decrypted {
+        decrypted if decrypted.key_rotated => {
+            print_signature_status(&decrypted.signature, &exchange_name);
             println!(
                 "{}{}{}",
-                "Clipboard text was: `".green(),
-                text.green().bold(),
-                "`".green()
+                "The other party rotated their keys. The Key Exchange `".green(),
+                exchange_name.green().bold(),
+                "` now uses their new Public Key.".green()
             );
+            return;
         }
-        Err(e) => {
-            println!("{}", e);
+        decrypted if decrypted.sender_key.is_some() => {
+            print_signature_status(&decrypted.signature, &exchange_name);
+            if let Some(update) = &decrypted.sender_key {
+                print_sender_key_update(update);
+            }
             return;
         }
-    }
+        decrypted => {
+            print_signature_status(&decrypted.signature, &exchange_name);
+            print_message_order(&decrypted.order);
+            decrypted.message
+        }
+    };
+
+    let sink = match source {
+        MessageSource::Clipboard => MessageSink::Stdout,
+        _ => match select_message_sink(
+            "Where do you want to write the decrypted message?",
+            vec!["Terminal", "File"],
+        ) {
+            Ok(sink) => sink,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        },
+    };
 
-    let (decoded_msg, decoded_nonce) = str_to_encrypted(text);
-    let decrypted_msg = decrypt_message(decoded_msg, &decoded_nonce, &encryption_key);
+    if let MessageSink::File(_) = sink {
+        match write_message(&sink, &decrypted_msg) {
+            Ok(_) => println!(
+                "{}{}{}",
+                "The decrypted message was written to `".green(),
+                sink.to_string().green().bold(),
+                "`".green()
+            ),
+            Err(e) => println!("Error: {}", e),
+        }
+        return;
+    }
 
     match std::str::from_utf8(&decrypted_msg) {
         Ok(msg) => {
@@ -396,24 +1232,483 @@ pub fn decrypt_prompt() {
                 msg.green().bold()
             );
         }
-        Err(e) => println!(
-            "Error converting the decrypted msg to UTF-8. \
-             This might have been caused by wrong encryption \
-             keys. The error was: {}",
-            e
+        Err(_) => println!(
+            "The decrypted message is not text, it might be a file. \
+             Decrypt it again and write it to a file instead."
         ),
     }
 }
 
-pub fn delete_key_exchange() {
-    let exchange_name;
-    match select_exchange() {
-        Ok(name) => exchange_name = name,
+fn print_sender_key_update(update: &SenderKeyUpdate) {
+    match update {
+        SenderKeyUpdate::Stored(group) => println!(
+            "{}{}{}",
+            "The sender key of the other party for the Group Channel `".green(),
+            group.green().bold(),
+            "` was stored.".green()
+        ),
+        SenderKeyUpdate::Outdated(group) => println!(
+            "{}",
+            format!(
+                "Warning: a newer sender key of the other party for the Group Channel \
+                 `{}` is already known, this one was ignored.",
+                group
+            )
+            .yellow()
+        ),
+        SenderKeyUpdate::NotMember(group) => println!(
+            "{}",
+            format!(
+                "Warning: the other party is not a member of the Group Channel `{}`. \
+                 Their sender key is kept until you add them with `Add Member`.",
+                group
+            )
+            .yellow()
+        ),
+    }
+}
+
+fn select_group() -> Result<String, &'static str> {
+    let mut options = match get_group_names() {
+        Ok(options) => options,
+        Err(e) => {
+            println!("Error: {}", e);
+            return Err("Failed to read the key storage");
+        }
+    };
+    options.sort();
+    let ans = Select::new("Please select a Group Channel:", options).prompt();
+
+    match ans {
+        Ok(choice) => Ok(choice),
+        Err(_) => Err("There was an error, please try again"),
+    }
+}
+
+fn print_sender_key_messages(messages: Vec<(String, String)>) {
+    println!(
+        "{}",
+        "Send each member the message below, they decrypt it with `Decrypt Message` \
+         to receive your sender key."
+            .green()
+    );
+    for (member, encoded_msg) in messages {
+        println!(
+            "{}{}{}\n{}",
+            "For `".green(),
+            member.green().bold(),
+            "`:".green(),
+            encoded_msg.green().bold()
+        );
+    }
+}
+
+fn create_group_prompt() -> Result<Vec<(String, String)>, &'static str> {
+    let group_name = match Text::new("Please enter a name for the Group Channel:")
+        .with_placeholder("Team")
+        .prompt()
+    {
+        Ok(name) => name,
+        Err(_) => return Err("An error happened when asking for the group name."),
+    };
+
+    let exchanges = match get_key_exchange_names() {
+        Ok(exchanges) => exchanges,
+        Err(e) => {
+            println!("Error: {}", e);
+            return Err("Failed to read the key storage");
+        }
+    };
+    let members = match MultiSelect::new("Please select the members of the group:", exchanges)
+        .with_help_message(
+            "Members are the Key Exchanges you completed with them. Your \
+             sender key is sent to each of them over their exchange.",
+        )
+        .with_validator(|members: &[ListOption<&String>]| match members.is_empty() {
+            true => Ok(Validation::Invalid("Select at least one member.".into())),
+            false => Ok(Validation::Valid),
+        })
+        .prompt()
+    {
+        Ok(members) => members,
+        Err(_) => return Err("There was an error, please try again"),
+    };
+
+    create_group(group_name, members).map_err(|e| {
+        println!("Error: {}", e);
+        "The Group Channel was not created."
+    })
+}
+
+fn encrypt_group_prompt(group_name: String) -> Result<(), &'static str> {
+    let source = select_message_source("Where is the message you want to encrypt?")?;
+    let message = read_message(&source).map(Zeroizing::new).map_err(|e| {
+        println!("Error: {}", e);
+        "Unable to read the message."
+    })?;
+    let encoded_msg = encrypt_for_group(group_name, &message).map_err(|e| {
+        println!("Error: {}", e);
+        "Unable to encrypt message."
+    })?;
+    let sink = select_message_sink(
+        "Where do you want to write the encrypted message?",
+        vec!["Clipboard", "File"],
+    )?;
+
+    match write_message(&sink, encoded_msg.as_bytes()) {
+        Ok(_) => println!(
+            "{}{}{}",
+            "The encrypted message was written to `".green(),
+            sink.to_string().green().bold(),
+            "`, every member can decrypt it.".green()
+        ),
+        Err(e) => println!("Error: {}", e),
+    }
+    Ok(())
+}
+
+fn decrypt_group_prompt(group_name: String) -> Result<(), &'static str> {
+    let source = select_message_source("Where is the message you want to decrypt?")?;
+    let text = match read_message(&source).map(String::from_utf8) {
+        Ok(Ok(text)) => text,
+        Ok(Err(_)) => return Err("Error: the encrypted message is not valid text"),
+        Err(e) => {
+            println!("Error: {}", e);
+            return Err("Unable to read the message.");
+        }
+    };
+    let decrypted = decrypt_for_group(group_name, text).map_err(|e| {
+        println!("Error: {}", e);
+        "Unable to decrypt message."
+    })?;
+
+    println!(
+        "{}{}{}",
+        "The message was sent by `".green(),
+        decrypted.sender.green().bold(),
+        "`.".green()
+    );
+    print_message_order(&decrypted.order);
+    match std::str::from_utf8(&decrypted.message) {
+        Ok(msg) => println!(
+            "{}{}",
+            "The encrypted message was: ".green(),
+            msg.green().bold()
+        ),
+        Err(_) => println!("Error: the decrypted message is not valid text"),
+    }
+    Ok(())
+}
+
+fn export_backup_prompt() -> Result<(), &'static str> {
+    let ans = Select::new(
+        "Which Key Exchanges do you want to back up?",
+        vec!["All Key Exchanges", "Choose Key Exchanges"],
+    )
+    .with_help_message("Group Channels are backed up along with all their members.")
+    .prompt();
+    let names = match ans {
+        Ok("All Key Exchanges") => vec![],
+        Ok(_) => select_exchanges()?,
+        Err(_) => return Err("There was an error, please try again"),
+    };
+
+    let output = file_path_prompt("Please enter the path of the backup file:")?;
+    let passphrase = Zeroizing::new(backup_passphrase_prompt(true)?);
+
+    let backup = match export_backup(&names, &passphrase) {
+        Ok(backup) => backup,
+        Err(e) => {
+            println!("Error: {}", e);
+            return Err("Failed to back up the key storage.");
+        }
+    };
+    if let Err(e) = write_atomically(&output, backup.as_bytes()) {
+        println!("Error: {}", e);
+        return Err("Failed to write the backup file.");
+    }
+
+    println!(
+        "{}{}{}",
+        "The backup was written to `".green(),
+        output.display().to_string().green().bold(),
+        "`. Keep it, and its passphrase, somewhere safe!".green()
+    );
+    Ok(())
+}
+
+fn import_backup_prompt() -> Result<(), &'static str> {
+    let input = file_path_prompt("Please enter the path of the backup file:")?;
+    let contents = match fs::read_to_string(&input) {
+        Ok(contents) => contents,
+        Err(e) => {
+            println!("Error: {}", e);
+            return Err("Failed to read the backup file.");
+        }
+    };
+    let passphrase = Zeroizing::new(backup_passphrase_prompt(false)?);
+
+    let ans = Select::new(
+        "What should be done with Key Exchanges you already have?",
+        vec![
+            "Skip them, keep mine",
+            "Overwrite mine",
+            "Keep both, under a new name",
+        ],
+    )
+    .prompt();
+    let conflict = match ans {
+        Ok("Skip them, keep mine") => ImportConflict::Skip,
+        Ok("Overwrite mine") => ImportConflict::Overwrite,
+        Ok(_) => ImportConflict::Merge,
+        Err(_) => return Err("There was an error, please try again"),
+    };
+
+    match import_backup(&contents, &passphrase, conflict) {
+        Ok(summary) => {
+            println!("{}", summary.to_string().green());
+            if summary.restored_any() {
+                println!("{}", RESTORED_STATE_WARNING.yellow());
+            }
+            Ok(())
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+            Err("Failed to import the backup.")
+        }
+    }
+}
+
+pub fn backup_prompt() {
+    let ans = Select::new(
+        "Please select an action to perform:",
+        vec!["Export Backup", "Import Backup", "Cancel"],
+    )
+    .with_help_message(
+        "A backup holds your Key Exchanges, encrypted with a passphrase, so \
+         you don't have to redo them with every contact if you lose this \
+         computer.",
+    )
+    .prompt();
+
+    let result = match ans {
+        Ok("Export Backup") => export_backup_prompt(),
+        Ok("Import Backup") => import_backup_prompt(),
+        Ok(_) => return,
+        Err(_) => Err("There was an error, please try again"),
+    };
+    if let Err(e) = result {
+        println!("{}", e);
+    }
+}
+
+pub fn group_channels_prompt() {
+    let options = vec![
+        "Create Group",
+        "Add Member",
+        "Remove Member",
+        "Share Sender Key",
+        "Encrypt Message",
+        "Decrypt Message",
+        "Delete Group",
+        "Cancel",
+    ];
+    let ans = Select::new(
+        "Please select an action to perform on a Group Channel:",
+        options,
+    )
+    .with_help_message(
+        "A Group Channel encrypts a message once for all its members. Each \
+             member has a sender key, shared with the others over the Key \
+             Exchanges you completed with them.",
+    )
+    .prompt();
+
+    let action = match ans {
+        Ok("Cancel") => return,
+        Ok(action) => action,
+        Err(_) => {
+            println!("There was an error, please try again");
+            return;
+        }
+    };
+    if action == "Create Group" {
+        match create_group_prompt() {
+            Ok(messages) => print_sender_key_messages(messages),
+            Err(e) => println!("{}", e),
+        }
+        return;
+    }
+
+    let group_name = match select_group() {
+        Ok(name) => name,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let result = match action {
+        "Add Member" => select_exchange().and_then(|member| {
+            add_member(group_name, member).map_err(|e| {
+                println!("Error: {}", e);
+                "The member was not added."
+            })
+        }),
+        "Remove Member" => {
+            let members = match get_group(group_name.clone()) {
+                Ok(group) => group.members,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+            Select::new("Please select the member to remove:", members)
+                .prompt()
+                .map_err(|_| "There was an error, please try again")
+                .and_then(|member| {
+                    remove_member(group_name, member).map_err(|e| {
+                        println!("Error: {}", e);
+                        "The member was not removed."
+                    })
+                })
+        }
+        "Share Sender Key" => share_sender_key(group_name).map_err(|e| {
+            println!("Error: {}", e);
+            "Unable to share your sender key."
+        }),
+        "Encrypt Message" => {
+            if let Err(e) = encrypt_group_prompt(group_name) {
+                println!("{}", e);
+            }
+            return;
+        }
+        "Decrypt Message" => {
+            if let Err(e) = decrypt_group_prompt(group_name) {
+                println!("{}", e);
+            }
+            return;
+        }
+        _ => {
+            let confirm = Confirm::new(&format!("Delete the Group Channel `{}`?", group_name))
+                .with_default(false)
+                .prompt();
+            if let Ok(true) = confirm {
+                match delete_group(group_name) {
+                    Ok(_) => println!("{}", "The Group Channel was deleted.".green()),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            return;
+        }
+    };
+
+    match result {
+        Ok(messages) => print_sender_key_messages(messages),
+        Err(e) => println!("{}", e),
+    }
+}
+
+pub fn encrypt_file_prompt() {
+    let exchange_name = match select_exchange() {
+        Ok(name) => name,
+        Err(_) => {
+            println!("Failed to get exchange, it might have been deleted");
+            return;
+        }
+    };
+
+    let encryption_key = match get_exchange_send_key(exchange_name) {
+        Ok(key) => key,
+        Err(e) => {
+            println!("Error: {}. Unable to encrypt file.", e);
+            return;
+        }
+    };
+
+    let input = match file_path_prompt("Please enter the path of the file to encrypt:") {
+        Ok(path) => path,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let mut default_output = input.clone().into_os_string();
+    default_output.push(".enc");
+    let output = match Text::new("Please enter the path of the encrypted file:")
+        .with_default(&default_output.to_string_lossy())
+        .prompt()
+    {
+        Ok(path) => PathBuf::from(path.trim()),
+        Err(_) => {
+            println!("An error happened when asking for the file path.");
+            return;
+        }
+    };
+
+    match encrypt_file(&input, &output, &encryption_key) {
+        Ok(_) => println!(
+            "{}{}{}",
+            "The file was encrypted successfully to `".green(),
+            output.display().to_string().green().bold(),
+            "`. You can now send it to the other party, they can \
+             decrypt it with the `Decrypt File` option."
+                .green()
+        ),
+        Err(e) => println!("Error: {}", e),
+    }
+}
+
+pub fn decrypt_file_prompt() {
+    let exchange_name = match select_exchange() {
+        Ok(name) => name,
         Err(_) => {
             println!("Failed to get exchange, it might have been deleted");
             return;
         }
+    };
+
+    let encryption_key = match get_exchange_receive_key(exchange_name) {
+        Ok(key) => key,
+        Err(e) => {
+            println!("Error: {}. Unable to decrypt file.", e);
+            return;
+        }
+    };
+
+    let input = match file_path_prompt("Please enter the path of the file to decrypt:") {
+        Ok(path) => path,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let output = match file_path_prompt("Please enter the path of the decrypted file:") {
+        Ok(path) => path,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    match decrypt_file(&input, &output, &encryption_key) {
+        Ok(_) => println!(
+            "{}{}{}",
+            "The file was decrypted successfully to `".green(),
+            output.display().to_string().green().bold(),
+            "`".green()
+        ),
+        Err(e) => println!("Error: {}", e),
     }
+}
+
+pub fn delete_key_exchange() {
+    let exchange_name = match select_exchange() {
+        Ok(name) => name,
+        Err(_) => {
+            println!("Failed to get exchange, it might have been deleted");
+            return;
+        }
+    };
 
     let question = "Are you sure you want to delete the Key Exchange `";
     let question_end = "` ?`";
@@ -439,9 +1734,7 @@ pub fn delete_key_exchange() {
         }
     }
 
-    let mut curr_storage = read_storage();
-    curr_storage.exchange_map.remove(&exchange_name);
-    match write_storage(curr_storage) {
+    match delete_exchange(exchange_name.clone()) {
         Ok(_) => println!(
             "{}{}{}",
             "Key Exchange `".green(),
@@ -0,0 +1,97 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use qrcode::render::{svg, unicode};
+use qrcode::{Color, QrCode};
+
+use crate::error::Error;
+use crate::key_encoding::bech32_decode_long;
+
+/// Longest encrypted message offered as a QR code, larger codes don't fit in
+/// a terminal and are hard to scan.
+pub const MAX_QR_MESSAGE_LEN: usize = 1000;
+
+const QUIET_ZONE: usize = 4;
+const PNG_MODULE_SIZE: usize = 8;
+
+fn new_qr_code(data: &str) -> Result<QrCode, Error> {
+    QrCode::new(data.as_bytes()).map_err(Error::QrCode)
+}
+
+/// Renders `data` with Unicode half-blocks, two rows of modules per line. The
+/// colors are inverted, as the blocks are drawn in the light foreground color
+/// of most terminals.
+pub fn qr_to_terminal(data: &str) -> Result<String, Error> {
+    let code = new_qr_code(data)?;
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .quiet_zone(true)
+        .build())
+}
+
+/// The data of the QR code of a key. Keys in the bech32m format are
+/// uppercased, to fit the denser alphanumeric mode of QR codes, and still
+/// decode to the same key. Keys in base64 are case sensitive, they're kept as
+/// they are.
+pub fn key_qr_data(key_str: &str) -> String {
+    match bech32_decode_long(key_str) {
+        Ok(_) => key_str.to_uppercase(),
+        Err(_) => key_str.to_string(),
+    }
+}
+
+/// Renders an encrypted message, if it's short enough to be scanned.
+pub fn message_qr_to_terminal(encoded_msg: &str) -> Result<String, Error> {
+    if encoded_msg.len() > MAX_QR_MESSAGE_LEN {
+        return Err(Error::MessageTooLongForQr(encoded_msg.len()));
+    }
+    qr_to_terminal(encoded_msg)
+}
+
+fn qr_to_png(code: &QrCode, path: &Path) -> Result<(), Error> {
+    let modules = code.width() + 2 * QUIET_ZONE;
+    let size = modules * PNG_MODULE_SIZE;
+    let colors = code.to_colors();
+
+    let mut pixels = vec![255u8; size * size];
+    for (i, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let x = (i % code.width() + QUIET_ZONE) * PNG_MODULE_SIZE;
+        let y = (i / code.width() + QUIET_ZONE) * PNG_MODULE_SIZE;
+        for row in y..y + PNG_MODULE_SIZE {
+            pixels[row * size + x..row * size + x + PNG_MODULE_SIZE].fill(0);
+        }
+    }
+
+    let writer = BufWriter::new(File::create(path).map_err(Error::MessageIo)?);
+    let mut encoder = png::Encoder::new(writer, size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut w| w.write_image_data(&pixels))
+        .map_err(|e| Error::MessageIo(e.into()))
+}
+
+/// Writes the QR code of `data` to a PNG or SVG image, chosen by the extension of `path`.
+pub fn export_qr(data: &str, path: &Path) -> Result<(), Error> {
+    let code = new_qr_code(data)?;
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "png" => qr_to_png(&code, path),
+        "svg" => {
+            let image = code.render::<svg::Color>().min_dimensions(256, 256).build();
+            fs::write(path, image).map_err(Error::MessageIo)
+        }
+        _ => Err(Error::UnsupportedImageFormat(extension)),
+    }
+}
@@ -0,0 +1,243 @@
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::error::Error;
+use crate::key_exchange::{gen_shared_secret, gen_static_kp};
+use crate::key_store_classes::MessageOrder;
+
+pub const MAX_SKIP: u32 = 1000;
+pub const RATCHET_HEADER_SIZE: usize = 32 + 4 + 4;
+
+const ROOT_LABEL: &[u8] = b"CryptoChannel ratchet root";
+const BOOTSTRAP_LABEL: &[u8] = b"CryptoChannel ratchet bootstrap chain from ";
+const MESSAGE_KEY_INFO: &[u8] = b"CryptoChannel ratchet message key";
+const CHAIN_KEY_INFO: &[u8] = b"CryptoChannel ratchet chain key";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RatchetHeader {
+    pub dh_public: [u8; 32],
+    pub previous_count: u32,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Zeroize, ZeroizeOnDrop)]
+pub struct SkippedKey {
+    pub dh_public: [u8; 32],
+    pub count: u32,
+    pub message_key: [u8; 32],
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Zeroize, ZeroizeOnDrop)]
+pub struct RatchetState {
+    pub dh_public: [u8; 32],
+    pub dh_secret: [u8; 32],
+    pub dh_remote: Option<[u8; 32]>,
+    pub root_key: [u8; 32],
+    pub send_chain: [u8; 32],
+    pub receive_chain: Option<[u8; 32]>,
+    pub send_count: u32,
+    pub receive_count: u32,
+    pub previous_send_count: u32,
+    pub skipped: Vec<SkippedKey>,
+}
+
+impl RatchetHeader {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.dh_public.to_vec();
+        bytes.extend_from_slice(&self.previous_count.to_be_bytes());
+        bytes.extend_from_slice(&self.count.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != RATCHET_HEADER_SIZE {
+            return Err(Error::BadEncoding);
+        }
+
+        let mut dh_public = [0u8; 32];
+        dh_public.copy_from_slice(&bytes[0..32]);
+        Ok(RatchetHeader {
+            dh_public,
+            previous_count: u32::from_be_bytes(bytes[32..36].try_into().unwrap()),
+            count: u32::from_be_bytes(bytes[36..40].try_into().unwrap()),
+        })
+    }
+}
+
+fn kdf_root(root_key: &[u8; 32], dh_output: [u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let hk = Hkdf::<Sha256>::new(Some(root_key), &dh_output);
+    let mut okm = [0u8; 64];
+    hk.expand(ROOT_LABEL, &mut okm)
+        .expect("64 is a valid length for Sha256 to output");
+
+    let mut new_root = [0u8; 32];
+    let mut chain_key = [0u8; 32];
+    new_root.copy_from_slice(&okm[..32]);
+    chain_key.copy_from_slice(&okm[32..]);
+    (new_root, chain_key)
+}
+
+fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let hk = Hkdf::<Sha256>::from_prk(chain_key).expect("PRK should be large enough");
+    let mut message_key = [0u8; 32];
+    let mut next_chain = [0u8; 32];
+    hk.expand(MESSAGE_KEY_INFO, &mut message_key)
+        .expect("32 is a valid length for Sha256 to output");
+    hk.expand(CHAIN_KEY_INFO, &mut next_chain)
+        .expect("32 is a valid length for Sha256 to output");
+    (next_chain, message_key)
+}
+
+fn bootstrap_chain(shared_secret: [u8; 32], sender_public: [u8; 32]) -> [u8; 32] {
+    let hk = Hkdf::<Sha256>::new(Some(ROOT_LABEL), &shared_secret);
+    let mut chain_key = [0u8; 32];
+    hk.expand(&[BOOTSTRAP_LABEL, &sender_public].concat(), &mut chain_key)
+        .expect("32 is a valid length for Sha256 to output");
+    chain_key
+}
+
+impl RatchetState {
+    /// Both parties build their session from the completed Key Exchange alone,
+    /// so either of them can send first. The party with the lowest public key
+    /// starts the ratchet, the other one sends on a chain bootstrapped from the
+    /// shared secret until it receives the first ratchet key.
+    pub fn new(
+        shared_secret: [u8; 32],
+        your_public: [u8; 32],
+        your_secret: [u8; 32],
+        other_public: [u8; 32],
+    ) -> Self {
+        if your_public < other_public {
+            let kp = gen_static_kp();
+            let (root_key, send_chain) =
+                kdf_root(&shared_secret, gen_shared_secret(other_public, kp.secret));
+
+            RatchetState {
+                dh_public: kp.public,
+                dh_secret: kp.secret,
+                dh_remote: Some(other_public),
+                root_key,
+                send_chain,
+                receive_chain: Some(bootstrap_chain(shared_secret, other_public)),
+                send_count: 0,
+                receive_count: 0,
+                previous_send_count: 0,
+                skipped: Vec::new(),
+            }
+        } else {
+            RatchetState {
+                dh_public: your_public,
+                dh_secret: your_secret,
+                dh_remote: None,
+                root_key: shared_secret,
+                send_chain: bootstrap_chain(shared_secret, your_public),
+                receive_chain: None,
+                send_count: 0,
+                receive_count: 0,
+                previous_send_count: 0,
+                skipped: Vec::new(),
+            }
+        }
+    }
+
+    pub fn next_send_key(&mut self) -> (RatchetHeader, [u8; 32]) {
+        let (next_chain, message_key) = kdf_chain(&self.send_chain);
+        let header = RatchetHeader {
+            dh_public: self.dh_public,
+            previous_count: self.previous_send_count,
+            count: self.send_count,
+        };
+
+        self.send_chain = next_chain;
+        self.send_count += 1;
+        (header, message_key)
+    }
+
+    /// Finds the key of a received message and advances the session. Callers
+    /// should work on a copy and only keep it once the message authenticated.
+    pub fn receive_key(
+        &mut self,
+        header: &RatchetHeader,
+    ) -> Result<([u8; 32], MessageOrder), Error> {
+        if let Some(index) = self
+            .skipped
+            .iter()
+            .position(|k| k.dh_public == header.dh_public && k.count == header.count)
+        {
+            let skipped = self.skipped.remove(index);
+            return Ok((skipped.message_key, MessageOrder::OutOfOrder));
+        }
+
+        let mut order = MessageOrder::InOrder;
+        if self.dh_remote != Some(header.dh_public) {
+            if self.skip_message_keys(header.previous_count)? {
+                order = MessageOrder::OutOfOrder;
+            }
+            self.dh_ratchet(header.dh_public);
+        }
+
+        if header.count < self.receive_count {
+            return Err(Error::MessageKeyUnavailable);
+        }
+        if self.skip_message_keys(header.count)? {
+            order = MessageOrder::OutOfOrder;
+        }
+
+        let receive_chain = self.receive_chain.ok_or(Error::MessageKeyUnavailable)?;
+        let (next_chain, message_key) = kdf_chain(&receive_chain);
+        self.receive_chain = Some(next_chain);
+        self.receive_count += 1;
+
+        Ok((message_key, order))
+    }
+
+    fn skip_message_keys(&mut self, until: u32) -> Result<bool, Error> {
+        let (Some(mut receive_chain), Some(dh_remote)) = (self.receive_chain, self.dh_remote)
+        else {
+            return Ok(false);
+        };
+        if until <= self.receive_count {
+            return Ok(false);
+        }
+        if until - self.receive_count > MAX_SKIP {
+            return Err(Error::MessageKeyUnavailable);
+        }
+
+        while self.receive_count < until {
+            let (next_chain, message_key) = kdf_chain(&receive_chain);
+            self.skipped.push(SkippedKey {
+                dh_public: dh_remote,
+                count: self.receive_count,
+                message_key,
+            });
+            receive_chain = next_chain;
+            self.receive_count += 1;
+        }
+        self.receive_chain = Some(receive_chain);
+
+        // Old skipped keys are dropped, they would otherwise pile up forever.
+        let excess = self.skipped.len().saturating_sub(MAX_SKIP as usize);
+        self.skipped.drain(..excess);
+        Ok(true)
+    }
+
+    fn dh_ratchet(&mut self, dh_remote: [u8; 32]) {
+        self.previous_send_count = self.send_count;
+        self.send_count = 0;
+        self.receive_count = 0;
+        self.dh_remote = Some(dh_remote);
+
+        let (root_key, receive_chain) =
+            kdf_root(&self.root_key, gen_shared_secret(dh_remote, self.dh_secret));
+        self.receive_chain = Some(receive_chain);
+
+        let kp = gen_static_kp();
+        let (root_key, send_chain) = kdf_root(&root_key, gen_shared_secret(dh_remote, kp.secret));
+        self.dh_public = kp.public;
+        self.dh_secret = kp.secret;
+        self.root_key = root_key;
+        self.send_chain = send_chain;
+    }
+}
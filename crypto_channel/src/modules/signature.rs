@@ -0,0 +1,36 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand_core::{OsRng, RngCore};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::error::Error;
+
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct SigningKeyPair {
+    pub public: [u8; 32],
+    pub secret: [u8; 32],
+}
+
+pub fn gen_signing_kp() -> SigningKeyPair {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let signing_key = SigningKey::from_bytes(&secret);
+
+    SigningKeyPair {
+        public: signing_key.verifying_key().to_bytes(),
+        secret,
+    }
+}
+
+pub fn sign_message(message: &[u8], secret: &[u8; 32]) -> [u8; 64] {
+    let signing_key = SigningKey::from_bytes(secret);
+    signing_key.sign(message).to_bytes()
+}
+
+pub fn verify_signature(message: &[u8], signature: &[u8], public: &[u8; 32]) -> Result<(), Error> {
+    let verifying_key = VerifyingKey::from_bytes(public).map_err(|_| Error::BadEncoding)?;
+    let signature = Signature::from_slice(signature).map_err(|_| Error::BadEncoding)?;
+
+    verifying_key
+        .verify_strict(message, &signature)
+        .map_err(|_| Error::InvalidSignature)
+}
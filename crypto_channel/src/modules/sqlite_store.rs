@@ -0,0 +1,180 @@
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+use crate::error::Error;
+use crate::key_store_classes::KeyStorage;
+use crate::storage_backend::KeyStore;
+use crate::storage_file::{
+    create_private_file, lock_storage_file, migration_backup_path, remove_migration_backups,
+    StorageLock,
+};
+use crate::storage_migration::{parse_storage, CURRENT_SCHEMA_VERSION};
+
+/// The storage in a SQLite database, with one row per Key Exchange and per
+/// Group Channel, so saving a change only rewrites the rows that changed.
+/// Rows hold the same JSON as the file storage, and go through the same
+/// migrations.
+pub struct SqliteStore {
+    path: PathBuf,
+    connection: Mutex<Connection>,
+}
+
+fn db_error(e: rusqlite::Error) -> Error {
+    Error::StorageIo(io::Error::other(e))
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        // SQLite gives its journal the permissions of the database.
+        create_private_file(path).map_err(Error::StorageIo)?;
+        let connection = Connection::open(path).map_err(db_error)?;
+        // Deleted rows are overwritten with zeros, instead of staying in free
+        // pages, so deleted exchanges and used message keys are really gone.
+        connection
+            .execute_batch(
+                "PRAGMA secure_delete = ON;
+                 CREATE TABLE IF NOT EXISTS metadata (key TEXT PRIMARY KEY, value INTEGER NOT NULL);
+                 CREATE TABLE IF NOT EXISTS exchanges (name TEXT PRIMARY KEY, data TEXT NOT NULL);
+                 CREATE TABLE IF NOT EXISTS groups (name TEXT PRIMARY KEY, data TEXT NOT NULL);",
+            )
+            .map_err(db_error)?;
+        connection
+            .execute(
+                "INSERT OR IGNORE INTO metadata (key, value) VALUES ('schema_version', ?1)",
+                params![CURRENT_SCHEMA_VERSION],
+            )
+            .map_err(db_error)?;
+
+        Ok(SqliteStore {
+            path: path.to_path_buf(),
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+// Appends the rows of `table` to `json`, as an object keyed by their name.
+fn append_rows(connection: &Connection, table: &str, json: &mut String) -> Result<(), Error> {
+    let mut statement = connection
+        .prepare(&format!("SELECT name, data FROM {} ORDER BY name", table))
+        .map_err(db_error)?;
+    let mut rows = statement.query([]).map_err(db_error)?;
+
+    json.push('{');
+    let mut first = true;
+    while let Some(row) = rows.next().map_err(db_error)? {
+        let name: String = row.get(0).map_err(db_error)?;
+        let data = Zeroizing::new(row.get::<_, String>(1).map_err(db_error)?);
+        if !first {
+            json.push(',');
+        }
+        first = false;
+        json.push_str(&serde_json::to_string(&name).map_err(Error::StorageParse)?);
+        json.push(':');
+        json.push_str(&data);
+    }
+    json.push('}');
+    Ok(())
+}
+
+// Writes the rows whose JSON changed, and deletes the ones that are gone.
+fn update_rows<T: Serialize>(
+    connection: &Connection,
+    table: &str,
+    entries: &HashMap<String, T>,
+) -> Result<(), Error> {
+    let mut upsert = connection
+        .prepare(&format!(
+            "INSERT INTO {0} (name, data) VALUES (?1, ?2) \
+             ON CONFLICT (name) DO UPDATE SET data = excluded.data \
+             WHERE {0}.data != excluded.data",
+            table
+        ))
+        .map_err(db_error)?;
+    for (name, entry) in entries {
+        let data = Zeroizing::new(serde_json::to_string(entry).map_err(Error::StorageParse)?);
+        upsert
+            .execute(params![name, data.as_str()])
+            .map_err(db_error)?;
+    }
+
+    let names: Vec<String> = connection
+        .prepare(&format!("SELECT name FROM {}", table))
+        .map_err(db_error)?
+        .query_map([], |row| row.get(0))
+        .map_err(db_error)?
+        .collect::<Result<_, _>>()
+        .map_err(db_error)?;
+    for name in names.iter().filter(|name| !entries.contains_key(*name)) {
+        connection
+            .execute(&format!("DELETE FROM {} WHERE name = ?1", table), [name])
+            .map_err(db_error)?;
+    }
+    Ok(())
+}
+
+impl KeyStore for SqliteStore {
+    fn load(&self) -> Result<KeyStorage, Error> {
+        let connection = self.connection.lock().unwrap();
+        let version: u32 = connection
+            .query_row(
+                "SELECT value FROM metadata WHERE key = 'schema_version'",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(db_error)?;
+
+        let mut json = Zeroizing::new(format!(
+            "{{\"schema_version\":{},\"exchange_map\":",
+            version
+        ));
+        append_rows(&connection, "exchanges", &mut json)?;
+        json.push_str(",\"group_map\":");
+        append_rows(&connection, "groups", &mut json)?;
+        json.push('}');
+        drop(connection);
+
+        let (storage, version) = parse_storage(json.as_bytes())?;
+        if version < CURRENT_SCHEMA_VERSION {
+            let _lock = self.lock()?;
+            let backup = migration_backup_path(&self.path, version);
+            fs::copy(&self.path, &backup).map_err(Error::StorageIo)?;
+            eprintln!(
+                "The previous key storage was backed up at: {}",
+                backup.display()
+            );
+            self.save(&storage)?;
+        }
+        Ok(storage)
+    }
+
+    fn save(&self, storage: &KeyStorage) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(db_error)?;
+
+        update_rows(&transaction, "exchanges", &storage.exchange_map)?;
+        update_rows(&transaction, "groups", &storage.group_map)?;
+        transaction
+            .execute(
+                "UPDATE metadata SET value = ?1 WHERE key = 'schema_version'",
+                params![CURRENT_SCHEMA_VERSION],
+            )
+            .map_err(db_error)?;
+        transaction.commit().map_err(db_error)
+    }
+
+    // Deleted rows are already overwritten in the database.
+    fn save_erasing(&self, storage: &KeyStorage) -> Result<(), Error> {
+        self.save(storage)?;
+        remove_migration_backups(&self.path).map_err(Error::StorageIo)
+    }
+
+    fn lock(&self) -> Result<StorageLock, Error> {
+        lock_storage_file(&self.path)
+    }
+}
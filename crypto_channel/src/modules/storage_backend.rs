@@ -0,0 +1,204 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use zeroize::Zeroizing;
+
+use crate::config::{ConfigSettings, StorageBackend};
+use crate::error::Error;
+use crate::key_store::get_storage_key;
+use crate::key_store_classes::KeyStorage;
+#[cfg(feature = "sqlite")]
+use crate::sqlite_store::SqliteStore;
+use crate::storage_encryption::{open_storage_with_key, seal_storage_with_key, SealedStorage};
+use crate::storage_file::{
+    backup_path, lock_storage_file, migration_backup_path, remove_migration_backups,
+    replace_storage_file, replace_storage_file_and_backup, write_atomically, StorageLock,
+};
+use crate::storage_migration::{parse_storage, CURRENT_SCHEMA_VERSION};
+
+/// Where the Key Exchanges and Group Channels are kept. Changes read the whole
+/// storage and save it back, while holding the lock.
+pub trait KeyStore: Send + Sync {
+    /// Reads the storage, upgrading it first if it has an older layout.
+    fn load(&self) -> Result<KeyStorage, Error>;
+
+    /// Replaces the storage with `storage`.
+    fn save(&self, storage: &KeyStorage) -> Result<(), Error>;
+
+    /// Replaces the storage with `storage`, along with the copies kept next to
+    /// it, so none of them keeps the keys that were deleted.
+    fn save_erasing(&self, storage: &KeyStorage) -> Result<(), Error> {
+        self.save(storage)
+    }
+
+    /// Keeps other instances of the tool from changing the storage until the
+    /// returned guard is dropped. Taking it again while it's held by this
+    /// process doesn't wait.
+    fn lock(&self) -> Result<StorageLock, Error>;
+}
+
+/// Opens the key storage with the backend chosen in the config.
+pub fn open_key_store(cfg: &ConfigSettings) -> Result<Arc<dyn KeyStore>, Error> {
+    match cfg.storage_backend {
+        StorageBackend::Json => Ok(Arc::new(JsonFileStore::new(
+            cfg.storage_path.clone(),
+            cfg.encrypt_storage,
+        ))),
+        StorageBackend::Sqlite if cfg.encrypt_storage => Err(Error::UnsupportedStorageEncryption),
+        StorageBackend::Sqlite => open_sqlite_store(&cfg.storage_path),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn open_sqlite_store(path: &Path) -> Result<Arc<dyn KeyStore>, Error> {
+    Ok(Arc::new(SqliteStore::open(path)?))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn open_sqlite_store(_path: &Path) -> Result<Arc<dyn KeyStore>, Error> {
+    Err(Error::SqliteUnavailable)
+}
+
+/// The whole storage in a single JSON file, sealed with the passphrase of the
+/// storage when `encrypted` is set.
+pub struct JsonFileStore {
+    path: PathBuf,
+    encrypted: bool,
+}
+
+impl JsonFileStore {
+    pub fn new(path: PathBuf, encrypted: bool) -> Self {
+        JsonFileStore { path, encrypted }
+    }
+
+    // The JSON holds every key in clear, so it's wiped once written.
+    fn encode(&self, storage: &KeyStorage) -> Result<Zeroizing<String>, Error> {
+        let j = Zeroizing::new(serde_json::to_string(storage).map_err(Error::StorageParse)?);
+        if !self.encrypted {
+            return Ok(j);
+        }
+
+        let sealed = seal_storage_with_key(j.as_bytes(), &get_storage_key(None)?)?;
+        serde_json::to_string(&sealed)
+            .map(Zeroizing::new)
+            .map_err(Error::StorageParse)
+    }
+
+    fn decode(&self, contents: &str) -> Result<(KeyStorage, u32), Error> {
+        if !self.encrypted {
+            return parse_storage(contents.as_bytes());
+        }
+
+        let sealed: SealedStorage = serde_json::from_str(contents).map_err(Error::StorageParse)?;
+        let plaintext = open_storage_with_key(&sealed, &get_storage_key(Some(&sealed))?)?;
+        parse_storage(&plaintext)
+    }
+
+    fn create(&self) -> Result<(), Error> {
+        eprintln!("Creating storage file...");
+        self.save(&KeyStorage::new())?;
+        eprintln!("Storage file create at: {}", self.path.display());
+        Ok(())
+    }
+
+    fn read(&self) -> Result<(KeyStorage, u32, Zeroizing<String>), Error> {
+        let contents = Zeroizing::new(fs::read_to_string(&self.path).map_err(Error::StorageIo)?);
+
+        let (storage, version) = self.decode(&contents).map_err(|e| match e {
+            Error::StorageParse(e) if backup_path(&self.path).exists() => {
+                Error::StorageCorrupted(e, backup_path(&self.path))
+            }
+            e => e,
+        })?;
+        Ok((storage, version, contents))
+    }
+
+    // Saves the storage with the current layout, after keeping the file as it
+    // was written by the older version of the tool.
+    fn migrate(&self) -> Result<KeyStorage, Error> {
+        let (storage, version, contents) = self.read()?;
+        if version == CURRENT_SCHEMA_VERSION {
+            return Ok(storage);
+        }
+
+        eprintln!(
+            "Upgrading the key storage to the layout version {}...",
+            CURRENT_SCHEMA_VERSION
+        );
+        let backup = migration_backup_path(&self.path, version);
+        write_atomically(&backup, contents.as_bytes()).map_err(Error::StorageIo)?;
+        eprintln!(
+            "The previous key storage was backed up at: {}",
+            backup.display()
+        );
+
+        self.save(&storage)?;
+        Ok(storage)
+    }
+}
+
+impl KeyStore for JsonFileStore {
+    fn load(&self) -> Result<KeyStorage, Error> {
+        if !self.path.exists() {
+            let _lock = self.lock()?;
+            if !self.path.exists() {
+                self.create()?;
+            }
+        }
+
+        let (storage, version, _) = self.read()?;
+        if version == CURRENT_SCHEMA_VERSION {
+            return Ok(storage);
+        }
+
+        // Read it again once locked, another instance might be upgrading it too.
+        let _lock = self.lock()?;
+        self.migrate()
+    }
+
+    fn save(&self, storage: &KeyStorage) -> Result<(), Error> {
+        let j = self.encode(storage)?;
+        replace_storage_file(&self.path, j.as_bytes()).map_err(Error::StorageIo)
+    }
+
+    fn save_erasing(&self, storage: &KeyStorage) -> Result<(), Error> {
+        let j = self.encode(storage)?;
+        replace_storage_file_and_backup(&self.path, j.as_bytes()).map_err(Error::StorageIo)?;
+        remove_migration_backups(&self.path).map_err(Error::StorageIo)
+    }
+
+    fn lock(&self) -> Result<StorageLock, Error> {
+        lock_storage_file(&self.path)
+    }
+}
+
+/// Keeps the storage in memory, so tests don't touch the real one.
+#[derive(Default)]
+pub struct MemoryStore {
+    json: Mutex<Option<Zeroizing<String>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl KeyStore for MemoryStore {
+    fn load(&self) -> Result<KeyStorage, Error> {
+        match self.json.lock().unwrap().as_ref() {
+            Some(json) => parse_storage(json.as_bytes()).map(|(storage, _)| storage),
+            None => Ok(KeyStorage::new()),
+        }
+    }
+
+    fn save(&self, storage: &KeyStorage) -> Result<(), Error> {
+        let json = serde_json::to_string(storage).map_err(Error::StorageParse)?;
+        *self.json.lock().unwrap() = Some(Zeroizing::new(json));
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<StorageLock, Error> {
+        Ok(StorageLock::unshared())
+    }
+}
@@ -0,0 +1,154 @@
+use aes_gcm::aead::{generic_array::GenericArray, Aead, AeadCore, KeyInit};
+use aes_gcm::Aes256Gcm;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose, Engine as _};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::error::Error;
+
+pub const SEALED_STORAGE_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SealedStorage {
+    pub version: u8,
+    pub kdf: KdfParams,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+impl KdfParams {
+    pub fn new() -> Self {
+        KdfParams {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn derive_storage_key(
+    passphrase: &str,
+    salt: &[u8],
+    kdf: &KdfParams,
+) -> Result<Zeroizing<[u8; 32]>, Error> {
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(32))
+        .map_err(Error::KeyDerivationFailed)?;
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(Error::KeyDerivationFailed)?;
+    Ok(key)
+}
+
+/// A key derived from the storage passphrase, kept with the salt and the
+/// parameters it was derived with, so it can be used again without running
+/// Argon2id on every read and write.
+#[derive(Clone)]
+pub struct StorageKey {
+    key: Zeroizing<[u8; 32]>,
+    salt: Vec<u8>,
+    kdf: KdfParams,
+}
+
+impl StorageKey {
+    /// Derives a key with a new random salt.
+    pub fn new(passphrase: &str) -> Result<Self, Error> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let kdf = KdfParams::new();
+        let key = derive_storage_key(passphrase, &salt, &kdf)?;
+        Ok(StorageKey {
+            key,
+            salt: salt.to_vec(),
+            kdf,
+        })
+    }
+
+    /// Derives the key `sealed` was sealed with.
+    pub fn for_sealed(sealed: &SealedStorage, passphrase: &str) -> Result<Self, Error> {
+        let salt = general_purpose::STANDARD
+            .decode(&sealed.salt)
+            .map_err(|_| Error::BadEncoding)?;
+        let key = derive_storage_key(passphrase, &salt, &sealed.kdf)?;
+        Ok(StorageKey {
+            key,
+            salt,
+            kdf: sealed.kdf.clone(),
+        })
+    }
+
+    /// Whether `sealed` was sealed with a key derived like this one.
+    pub fn matches(&self, sealed: &SealedStorage) -> bool {
+        sealed.kdf == self.kdf && general_purpose::STANDARD.encode(&self.salt) == sealed.salt
+    }
+}
+
+pub fn seal_storage(plaintext: &[u8], passphrase: &str) -> Result<SealedStorage, Error> {
+    seal_storage_with_key(plaintext, &StorageKey::new(passphrase)?)
+}
+
+pub fn open_storage(sealed: &SealedStorage, passphrase: &str) -> Result<Zeroizing<Vec<u8>>, Error> {
+    if sealed.version != SEALED_STORAGE_VERSION {
+        return Err(Error::UnsupportedVersion(sealed.version));
+    }
+    open_storage_with_key(sealed, &StorageKey::for_sealed(sealed, passphrase)?)
+}
+
+// Each seal gets a new random nonce, so the key and its salt can be kept
+// across writes.
+pub fn seal_storage_with_key(plaintext: &[u8], key: &StorageKey) -> Result<SealedStorage, Error> {
+    let cipher = Aes256Gcm::new(GenericArray::from_slice(key.key.as_ref()));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| Error::EncryptionFailed)?;
+
+    Ok(SealedStorage {
+        version: SEALED_STORAGE_VERSION,
+        kdf: key.kdf.clone(),
+        salt: general_purpose::STANDARD.encode(&key.salt),
+        nonce: general_purpose::STANDARD.encode(nonce),
+        ciphertext: general_purpose::STANDARD.encode(ciphertext),
+    })
+}
+
+pub fn open_storage_with_key(
+    sealed: &SealedStorage,
+    key: &StorageKey,
+) -> Result<Zeroizing<Vec<u8>>, Error> {
+    if sealed.version != SEALED_STORAGE_VERSION {
+        return Err(Error::UnsupportedVersion(sealed.version));
+    }
+
+    let decode = |s: &str| general_purpose::STANDARD.decode(s);
+    let (nonce, ciphertext) = match (decode(&sealed.nonce), decode(&sealed.ciphertext)) {
+        (Ok(nonce), Ok(ciphertext)) => (nonce, ciphertext),
+        _ => return Err(Error::BadEncoding),
+    };
+    if nonce.len() != 12 {
+        return Err(Error::BadEncoding);
+    }
+
+    let cipher = Aes256Gcm::new(GenericArray::from_slice(key.key.as_ref()));
+    cipher
+        .decrypt(GenericArray::from_slice(&nonce), ciphertext.as_ref())
+        .map(Zeroizing::new)
+        .map_err(|_| Error::WrongPassphrase)
+}
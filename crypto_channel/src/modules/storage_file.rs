@@ -0,0 +1,184 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use zeroize::Zeroizing;
+
+use crate::error::Error;
+use crate::storage_migration::CURRENT_SCHEMA_VERSION;
+
+/// The lock is held by the whole process, `depth` counts the guards that are
+/// still alive so nested read-modify-write cycles don't wait on themselves.
+struct HeldLock {
+    depth: usize,
+    file: Option<File>,
+}
+
+static HELD_LOCK: Mutex<HeldLock> = Mutex::new(HeldLock {
+    depth: 0,
+    file: None,
+});
+
+/// Advisory lock on the key storage, released when dropped. Other instances
+/// of the tool wait for it before changing the storage.
+pub struct StorageLock {
+    holds_file: bool,
+}
+
+impl StorageLock {
+    /// Lock of a key storage no other process can open, like the in-memory one.
+    pub fn unshared() -> Self {
+        StorageLock { holds_file: false }
+    }
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Where the previous version of the storage is kept.
+pub fn backup_path(storage_path: &Path) -> PathBuf {
+    sibling_path(storage_path, ".bak")
+}
+
+/// Where the storage is kept as it was before being upgraded from the layout
+/// `version`. Unlike the rolling backup, it's never replaced.
+pub fn migration_backup_path(storage_path: &Path, version: u32) -> PathBuf {
+    sibling_path(storage_path, &format!(".v{}.bak", version))
+}
+
+/// Opens a file only you can read and write, as every file next to the
+/// storage may hold keys.
+fn private_file_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options
+}
+
+/// Creates an empty file only you can read and write, unless it exists.
+pub fn create_private_file(path: &Path) -> io::Result<()> {
+    private_file_options()
+        .truncate(false)
+        .open(path)
+        .map(|_| ())
+}
+
+/// Creates an empty file only you can read and write at `path`, in place of
+/// any file already there.
+pub fn recreate_private_file(path: &Path) -> io::Result<File> {
+    // An existing file keeps its permissions when opened, start afresh.
+    let _ = fs::remove_file(path);
+    private_file_options().truncate(true).open(path)
+}
+
+pub fn lock_storage_file(storage_path: &Path) -> Result<StorageLock, Error> {
+    let mut held = HELD_LOCK.lock().unwrap();
+    if held.depth == 0 {
+        let file = private_file_options()
+            .truncate(false)
+            .open(sibling_path(storage_path, ".lock"))
+            .map_err(Error::StorageIo)?;
+
+        if file.try_lock().is_err() {
+            eprintln!("Waiting for another CryptoChannel to finish with the key storage...");
+            file.lock().map_err(Error::StorageIo)?;
+        }
+        held.file = Some(file);
+    }
+    held.depth += 1;
+    Ok(StorageLock { holds_file: true })
+}
+
+impl Drop for StorageLock {
+    fn drop(&mut self) {
+        if !self.holds_file {
+            return;
+        }
+        let mut held = HELD_LOCK.lock().unwrap();
+        held.depth -= 1;
+        if held.depth == 0 {
+            // Closing the file releases the lock.
+            held.file = None;
+        }
+    }
+}
+
+/// Writes to a temporary file next to `path`, then renames it over `path`, so
+/// a crash leaves either the old or the new contents, never a mix.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = sibling_path(path, ".tmp");
+    let mut file = recreate_private_file(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temp_path, path)?;
+    // The rename itself is only durable once the directory is synced, which
+    // is not possible on every platform.
+    if let Some(dir) = path.parent().and_then(|dir| File::open(dir).ok()) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Replaces the storage file, after copying its current version to the backup.
+pub fn replace_storage_file(storage_path: &Path, contents: &[u8]) -> io::Result<()> {
+    if storage_path.exists() {
+        let previous = Zeroizing::new(fs::read(storage_path)?);
+        write_atomically(&backup_path(storage_path), &previous)?;
+    }
+    write_atomically(storage_path, contents)
+}
+
+/// Replaces the storage file and its backup with the same contents, so the
+/// backup doesn't keep what was just deleted from the storage.
+pub fn replace_storage_file_and_backup(storage_path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_atomically(storage_path, contents)?;
+    write_atomically(&backup_path(storage_path), contents)
+}
+
+/// Removes the copies of the storage kept before upgrading it from an older
+/// layout.
+pub fn remove_migration_backups(storage_path: &Path) -> io::Result<()> {
+    for version in 0..CURRENT_SCHEMA_VERSION {
+        match fs::remove_file(migration_backup_path(storage_path, version)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Tells whether other users can access the file or directory at `path`.
+#[cfg(unix)]
+pub fn is_exposed(path: &Path) -> io::Result<bool> {
+    Ok(fs::metadata(path)?.permissions().mode() & 0o077 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn is_exposed(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Only lets you access the file or directory at `path`.
+#[cfg(unix)]
+pub fn restrict_permissions(path: &Path) -> io::Result<()> {
+    let mode = match fs::metadata(path)?.is_dir() {
+        true => 0o700,
+        false => 0o600,
+    };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn restrict_permissions(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::error::Error;
+use crate::key_exchange::KDF_V1;
+use crate::key_store_classes::KeyStorage;
+
+/// Layout version of the key storage, saved in its `schema_version` field.
+/// Storage files written before the field existed are version 0.
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+/// Upgrades a document from the version at its index to the next one. Add a
+/// step here, and bump `CURRENT_SCHEMA_VERSION`, whenever a change to the
+/// storage can't be read from the previous layout.
+const MIGRATIONS: [fn(&mut Map<String, Value>); CURRENT_SCHEMA_VERSION as usize] = [v0_to_v1];
+
+#[derive(Deserialize)]
+struct SchemaVersion {
+    #[serde(default)]
+    schema_version: u32,
+}
+
+/// Reads the layout version of a key storage document, without the keys.
+pub fn schema_version(json: &[u8]) -> Result<u32, Error> {
+    let version: SchemaVersion = serde_json::from_slice(json).map_err(Error::StorageParse)?;
+    Ok(version.schema_version)
+}
+
+/// Parses a key storage document written with any layout version, and
+/// returns it with the version it was written with.
+pub fn parse_storage(json: &[u8]) -> Result<(KeyStorage, u32), Error> {
+    let version = schema_version(json)?;
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(Error::UnsupportedStorageVersion(version));
+    }
+    if version == CURRENT_SCHEMA_VERSION {
+        let storage = serde_json::from_slice(json).map_err(Error::StorageParse)?;
+        return Ok((storage, version));
+    }
+
+    let mut document: Map<String, Value> =
+        serde_json::from_slice(json).map_err(Error::StorageParse)?;
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut document);
+    }
+    document.insert("schema_version".to_string(), CURRENT_SCHEMA_VERSION.into());
+
+    let storage = serde_json::from_value(Value::Object(document)).map_err(Error::StorageParse)?;
+    Ok((storage, version))
+}
+
+// Exchanges stored before the KDF was versioned were all derived with v1, and
+// groups came later on.
+fn v0_to_v1(document: &mut Map<String, Value>) {
+    if let Some(Value::Object(exchanges)) = document.get_mut("exchange_map") {
+        for entry in exchanges.values_mut() {
+            if let Some(Value::Object(exchange)) = entry.get_mut(0) {
+                exchange
+                    .entry("kdf_version")
+                    .or_insert_with(|| KDF_V1.into());
+            }
+        }
+    }
+    document
+        .entry("group_map")
+        .or_insert_with(|| Value::Object(Map::new()));
+}
@@ -0,0 +1,155 @@
+use modules::backup::{
+    import_into, open_backup, seal_backup, select_exchanges, ImportConflict, ImportSummary,
+};
+use modules::error::Error;
+use modules::key_exchange::gen_static_kp;
+use modules::key_store_classes::{GroupChannel, KeyExchange, KeyStorage, SenderKey};
+
+fn storage_with(names: &[&str]) -> KeyStorage {
+    let mut storage = KeyStorage::new();
+    for name in names {
+        let mut exchange = KeyExchange::new();
+        exchange.add_your_dh_kp(gen_static_kp());
+        storage
+            .create_exchange(name, false, Some(exchange), None)
+            .unwrap();
+    }
+    storage
+}
+
+fn public_key(storage: &KeyStorage, name: &str) -> [u8; 32] {
+    storage.get_exchange(name).unwrap().get_your_public_key()
+}
+
+// A backup small enough for the tests, the passphrase still goes through Argon2id.
+fn backup_of(storage: &KeyStorage) -> KeyStorage {
+    let sealed = seal_backup(storage, "backup passphrase").unwrap();
+    open_backup(&sealed, "backup passphrase").unwrap()
+}
+
+#[test]
+fn backup_round_trip() {
+    let mut storage = storage_with(&["bob", "carol"]);
+    let mut group = GroupChannel::new(vec!["bob".to_string()]);
+    group
+        .member_keys
+        .insert("bob".to_string(), SenderKey::new(1));
+    storage.group_map.insert("team".to_string(), group);
+
+    let sealed = seal_backup(&storage, "backup passphrase").unwrap();
+    assert!(matches!(
+        open_backup(&sealed, "wrong passphrase"),
+        Err(Error::WrongPassphrase)
+    ));
+
+    let restored = open_backup(&sealed, "backup passphrase").unwrap();
+    assert_eq!(public_key(&restored, "bob"), public_key(&storage, "bob"));
+    assert_eq!(restored.group_map["team"], storage.group_map["team"]);
+}
+
+#[test]
+fn not_a_backup() {
+    assert!(matches!(
+        open_backup("{\"exchange_map\":{}}", "backup passphrase"),
+        Err(Error::InvalidBackup)
+    ));
+
+    let sealed = seal_backup(&KeyStorage::new(), "backup passphrase").unwrap();
+    let newer = sealed.replace("\"version\": 1,", "\"version\": 2,");
+    assert!(matches!(
+        open_backup(&newer, "backup passphrase"),
+        Err(Error::UnsupportedBackupVersion(2))
+    ));
+}
+
+#[test]
+fn subset_keeps_groups_of_selected_members_only() {
+    let mut storage = storage_with(&["bob", "carol", "dave"]);
+    storage.group_map.insert(
+        "bob and carol".to_string(),
+        GroupChannel::new(vec!["bob".to_string(), "carol".to_string()]),
+    );
+    storage.group_map.insert(
+        "everyone".to_string(),
+        GroupChannel::new(vec!["bob".to_string(), "dave".to_string()]),
+    );
+
+    let names = vec!["bob".to_string(), "carol".to_string()];
+    let subset = select_exchanges(storage, &names).unwrap();
+    assert_eq!(subset.exchange_map.len(), 2);
+    assert!(subset.group_map.contains_key("bob and carol"));
+    assert!(!subset.group_map.contains_key("everyone"));
+
+    assert!(matches!(
+        select_exchanges(KeyStorage::new(), &names),
+        Err(Error::UnknownExchange(_))
+    ));
+}
+
+#[test]
+fn import_conflicts() {
+    let mut backup = storage_with(&["bob", "carol"]);
+    backup.group_map.insert(
+        "team".to_string(),
+        GroupChannel::new(vec!["bob".to_string(), "carol".to_string()]),
+    );
+    let backup_bob = public_key(&backup, "bob");
+
+    let mut storage = storage_with(&["bob"]);
+    let bob = public_key(&storage, "bob");
+    let summary = import_into(&mut storage, backup_of(&backup), ImportConflict::Skip).unwrap();
+    assert_eq!(summary.skipped, vec!["bob".to_string()]);
+    assert_eq!(public_key(&storage, "bob"), bob);
+    assert_eq!(storage.group_map["team"].members, vec!["bob", "carol"]);
+
+    let mut storage = storage_with(&["bob"]);
+    let summary = import_into(&mut storage, backup_of(&backup), ImportConflict::Overwrite).unwrap();
+    assert_eq!(summary.overwritten, vec!["bob".to_string()]);
+    assert_eq!(public_key(&storage, "bob"), backup_bob);
+
+    let mut storage = storage_with(&["bob"]);
+    storage
+        .group_map
+        .insert("team".to_string(), GroupChannel::new(vec![]));
+    let bob = public_key(&storage, "bob");
+    let summary = import_into(&mut storage, backup_of(&backup), ImportConflict::Merge).unwrap();
+    assert_eq!(public_key(&storage, "bob"), bob);
+    assert_eq!(public_key(&storage, "bob-imported"), backup_bob);
+    assert_eq!(
+        storage.group_map["team-imported"].members,
+        vec!["bob-imported", "carol"]
+    );
+    assert_eq!(
+        summary,
+        ImportSummary {
+            imported: vec!["carol".to_string()],
+            overwritten: vec![],
+            skipped: vec![],
+            renamed: vec![
+                ("bob".to_string(), "bob-imported".to_string()),
+                ("team".to_string(), "team-imported".to_string()),
+            ],
+        }
+    );
+}
+
+#[test]
+fn import_skips_exchanges_already_stored() {
+    let mut backup = storage_with(&["bob"]);
+    backup.group_map.insert(
+        "team".to_string(),
+        GroupChannel::new(vec!["bob".to_string()]),
+    );
+
+    // The same exchange, stored under another name.
+    let mut storage = backup_of(&backup);
+    storage.group_map.clear();
+    let bob = storage.exchange_map.remove("bob").unwrap();
+    storage.exchange_map.insert("robert".to_string(), bob);
+
+    let summary = import_into(&mut storage, backup_of(&backup), ImportConflict::Merge).unwrap();
+    assert_eq!(summary.skipped, vec!["bob".to_string()]);
+    assert_eq!(summary.imported, vec!["team".to_string()]);
+    assert_eq!(storage.exchange_map.len(), 1);
+    assert_eq!(storage.group_map["team"].members, vec!["robert"]);
+}
@@ -0,0 +1,39 @@
+use clap::Parser;
+use modules::cli::{Cli, Command};
+
+#[test]
+fn test_parse_no_command() {
+    let cli = Cli::try_parse_from(["crypto_channel"]).unwrap();
+    assert!(cli.command.is_none());
+}
+
+#[test]
+fn test_parse_complete() {
+    let cli = Cli::try_parse_from([
+        "crypto_channel",
+        "complete",
+        "bob",
+        "C02CYYKtZJ6qvUtux2YKw5jM/+PHs3q2iOcHN3K/EXU=",
+    ])
+    .unwrap();
+
+    match cli.command {
+        Some(Command::Complete {
+            name,
+            peer_key,
+            peer_signing_key,
+            confirm,
+        }) => {
+            assert_eq!(name, "bob");
+            assert_eq!(peer_key, "C02CYYKtZJ6qvUtux2YKw5jM/+PHs3q2iOcHN3K/EXU=");
+            assert!(peer_signing_key.is_none());
+            assert!(!confirm);
+        }
+        _ => panic!("Expected the complete command"),
+    }
+}
+
+#[test]
+fn test_parse_missing_argument() {
+    assert!(Cli::try_parse_from(["crypto_channel", "encrypt"]).is_err());
+}
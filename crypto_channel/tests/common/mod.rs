@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use modules::config::ConfigSettings;
+use modules::error::Error;
+use modules::key_encoding::parse_peer_key;
+use modules::key_store::{complete_exchange, get_exchange_public_key, new_exchange, use_key_store};
+use modules::key_store_classes::ExchangeKind;
+use modules::message::{decrypt_for_exchange, encrypt_for_exchange};
+use modules::storage_backend::MemoryStore;
+
+// The key storage is global, the tests take turns to switch it between parties.
+pub static PARTIES: Mutex<()> = Mutex::new(());
+
+pub struct Party {
+    store: Arc<MemoryStore>,
+    pub cfg: ConfigSettings,
+    // The name this party gave to its exchange with the other one.
+    exchange: &'static str,
+}
+
+impl Party {
+    pub fn new(exchange: &'static str) -> Self {
+        Party {
+            store: Arc::new(MemoryStore::new()),
+            cfg: ConfigSettings::with_storage_path(PathBuf::new()),
+            exchange,
+        }
+    }
+
+    pub fn act<T>(&self, f: impl FnOnce(String) -> T) -> T {
+        use_key_store(self.store.clone(), self.cfg.clone());
+        f(self.exchange.to_string())
+    }
+
+    pub fn public_key(&self) -> String {
+        self.act(|name| get_exchange_public_key(name).unwrap())
+    }
+
+    pub fn try_complete_with(&self, other: &Party) -> Result<(), Error> {
+        let peer_key = parse_peer_key(&other.public_key()).unwrap();
+        self.act(|name| complete_exchange(name, peer_key, None))
+    }
+
+    pub fn complete_with(&self, other: &Party) {
+        self.try_complete_with(other).unwrap();
+    }
+
+    pub fn send(&self, to: &Party, message: &str) {
+        let (encoded_msg, _) =
+            self.act(|name| encrypt_for_exchange(name, message.as_bytes()).unwrap());
+        let decrypted = to.act(|name| decrypt_for_exchange(name, encoded_msg).unwrap());
+        assert_eq!(*decrypted.message, message.as_bytes());
+    }
+}
+
+pub fn exchange(kind: ExchangeKind) -> (Party, Party) {
+    let (alice, bob) = (Party::new("bob"), Party::new("alice"));
+    alice.act(|name| new_exchange(name, kind).unwrap());
+    bob.act(|name| new_exchange(name, kind).unwrap());
+    // For hybrid exchanges, the tool picks who completes it first.
+    match alice.try_complete_with(&bob) {
+        Err(Error::HybridResponseExpected(_)) => {
+            bob.complete_with(&alice);
+            alice.complete_with(&bob);
+        }
+        result => {
+            result.unwrap();
+            bob.complete_with(&alice);
+        }
+    }
+    alice.send(&bob, "hello");
+    bob.send(&alice, "hi");
+    (alice, bob)
+}
@@ -1,5 +1,8 @@
+use base64::{engine::general_purpose, Engine as _};
 use modules::encryption::{decrypt_message, encrypt_message, encrypted_to_str, str_to_encrypted};
-use modules::key_exchange::{gen_encryption_key, gen_shared_secret, gen_static_kp, DhKeyPair};
+use modules::envelope::{open_envelope, seal_envelope};
+use modules::error::Error;
+use modules::key_exchange::{gen_encryption_key, gen_shared_secret, gen_static_kp};
 
 #[test]
 fn test_encryption() {
@@ -18,21 +21,72 @@ fn test_encryption() {
 
     let msg_from_alice = "This is a new test";
 
-    let (encrypted_msg_from_alice, alice_nonce) =
-        encrypt_message(msg_from_alice.as_bytes(), &alice_encrypt_key);
+    let envelope_from_alice =
+        seal_envelope(msg_from_alice.as_bytes(), &alice_encrypt_key, 0, None).unwrap();
 
-    let encoded_msg_from_alice =
-        encrypted_to_str(encrypted_msg_from_alice.clone(), alice_nonce.clone());
+    let encoded_msg_from_alice = encrypted_to_str(&envelope_from_alice);
 
-    let (decoded_msg_by_bob, decoded_alice_nonce) = str_to_encrypted(encoded_msg_from_alice);
+    let decoded_envelope_by_bob = str_to_encrypted(encoded_msg_from_alice).unwrap();
 
-    assert_eq!(encrypted_msg_from_alice, decoded_msg_by_bob);
-    assert_eq!(alice_nonce, decoded_alice_nonce);
+    assert_eq!(envelope_from_alice, decoded_envelope_by_bob);
 
-    let decrypted_msg_by_bob =
-        decrypt_message(decoded_msg_by_bob, &decoded_alice_nonce, &bob_encrypt_key);
+    let decrypted_msg_by_bob = open_envelope(&decoded_envelope_by_bob, &bob_encrypt_key).unwrap();
 
     assert_eq!(decrypted_msg_by_bob, msg_from_alice.as_bytes().to_owned());
 
     println!("{}", std::str::from_utf8(&decrypted_msg_by_bob).unwrap());
 }
+
+#[test]
+fn test_decrypt_with_wrong_key() {
+    let alice_kp = gen_static_kp();
+    let bob_kp = gen_static_kp();
+    let eve_kp = gen_static_kp();
+
+    let alice_encrypt_key = gen_encryption_key(gen_shared_secret(bob_kp.public, alice_kp.secret));
+    let eve_encrypt_key = gen_encryption_key(gen_shared_secret(alice_kp.public, eve_kp.secret));
+
+    let (encrypted_msg, nonce) = encrypt_message(b"For Bob only", &alice_encrypt_key).unwrap();
+
+    assert!(matches!(
+        decrypt_message(encrypted_msg, &nonce, &eve_encrypt_key),
+        Err(Error::AuthenticationFailed)
+    ));
+}
+
+#[test]
+fn test_str_to_encrypted_bad_input() {
+    assert!(matches!(
+        str_to_encrypted(String::from("no envelope here")),
+        Err(Error::BadEncoding)
+    ));
+    assert!(matches!(
+        str_to_encrypted(String::from("not base64!;also not base64!")),
+        Err(Error::BadEncoding)
+    ));
+}
+
+#[test]
+fn test_legacy_message_still_decrypts() {
+    let key = gen_encryption_key(gen_shared_secret(
+        gen_static_kp().public,
+        gen_static_kp().secret,
+    ));
+
+    let (encrypted_msg, nonce) = encrypt_message(b"Sent with an older version", &key).unwrap();
+    let legacy = general_purpose::STANDARD.encode(&nonce)
+        + ";"
+        + &general_purpose::STANDARD.encode(&encrypted_msg);
+
+    let envelope = str_to_encrypted(legacy).unwrap();
+    assert!(envelope.is_legacy());
+    assert_eq!(envelope.signature, None);
+    assert_eq!(
+        open_envelope(&envelope, &key).unwrap(),
+        b"Sent with an older version"
+    );
+    assert_eq!(
+        decrypt_message(envelope.ciphertext, &envelope.nonce, &key).unwrap(),
+        b"Sent with an older version"
+    );
+}
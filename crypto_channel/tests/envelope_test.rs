@@ -0,0 +1,167 @@
+use modules::encryption::{encrypted_to_str, str_to_encrypted};
+use modules::envelope::{
+    key_id, open_envelope, open_multi_recipient_envelope, seal_envelope,
+    seal_multi_recipient_envelope, Envelope, ENVELOPE_VERSION, FLAG_COUNTER, FLAG_SIGNED,
+    HEADER_SIZE, MAX_RECIPIENTS,
+};
+use modules::error::Error;
+use modules::key_exchange::{gen_encryption_key, gen_shared_secret, gen_static_kp};
+
+fn new_key() -> [u8; 32] {
+    gen_encryption_key(gen_shared_secret(
+        gen_static_kp().public,
+        gen_static_kp().secret,
+    ))
+}
+
+#[test]
+fn test_envelope_roundtrip() {
+    let key = new_key();
+    let envelope = seal_envelope(b"Hello through the envelope", &key, 0, None).unwrap();
+
+    assert_eq!(envelope.version, ENVELOPE_VERSION);
+    assert_eq!(envelope.key_id, key_id(&key));
+    assert!(!envelope.is_signed());
+
+    let bytes = envelope.to_bytes();
+    assert_eq!(&bytes[0..2], b"CC");
+
+    let decoded = Envelope::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded, envelope);
+    assert_eq!(
+        open_envelope(&decoded, &key).unwrap(),
+        b"Hello through the envelope"
+    );
+}
+
+#[test]
+fn test_signed_envelope_roundtrip() {
+    let key = new_key();
+    let mut envelope = seal_envelope(b"Signed message", &key, FLAG_SIGNED, None).unwrap();
+    envelope.signature = Some(vec![7u8; 64]);
+
+    let decoded = str_to_encrypted(encrypted_to_str(&envelope)).unwrap();
+    assert_eq!(decoded.signature, Some(vec![7u8; 64]));
+    assert_eq!(open_envelope(&decoded, &key).unwrap(), b"Signed message");
+}
+
+#[test]
+fn test_envelope_wrong_key() {
+    let envelope = seal_envelope(b"For someone else", &new_key(), 0, None).unwrap();
+
+    assert!(matches!(
+        open_envelope(&envelope, &new_key()),
+        Err(Error::KeyMismatch)
+    ));
+}
+
+#[test]
+fn test_envelope_tampered_header() {
+    let key = new_key();
+    let envelope = seal_envelope(b"Don't touch the flags", &key, 0, None).unwrap();
+
+    let mut bytes = envelope.to_bytes();
+    bytes[4] ^= 0b1000_0000;
+    let tampered = Envelope::from_bytes(&bytes).unwrap();
+
+    assert!(matches!(
+        open_envelope(&tampered, &key),
+        Err(Error::AuthenticationFailed)
+    ));
+}
+
+#[test]
+fn test_envelope_unknown_version_and_algorithm() {
+    let key = new_key();
+    let bytes = seal_envelope(b"From the future", &key, 0, None)
+        .unwrap()
+        .to_bytes();
+
+    let mut future_version = bytes.clone();
+    future_version[2] = 9;
+    assert!(matches!(
+        Envelope::from_bytes(&future_version),
+        Err(Error::UnsupportedVersion(9))
+    ));
+
+    let mut future_algorithm = bytes;
+    future_algorithm[3] = 9;
+    assert!(matches!(
+        Envelope::from_bytes(&future_algorithm),
+        Err(Error::UnsupportedAlgorithm(9))
+    ));
+
+    assert!(matches!(
+        Envelope::from_bytes(&[0u8; HEADER_SIZE - 1]),
+        Err(Error::BadEncoding)
+    ));
+}
+
+#[test]
+fn test_envelope_counter_is_authenticated() {
+    let key = new_key();
+    let envelope = seal_envelope(b"Message number 5", &key, 0, Some(5)).unwrap();
+    assert_ne!(envelope.flags & FLAG_COUNTER, 0);
+
+    let decoded = Envelope::from_bytes(&envelope.to_bytes()).unwrap();
+    assert_eq!(decoded.counter, Some(5));
+    assert_eq!(open_envelope(&decoded, &key).unwrap(), b"Message number 5");
+
+    let mut replayed_as_new = decoded;
+    replayed_as_new.counter = Some(6);
+    assert!(matches!(
+        open_envelope(&replayed_as_new, &key),
+        Err(Error::AuthenticationFailed)
+    ));
+}
+
+#[test]
+fn test_multi_recipient_roundtrip() {
+    let (bob, carol, dave) = (new_key(), new_key(), new_key());
+    let envelope = seal_multi_recipient_envelope(b"Hello both", &[(&bob, 4), (&carol, 9)]).unwrap();
+
+    let decoded = Envelope::from_bytes(&envelope.to_bytes()).unwrap();
+    assert_eq!(decoded, envelope);
+    assert!(decoded.is_multi_recipient());
+    assert_eq!(
+        open_multi_recipient_envelope(&decoded, &bob).unwrap(),
+        (b"Hello both".to_vec(), 4)
+    );
+    assert_eq!(
+        open_multi_recipient_envelope(&decoded, &carol).unwrap(),
+        (b"Hello both".to_vec(), 9)
+    );
+    assert!(matches!(
+        open_multi_recipient_envelope(&decoded, &dave),
+        Err(Error::KeyMismatch)
+    ));
+}
+
+#[test]
+fn test_multi_recipient_payload_is_bound_to_wrapped_keys() {
+    let (bob, carol) = (new_key(), new_key());
+    let envelope =
+        seal_multi_recipient_envelope(b"Meet at noon", &[(&bob, 1), (&carol, 1)]).unwrap();
+
+    // Bob knows the content key, but can't swap the payload for Carol.
+    let forged = seal_multi_recipient_envelope(b"Meet at midnight", &[(&bob, 1)]).unwrap();
+    let mut tampered = envelope.clone();
+    tampered.key_id = forged.key_id;
+    tampered.nonce = forged.nonce;
+    tampered.ciphertext = forged.ciphertext;
+
+    assert!(matches!(
+        open_multi_recipient_envelope(&tampered, &carol),
+        Err(Error::AuthenticationFailed)
+    ));
+}
+
+#[test]
+fn test_too_many_recipients() {
+    let key = new_key();
+    let recipients = vec![(&key, 1); MAX_RECIPIENTS + 1];
+    assert!(matches!(
+        seal_multi_recipient_envelope(b"Hello everyone", &recipients),
+        Err(Error::TooManyRecipients(256))
+    ));
+}
@@ -0,0 +1,91 @@
+use modules::error::Error;
+use modules::file_encryption::{
+    decrypt_file, decrypt_stream, encrypt_file, encrypt_stream, CHUNK_SIZE,
+};
+use modules::key_exchange::{gen_encryption_key, gen_shared_secret, gen_static_kp};
+
+fn gen_key() -> [u8; 32] {
+    let alice_kp = gen_static_kp();
+    let bob_kp = gen_static_kp();
+    gen_encryption_key(gen_shared_secret(bob_kp.public, alice_kp.secret))
+}
+
+fn roundtrip(plaintext: &[u8], key: &[u8; 32]) -> Vec<u8> {
+    let mut encrypted = Vec::new();
+    encrypt_stream(plaintext, &mut encrypted, key).unwrap();
+
+    let mut decrypted = Vec::new();
+    decrypt_stream(encrypted.as_slice(), &mut decrypted, key).unwrap();
+    decrypted
+}
+
+#[test]
+fn test_stream_roundtrip() {
+    let key = gen_key();
+
+    for len in [
+        0,
+        1,
+        CHUNK_SIZE - 1,
+        CHUNK_SIZE,
+        CHUNK_SIZE + 1,
+        3 * CHUNK_SIZE,
+    ] {
+        let plaintext: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        assert_eq!(roundtrip(&plaintext, &key), plaintext);
+    }
+}
+
+#[test]
+fn test_stream_truncation_detected() {
+    let key = gen_key();
+    let plaintext = vec![42u8; 2 * CHUNK_SIZE + 10];
+
+    let mut encrypted = Vec::new();
+    encrypt_stream(plaintext.as_slice(), &mut encrypted, &key).unwrap();
+
+    // Drop the final chunk, keeping only the header and the first two chunks.
+    let header_len = encrypted.len() - (2 * (CHUNK_SIZE + 16) + 10 + 16);
+    encrypted.truncate(header_len + 2 * (CHUNK_SIZE + 16));
+
+    let mut decrypted = Vec::new();
+    assert!(matches!(
+        decrypt_stream(encrypted.as_slice(), &mut decrypted, &key),
+        Err(Error::AuthenticationFailed)
+    ));
+}
+
+#[test]
+fn test_stream_wrong_key() {
+    let mut encrypted = Vec::new();
+    encrypt_stream(&b"attachment"[..], &mut encrypted, &gen_key()).unwrap();
+
+    let mut decrypted = Vec::new();
+    assert!(matches!(
+        decrypt_stream(encrypted.as_slice(), &mut decrypted, &gen_key()),
+        Err(Error::AuthenticationFailed)
+    ));
+}
+
+#[test]
+fn test_file_encrypted_in_place() {
+    let key = gen_key();
+    let dir = std::env::temp_dir().join(format!("crypto_channel_file_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("attachment");
+    let plaintext = vec![7u8; CHUNK_SIZE + 3];
+    std::fs::write(&path, &plaintext).unwrap();
+
+    encrypt_file(&path, &path, &key).unwrap();
+    assert_ne!(std::fs::read(&path).unwrap(), plaintext);
+    decrypt_file(&path, &path, &key).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), plaintext);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o077, 0);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use common::{exchange, Party, PARTIES};
+use modules::envelope::key_id;
+use modules::error::Error;
+use modules::group::{
+    add_member, create_group, decrypt_for_group, encrypt_for_group, share_sender_key,
+    SenderKeyDistribution, SenderKeyUpdate,
+};
+use modules::key_store::{delete_exchange, get_group};
+use modules::key_store_classes::ExchangeKind;
+use modules::key_store_classes::{GroupChannel, SenderKey};
+use modules::message::decrypt_for_exchange;
+
+fn members() -> Vec<String> {
+    vec!["Bob".to_string(), "Carol".to_string()]
+}
+
+#[test]
+fn test_distribution_roundtrip() {
+    let distribution = SenderKeyDistribution {
+        group_id: [7u8; 16],
+        generation: 3,
+        key: [1u8; 32],
+        signing_public: [2u8; 32],
+        group_name: "Team 🚀".to_string(),
+    };
+
+    let parsed = SenderKeyDistribution::from_bytes(&distribution.to_bytes()).unwrap();
+    assert_eq!(parsed, distribution);
+}
+
+#[test]
+fn test_distribution_too_short() {
+    assert!(matches!(
+        SenderKeyDistribution::from_bytes(&[0u8; 83]),
+        Err(Error::BadEncoding)
+    ));
+}
+
+#[test]
+fn test_rekey_replaces_sender_key() {
+    let mut group = GroupChannel::new(members());
+    let old_key = group.sender_key.key;
+
+    group.rekey();
+
+    assert_eq!(group.sender_key.generation, 1);
+    assert_ne!(group.sender_key.key, old_key);
+}
+
+#[test]
+fn test_older_member_key_is_ignored() {
+    let mut group = GroupChannel::new(members());
+    let old_key = SenderKey::new(0);
+    let new_key = SenderKey::new(1);
+
+    assert!(group.add_member_key("Bob", old_key.clone()));
+    assert!(group.add_member_key("Bob", new_key.clone()));
+    assert!(!group.add_member_key("Bob", old_key));
+    assert!(group.add_member_key("Bob", new_key.clone()));
+    assert_eq!(group.member_keys["Bob"].key, new_key.key);
+}
+
+#[test]
+fn test_find_sender_only_among_members() {
+    let mut group = GroupChannel::new(members());
+    let carol_key = SenderKey::new(0);
+    let dave_key = SenderKey::new(0);
+    group.add_member_key("Carol", carol_key.clone());
+    group.add_member_key("Dave", dave_key.clone());
+
+    assert_eq!(group.find_sender(&key_id(&carol_key.key)), Some("Carol"));
+    assert_eq!(group.find_sender(&key_id(&dave_key.key)), None);
+
+    group.members.push("Dave".to_string());
+    assert_eq!(group.find_sender(&key_id(&dave_key.key)), Some("Dave"));
+}
+
+// Decrypts the sender key messages of `from` for their only member, `to`.
+fn receive_sender_key(to: &Party, messages: Vec<(String, String)>) -> SenderKeyUpdate {
+    let [(_, encoded_msg)] = <[_; 1]>::try_from(messages).unwrap();
+    let decrypted = to.act(|name| decrypt_for_exchange(name, encoded_msg).unwrap());
+    decrypted.sender_key.unwrap()
+}
+
+fn send_to_group(from: &Party, to: &Party, message: &str) {
+    let encoded_msg = from.act(|_| encrypt_for_group("team".to_string(), message.as_bytes()));
+    let decrypted = to
+        .act(|_| decrypt_for_group("team".to_string(), encoded_msg.unwrap()))
+        .unwrap();
+    assert_eq!(*decrypted.message, message.as_bytes());
+    assert_eq!(decrypted.sender, to.act(|name| name));
+}
+
+#[test]
+fn test_group_messages_between_members() {
+    let _parties = PARTIES.lock().unwrap_or_else(|e| e.into_inner());
+    let (alice, bob) = exchange(ExchangeKind::Classic);
+
+    let messages = alice.act(|name| create_group("team".to_string(), vec![name]).unwrap());
+    assert_eq!(
+        receive_sender_key(&bob, messages),
+        SenderKeyUpdate::Stored("team".to_string())
+    );
+    send_to_group(&alice, &bob, "hello team");
+
+    let messages = bob.act(|_| share_sender_key("team".to_string()).unwrap());
+    receive_sender_key(&alice, messages);
+    send_to_group(&bob, &alice, "hi");
+
+    // Adding a member again replaces the sender key, and shares it.
+    let old_key = alice.act(|_| get_group("team".to_string()).unwrap().sender_key.key);
+    let messages = alice.act(|name| add_member("team".to_string(), name).unwrap());
+    let new_key = alice.act(|_| get_group("team".to_string()).unwrap().sender_key.key);
+    assert_ne!(new_key, old_key);
+    receive_sender_key(&bob, messages);
+    send_to_group(&alice, &bob, "with the new key");
+}
+
+#[test]
+fn test_member_exchange_not_deleted() {
+    let _parties = PARTIES.lock().unwrap_or_else(|e| e.into_inner());
+    let (alice, _bob) = exchange(ExchangeKind::Classic);
+
+    alice.act(|name| create_group("team".to_string(), vec![name]).unwrap());
+    assert!(matches!(
+        alice.act(delete_exchange),
+        Err(Error::ExchangeInGroup(..))
+    ));
+    assert!(alice.act(|_| share_sender_key("team".to_string())).is_ok());
+}
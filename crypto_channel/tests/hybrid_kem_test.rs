@@ -0,0 +1,54 @@
+use modules::error::Error;
+use modules::hybrid_kem::{
+    gen_kem_kp, hybrid_shared_secret, kem_decapsulate, kem_encapsulate, KEM_CIPHERTEXT_SIZE,
+    KEM_PUBLIC_KEY_SIZE, KEM_SECRET_KEY_SIZE,
+};
+
+#[test]
+fn test_kem_roundtrip() {
+    let kp = gen_kem_kp().unwrap();
+    assert_eq!(kp.public.len(), KEM_PUBLIC_KEY_SIZE);
+    assert_eq!(kp.secret.len(), KEM_SECRET_KEY_SIZE);
+
+    let (ciphertext, secret) = kem_encapsulate(&kp.public).unwrap();
+    assert_eq!(ciphertext.len(), KEM_CIPHERTEXT_SIZE);
+    assert_eq!(kem_decapsulate(&kp.secret, &ciphertext).unwrap(), secret);
+}
+
+#[test]
+fn test_kem_tampered_ciphertext() {
+    let kp = gen_kem_kp().unwrap();
+    let (mut ciphertext, secret) = kem_encapsulate(&kp.public).unwrap();
+    ciphertext[0] ^= 1;
+
+    // ML-KEM rejects implicitly: a tampered ciphertext gives an unrelated secret.
+    assert_ne!(kem_decapsulate(&kp.secret, &ciphertext).unwrap(), secret);
+    assert!(matches!(
+        kem_decapsulate(&kp.secret, &ciphertext[1..]),
+        Err(Error::KemFailed)
+    ));
+}
+
+#[test]
+fn test_kem_wrong_public_key() {
+    assert!(matches!(kem_encapsulate(&[0u8; 32]), Err(Error::KemFailed)));
+}
+
+#[test]
+fn test_hybrid_shared_secret() {
+    let secret = hybrid_shared_secret([1u8; 32], [2u8; 32], b"ciphertext");
+
+    assert_eq!(
+        secret,
+        hybrid_shared_secret([1u8; 32], [2u8; 32], b"ciphertext")
+    );
+    assert_ne!(
+        secret,
+        hybrid_shared_secret([3u8; 32], [2u8; 32], b"ciphertext")
+    );
+    assert_ne!(
+        secret,
+        hybrid_shared_secret([1u8; 32], [3u8; 32], b"ciphertext")
+    );
+    assert_ne!(secret, hybrid_shared_secret([1u8; 32], [2u8; 32], b"other"));
+}
@@ -0,0 +1,135 @@
+use modules::error::Error;
+use modules::hybrid_kem::gen_kem_kp;
+use modules::key_encoding::{
+    bech32_decode, bech32_encode, encode_hybrid_key, encode_public_key, parse_peer_key,
+    parse_public_key, PeerKey, HYBRID_PUBLIC_KEY_PREFIX, HYBRID_RESPONSE_PREFIX, PUBLIC_KEY_PREFIX,
+    SIGNING_KEY_PREFIX,
+};
+use modules::key_exchange::{gen_static_kp, key_to_string, CURRENT_KDF_VERSION, KDF_V1};
+
+#[test]
+fn test_bech32m_vectors() {
+    assert_eq!(
+        bech32_decode("a1lqfn3a").unwrap(),
+        (String::from("a"), vec![])
+    );
+    assert_eq!(
+        bech32_decode("A1LQFN3A").unwrap(),
+        (String::from("a"), vec![])
+    );
+    assert!(bech32_decode("abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx").is_ok());
+
+    // Valid bech32 (not bech32m) and mixed case strings are rejected.
+    assert!(bech32_decode("a12uel5l").is_err());
+    assert!(bech32_decode("A1lqfn3a").is_err());
+}
+
+#[test]
+fn test_public_key_roundtrip() {
+    let kp = gen_static_kp();
+    let encoded = encode_public_key(kp.public, PUBLIC_KEY_PREFIX);
+
+    assert!(encoded.starts_with("ccpub1"));
+    assert_eq!(
+        parse_public_key(&encoded, PUBLIC_KEY_PREFIX).unwrap(),
+        kp.public
+    );
+    assert_eq!(
+        parse_public_key(
+            &format!("  {}\n", encoded.to_uppercase()),
+            PUBLIC_KEY_PREFIX
+        )
+        .unwrap(),
+        kp.public
+    );
+    assert_eq!(bech32_encode(PUBLIC_KEY_PREFIX, &kp.public), encoded);
+}
+
+#[test]
+fn test_base64_keys_still_accepted() {
+    let kp = gen_static_kp();
+    assert_eq!(
+        parse_public_key(&key_to_string(kp.public), PUBLIC_KEY_PREFIX).unwrap(),
+        kp.public
+    );
+}
+
+#[test]
+fn test_typo_is_detected() {
+    let encoded = encode_public_key(gen_static_kp().public, PUBLIC_KEY_PREFIX);
+
+    for position in [6, 20, encoded.len() - 1] {
+        let mut typo: Vec<char> = encoded.chars().collect();
+        typo[position] = if typo[position] == 'q' { 'p' } else { 'q' };
+        let typo: String = typo.into_iter().collect();
+
+        assert!(matches!(
+            parse_public_key(&typo, PUBLIC_KEY_PREFIX),
+            Err(Error::BadChecksum)
+        ));
+    }
+
+    let mut swapped: Vec<char> = encoded.chars().collect();
+    swapped.swap(10, 11);
+    if swapped[10] != swapped[11] {
+        let swapped: String = swapped.into_iter().collect();
+        assert!(parse_public_key(&swapped, PUBLIC_KEY_PREFIX).is_err());
+    }
+
+    assert!(parse_public_key(&encoded[..encoded.len() - 1], PUBLIC_KEY_PREFIX).is_err());
+}
+
+#[test]
+fn test_wrong_prefix() {
+    let signing = encode_public_key(gen_static_kp().public, SIGNING_KEY_PREFIX);
+
+    assert!(matches!(
+        parse_public_key(&signing, PUBLIC_KEY_PREFIX),
+        Err(Error::WrongKeyPrefix(_, _))
+    ));
+}
+
+#[test]
+fn test_peer_key_kinds() {
+    let dh_public = gen_static_kp().public;
+    let kem_public = gen_kem_kp().unwrap().public.clone();
+
+    assert_eq!(
+        parse_peer_key(&encode_public_key(dh_public, PUBLIC_KEY_PREFIX)).unwrap(),
+        PeerKey::Classic(dh_public)
+    );
+    assert_eq!(
+        parse_peer_key(&encode_hybrid_key(
+            dh_public,
+            &kem_public,
+            HYBRID_PUBLIC_KEY_PREFIX
+        ))
+        .unwrap(),
+        PeerKey::HybridPublic(dh_public, kem_public.clone())
+    );
+
+    // A ciphertext is shorter than an encapsulation key.
+    let response = encode_hybrid_key(dh_public, &kem_public, HYBRID_RESPONSE_PREFIX);
+    assert!(matches!(parse_peer_key(&response), Err(Error::BadEncoding)));
+
+    let mut typo: Vec<char> = encode_hybrid_key(dh_public, &kem_public, HYBRID_PUBLIC_KEY_PREFIX)
+        .chars()
+        .collect();
+    typo[500] = if typo[500] == 'q' { 'p' } else { 'q' };
+    assert!(matches!(
+        parse_peer_key(&typo.into_iter().collect::<String>()),
+        Err(Error::BadChecksum)
+    ));
+}
+
+#[test]
+fn test_peer_key_tells_kdf_version() {
+    let dh_public = gen_static_kp().public;
+
+    let legacy = parse_peer_key(&key_to_string(dh_public)).unwrap();
+    assert_eq!(legacy, PeerKey::Legacy(dh_public));
+    assert_eq!(legacy.kdf_version(), KDF_V1);
+
+    let current = parse_peer_key(&encode_public_key(dh_public, PUBLIC_KEY_PREFIX)).unwrap();
+    assert_eq!(current.kdf_version(), CURRENT_KDF_VERSION);
+}
@@ -1,5 +1,7 @@
+use modules::error::Error;
 use modules::key_exchange::{
-    gen_encryption_key, gen_shared_secret, gen_static_kp, key_to_string, string_to_key,
+    check_confirmation_token, confirmation_token, derive_channel_keys, gen_encryption_key,
+    gen_shared_secret, gen_static_kp, key_to_string, safety_number, string_to_key,
 };
 
 #[test]
@@ -16,8 +18,8 @@ fn test_kp_str_conversion() {
     let public_str = key_to_string(kp.public);
     let secret_str = key_to_string(kp.secret);
 
-    let public_arr = string_to_key(public_str.clone());
-    let secret_arr = string_to_key(secret_str.clone());
+    let public_arr = string_to_key(public_str.clone()).unwrap();
+    let secret_arr = string_to_key(secret_str.clone()).unwrap();
 
     println!("Public key: {:?}", kp.public);
     println!("Public str: {}", public_str);
@@ -55,3 +57,126 @@ fn test_gen_encrypt_key() {
 
     assert!(alice_encrypt_key == bob_encrypt_key);
 }
+
+#[test]
+fn test_derive_channel_keys() {
+    let alice_kp = gen_static_kp();
+    let bob_kp = gen_static_kp();
+
+    let alice_shared = gen_shared_secret(bob_kp.public, alice_kp.secret);
+    let bob_shared = gen_shared_secret(alice_kp.public, bob_kp.secret);
+
+    let alice_keys = derive_channel_keys(alice_shared, alice_kp.public, bob_kp.public);
+    let bob_keys = derive_channel_keys(bob_shared, bob_kp.public, alice_kp.public);
+
+    assert_eq!(alice_keys.send_key, bob_keys.receive_key);
+    assert_eq!(alice_keys.receive_key, bob_keys.send_key);
+    assert_eq!(alice_keys.mac_key, bob_keys.mac_key);
+
+    assert_ne!(alice_keys.send_key, alice_keys.receive_key);
+    assert_ne!(alice_keys.send_key, alice_keys.mac_key);
+    assert_ne!(alice_keys.send_key, gen_encryption_key(alice_shared));
+
+    // Someone substituting their own public key ends up with different keys.
+    let eve_kp = gen_static_kp();
+    let eve_keys = derive_channel_keys(alice_shared, alice_kp.public, eve_kp.public);
+    assert_ne!(alice_keys.send_key, eve_keys.send_key);
+}
+
+#[test]
+fn test_string_to_key_bad_input() {
+    assert!(matches!(
+        string_to_key(String::from("C02CYYKtZJ6qvUtux2YKw5jM/+PHs3q2iOcHN3K/EX!")),
+        Err(Error::BadEncoding)
+    ));
+    assert!(matches!(
+        string_to_key(key_to_string([7u8; 32])[..40].to_string()),
+        Err(Error::BadEncoding) | Err(Error::WrongKeyLength(_))
+    ));
+    assert!(matches!(
+        string_to_key(String::from("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA")),
+        Err(Error::WrongKeyLength(33))
+    ));
+}
+
+#[test]
+fn test_safety_number() {
+    let alice_kp = gen_static_kp();
+    let bob_kp = gen_static_kp();
+    let eve_kp = gen_static_kp();
+
+    let alice_number = safety_number(alice_kp.public, bob_kp.public);
+    let bob_number = safety_number(bob_kp.public, alice_kp.public);
+    assert_eq!(alice_number, bob_number);
+
+    let groups: Vec<&str> = alice_number.split(' ').collect();
+    assert_eq!(groups.len(), 6);
+    assert!(groups
+        .iter()
+        .all(|g| g.len() == 5 && g.chars().all(|c| c.is_ascii_digit())));
+
+    assert_ne!(alice_number, safety_number(alice_kp.public, eve_kp.public));
+}
+
+#[test]
+fn test_confirmation_token() {
+    let alice_kp = gen_static_kp();
+    let bob_kp = gen_static_kp();
+    let shared = gen_shared_secret(bob_kp.public, alice_kp.secret);
+
+    let alice = derive_channel_keys(shared, alice_kp.public, bob_kp.public);
+    let bob = derive_channel_keys(shared, bob_kp.public, alice_kp.public);
+
+    let alice_token = confirmation_token(
+        &alice.mac_key,
+        alice_kp.public,
+        bob_kp.public,
+        alice.send_key,
+        alice.receive_key,
+    );
+    let check_as_bob = |token: &str| {
+        check_confirmation_token(
+            token,
+            &bob.mac_key,
+            bob_kp.public,
+            alice_kp.public,
+            bob.send_key,
+            bob.receive_key,
+        )
+    };
+
+    assert_eq!(alice_token.len(), 19);
+    assert!(check_as_bob(&alice_token).is_ok());
+    assert!(check_as_bob(&alice_token.replace('-', " ").to_uppercase()).is_ok());
+
+    // Bob's own token can't be reflected back to him.
+    let bob_token = confirmation_token(
+        &bob.mac_key,
+        bob_kp.public,
+        alice_kp.public,
+        bob.send_key,
+        bob.receive_key,
+    );
+    assert!(matches!(
+        check_as_bob(&bob_token),
+        Err(Error::ConfirmationMismatch)
+    ));
+
+    // Keys derived from a swapped Public Key don't confirm.
+    let eve_kp = gen_static_kp();
+    let eve_shared = gen_shared_secret(eve_kp.public, alice_kp.secret);
+    let swapped = derive_channel_keys(eve_shared, alice_kp.public, eve_kp.public);
+    let swapped_token = confirmation_token(
+        &swapped.mac_key,
+        alice_kp.public,
+        bob_kp.public,
+        swapped.send_key,
+        swapped.receive_key,
+    );
+    assert!(matches!(
+        check_as_bob(&swapped_token),
+        Err(Error::ConfirmationMismatch)
+    ));
+
+    assert!(matches!(check_as_bob("1234"), Err(Error::BadEncoding)));
+}
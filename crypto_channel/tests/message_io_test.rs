@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+
+use modules::message_io::{read_message, write_message, MessageSink, MessageSource};
+
+#[test]
+fn test_parse_source_and_sink() {
+    assert_eq!("-".parse::<MessageSource>().unwrap(), MessageSource::Stdin);
+    assert_eq!(
+        "clipboard".parse::<MessageSource>().unwrap(),
+        MessageSource::Clipboard
+    );
+    assert_eq!(
+        "msg.txt".parse::<MessageSource>().unwrap(),
+        MessageSource::File(PathBuf::from("msg.txt"))
+    );
+    assert!("".parse::<MessageSource>().is_err());
+
+    assert_eq!("-".parse::<MessageSink>().unwrap(), MessageSink::Stdout);
+    assert_eq!(
+        "clipboard".parse::<MessageSink>().unwrap(),
+        MessageSink::Clipboard
+    );
+}
+
+#[test]
+fn test_file_message_roundtrip() {
+    let path = std::env::temp_dir().join("crypto_channel_message_io_test.txt");
+    let message = b"binary \x00\xff message";
+
+    write_message(&MessageSink::File(path.clone()), message).unwrap();
+    let read = read_message(&MessageSource::File(path.clone())).unwrap();
+
+    assert_eq!(read, message);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o077, 0);
+    }
+    std::fs::remove_file(path).unwrap();
+}
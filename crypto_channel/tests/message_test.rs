@@ -0,0 +1,110 @@
+mod common;
+
+use common::{exchange, Party, PARTIES};
+use modules::error::Error;
+use modules::key_encoding::{parse_peer_key, PeerKey};
+use modules::key_exchange::key_to_string;
+use modules::key_store::{complete_exchange, new_exchange, read_storage};
+use modules::key_store_classes::ExchangeKind;
+use modules::message::{
+    decrypt_for_exchange, decrypt_from_any_exchange, encrypt_for_exchange, encrypt_for_exchanges,
+    SignatureStatus,
+};
+
+#[test]
+fn several_exchanges_message_is_unsigned() {
+    let _parties = PARTIES.lock().unwrap_or_else(|e| e.into_inner());
+    let (mut alice, bob) = exchange(ExchangeKind::Classic);
+
+    let (encoded_msg, status) =
+        alice.act(|name| encrypt_for_exchanges(vec![name], b"hello").unwrap());
+    assert_eq!(status, SignatureStatus::Disabled);
+    let decrypted = bob.act(|name| decrypt_for_exchange(name, encoded_msg).unwrap());
+    assert_eq!(*decrypted.message, b"hello");
+
+    alice.cfg.use_signature = true;
+    let (_, status) = alice.act(|name| encrypt_for_exchanges(vec![name], b"hello").unwrap());
+    assert_eq!(status, SignatureStatus::Unsigned);
+}
+
+#[test]
+fn several_exchanges_refused_with_ratchet() {
+    let _parties = PARTIES.lock().unwrap_or_else(|e| e.into_inner());
+    let (mut alice, _bob) = exchange(ExchangeKind::Classic);
+
+    alice.cfg.use_ratchet = true;
+    assert!(matches!(
+        alice.act(|name| encrypt_for_exchanges(vec![name], b"hello")),
+        Err(Error::MultiRecipientWithRatchet)
+    ));
+}
+
+#[test]
+fn any_exchange_skips_pending_exchanges() {
+    let _parties = PARTIES.lock().unwrap_or_else(|e| e.into_inner());
+    let (mut alice, mut bob) = exchange(ExchangeKind::Classic);
+    alice.cfg.use_ratchet = true;
+    bob.cfg.use_ratchet = true;
+    bob.act(|_| new_exchange("aaron".to_string(), ExchangeKind::Classic).unwrap());
+
+    // Ratchet messages don't tell their exchange, each one is tried.
+    let (encoded_msg, _) = alice.act(|name| encrypt_for_exchange(name, b"hello").unwrap());
+    let (name, decrypted) = bob.act(|_| decrypt_from_any_exchange(encoded_msg).unwrap());
+    assert_eq!(name, "alice");
+    assert_eq!(*decrypted.message, b"hello");
+}
+
+#[test]
+fn base64_key_completes_both_sides_with_kdf_v1() {
+    let _parties = PARTIES.lock().unwrap_or_else(|e| e.into_inner());
+    let (alice, bob) = (Party::new("bob"), Party::new("alice"));
+    alice.act(|name| new_exchange(name, ExchangeKind::Classic).unwrap());
+    bob.act(|name| new_exchange(name, ExchangeKind::Classic).unwrap());
+
+    // Alice sends her Public Key in base64, and completes with Bob's `ccpub` key.
+    let bob_key = parse_peer_key(&bob.public_key()).unwrap();
+    let PeerKey::Classic(alice_key) = parse_peer_key(&alice.public_key()).unwrap() else {
+        panic!("a new exchange shows a `ccpub` key");
+    };
+    alice.act(|name| complete_exchange(name, bob_key, None).unwrap());
+    let legacy_key = parse_peer_key(&key_to_string(alice_key)).unwrap();
+    bob.act(|name| complete_exchange(name, legacy_key, None).unwrap());
+
+    // Bob's key is now shown in base64 too, and completing with it again
+    // gets Alice to the same keys.
+    alice.complete_with(&bob);
+    alice.send(&bob, "hello");
+    bob.send(&alice, "hi");
+}
+
+#[test]
+fn trying_exchanges_leaves_their_ratchet_sessions_unstarted() {
+    let _parties = PARTIES.lock().unwrap_or_else(|e| e.into_inner());
+    let (mut alice, mut bob) = exchange(ExchangeKind::Classic);
+    alice.cfg.use_ratchet = true;
+    bob.cfg.use_ratchet = true;
+
+    // Sorted first, so Bob tries it before his exchange with Alice.
+    let carol = Party::new("bob");
+    carol.act(|name| new_exchange(name, ExchangeKind::Classic).unwrap());
+    bob.act(|_| new_exchange("aaron".to_string(), ExchangeKind::Classic).unwrap());
+    let carol_key = parse_peer_key(&carol.public_key()).unwrap();
+    bob.act(|_| complete_exchange("aaron".to_string(), carol_key, None).unwrap());
+
+    let (encoded_msg, _) = alice.act(|name| encrypt_for_exchange(name, b"hello").unwrap());
+    let (exchange_name, decrypted) = bob.act(|_| decrypt_from_any_exchange(encoded_msg).unwrap());
+    assert_eq!(exchange_name, "alice");
+    assert_eq!(*decrypted.message, b"hello");
+
+    let storage = bob.act(|_| read_storage().unwrap());
+    assert!(storage
+        .get_exchange("aaron")
+        .unwrap()
+        .get_ratchet()
+        .is_none());
+    assert!(storage
+        .get_exchange("alice")
+        .unwrap()
+        .get_ratchet()
+        .is_some());
+}
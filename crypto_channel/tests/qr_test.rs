@@ -0,0 +1,78 @@
+use std::fs;
+
+use modules::error::Error;
+use modules::key_encoding::{encode_public_key, parse_peer_key, PeerKey, PUBLIC_KEY_PREFIX};
+use modules::key_exchange::{gen_static_kp, key_to_string};
+use modules::qr::{
+    export_qr, key_qr_data, message_qr_to_terminal, qr_to_terminal, MAX_QR_MESSAGE_LEN,
+};
+
+fn public_key_str() -> String {
+    encode_public_key(gen_static_kp().public, PUBLIC_KEY_PREFIX).to_uppercase()
+}
+
+#[test]
+fn test_qr_to_terminal() {
+    let code = qr_to_terminal(&public_key_str()).unwrap();
+
+    assert!(code.lines().count() > 10);
+    assert!(code.contains('█') || code.contains('▀') || code.contains('▄'));
+}
+
+#[test]
+fn test_longest_message_fits() {
+    let message = "A".repeat(MAX_QR_MESSAGE_LEN);
+    assert!(message_qr_to_terminal(&message).is_ok());
+
+    let message = "A".repeat(MAX_QR_MESSAGE_LEN + 1);
+    assert!(matches!(
+        message_qr_to_terminal(&message),
+        Err(Error::MessageTooLongForQr(_))
+    ));
+}
+
+#[test]
+fn test_export_png_and_svg() {
+    let dir = std::env::temp_dir();
+    let png_path = dir.join("crypto_channel_qr_test.png");
+    let svg_path = dir.join("crypto_channel_qr_test.svg");
+
+    export_qr(&public_key_str(), &png_path).unwrap();
+    export_qr(&public_key_str(), &svg_path).unwrap();
+
+    let png = fs::read(&png_path).unwrap();
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    let svg = fs::read_to_string(&svg_path).unwrap();
+    assert!(svg.contains("<svg"));
+
+    fs::remove_file(png_path).unwrap();
+    fs::remove_file(svg_path).unwrap();
+}
+
+#[test]
+fn test_unsupported_image_format() {
+    let path = std::env::temp_dir().join("crypto_channel_qr_test.gif");
+    assert!(matches!(
+        export_qr(&public_key_str(), &path),
+        Err(Error::UnsupportedImageFormat(_))
+    ));
+}
+
+#[test]
+fn test_data_too_long() {
+    let data = "a".repeat(8000);
+    assert!(matches!(qr_to_terminal(&data), Err(Error::QrCode(_))));
+}
+
+#[test]
+fn test_key_qr_data_decodes_to_the_same_key() {
+    let public = gen_static_kp().public;
+
+    let qr_data = key_qr_data(&encode_public_key(public, PUBLIC_KEY_PREFIX));
+    assert_eq!(qr_data, qr_data.to_uppercase());
+    assert_eq!(parse_peer_key(&qr_data).unwrap(), PeerKey::Classic(public));
+
+    // Base64 keys of KDF v1 exchanges are case sensitive.
+    let qr_data = key_qr_data(&key_to_string(public));
+    assert_eq!(parse_peer_key(&qr_data).unwrap(), PeerKey::Legacy(public));
+}
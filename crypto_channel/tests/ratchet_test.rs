@@ -0,0 +1,137 @@
+use modules::envelope::{open_envelope, seal_ratchet_envelope, Envelope};
+use modules::error::Error;
+use modules::key_exchange::{gen_shared_secret, gen_static_kp};
+use modules::key_store_classes::MessageOrder;
+use modules::ratchet::RatchetState;
+
+fn new_sessions() -> (RatchetState, RatchetState) {
+    let alice_kp = gen_static_kp();
+    let bob_kp = gen_static_kp();
+    let shared = gen_shared_secret(bob_kp.public, alice_kp.secret);
+
+    let alice = RatchetState::new(shared, alice_kp.public, alice_kp.secret, bob_kp.public);
+    let bob = RatchetState::new(shared, bob_kp.public, bob_kp.secret, alice_kp.public);
+    (alice, bob)
+}
+
+fn send(from: &mut RatchetState, msg: &[u8]) -> Envelope {
+    let (header, message_key) = from.next_send_key();
+    let bytes = seal_ratchet_envelope(msg, &message_key, 0, header)
+        .unwrap()
+        .to_bytes();
+    Envelope::from_bytes(&bytes).unwrap()
+}
+
+fn receive(to: &mut RatchetState, envelope: &Envelope) -> Result<(Vec<u8>, MessageOrder), Error> {
+    let (message_key, order) = to.receive_key(envelope.ratchet.as_ref().unwrap())?;
+    Ok((open_envelope(envelope, &message_key)?, order))
+}
+
+#[test]
+fn test_either_party_can_send_first() {
+    for alice_first in [true, false] {
+        let (mut alice, mut bob) = new_sessions();
+        let (first, second) = match alice_first {
+            true => (&mut alice, &mut bob),
+            false => (&mut bob, &mut alice),
+        };
+
+        let msg = send(first, b"hello");
+        assert_eq!(receive(second, &msg).unwrap().0, b"hello");
+
+        let reply = send(second, b"hi back");
+        assert_eq!(receive(first, &reply).unwrap().0, b"hi back");
+    }
+}
+
+#[test]
+fn test_both_parties_send_first() {
+    let (mut alice, mut bob) = new_sessions();
+
+    let from_alice = send(&mut alice, b"from alice");
+    let from_bob = send(&mut bob, b"from bob");
+
+    assert_eq!(receive(&mut bob, &from_alice).unwrap().0, b"from alice");
+    assert_eq!(receive(&mut alice, &from_bob).unwrap().0, b"from bob");
+
+    for i in 0..3 {
+        let msg = format!("ping {}", i);
+        let ping = send(&mut alice, msg.as_bytes());
+        assert_eq!(receive(&mut bob, &ping).unwrap().0, msg.as_bytes());
+
+        let pong = send(&mut bob, b"pong");
+        assert_eq!(receive(&mut alice, &pong).unwrap().0, b"pong");
+    }
+}
+
+#[test]
+fn test_ratchet_keys_change() {
+    let (mut alice, mut bob) = new_sessions();
+
+    let first = send(&mut alice, b"one");
+    receive(&mut bob, &first).unwrap();
+    let reply = send(&mut bob, b"two");
+    receive(&mut alice, &reply).unwrap();
+    let second = send(&mut alice, b"three");
+
+    assert_ne!(first.key_id, second.key_id);
+    assert_ne!(
+        first.ratchet.unwrap().dh_public,
+        second.ratchet.unwrap().dh_public
+    );
+}
+
+#[test]
+fn test_out_of_order_and_skipped_messages() {
+    let (mut alice, mut bob) = new_sessions();
+
+    let first = send(&mut alice, b"first");
+    let second = send(&mut alice, b"second");
+    let third = send(&mut alice, b"third");
+
+    assert_eq!(
+        receive(&mut bob, &third).unwrap(),
+        (b"third".to_vec(), MessageOrder::OutOfOrder)
+    );
+    assert_eq!(
+        receive(&mut bob, &first).unwrap(),
+        (b"first".to_vec(), MessageOrder::OutOfOrder)
+    );
+
+    // Alice's new ratchet key arrives before the end of her previous chain.
+    let reply = send(&mut bob, b"reply");
+    receive(&mut alice, &reply).unwrap();
+    let fourth = send(&mut alice, b"fourth");
+    assert_eq!(receive(&mut bob, &fourth).unwrap().0, b"fourth");
+
+    assert_eq!(
+        receive(&mut bob, &second).unwrap(),
+        (b"second".to_vec(), MessageOrder::OutOfOrder)
+    );
+}
+
+#[test]
+fn test_replayed_message() {
+    let (mut alice, mut bob) = new_sessions();
+
+    let msg = send(&mut alice, b"only once");
+    assert_eq!(
+        receive(&mut bob, &msg).unwrap(),
+        (b"only once".to_vec(), MessageOrder::InOrder)
+    );
+    assert!(matches!(
+        receive(&mut bob, &msg),
+        Err(Error::MessageKeyUnavailable)
+    ));
+}
+
+#[test]
+fn test_state_survives_serialization() {
+    let (mut alice, bob) = new_sessions();
+
+    let json = serde_json::to_string(&bob).unwrap();
+    let mut bob: RatchetState = serde_json::from_str(&json).unwrap();
+
+    let msg = send(&mut alice, b"after restart");
+    assert_eq!(receive(&mut bob, &msg).unwrap().0, b"after restart");
+}
@@ -0,0 +1,46 @@
+use modules::key_store_classes::{MessageOrder, ReceiveWindow, RECEIVE_WINDOW_SIZE};
+
+#[test]
+fn test_in_order_messages() {
+    let mut window = ReceiveWindow::default();
+
+    assert_eq!(window.record(1), MessageOrder::InOrder);
+    assert_eq!(window.record(2), MessageOrder::InOrder);
+    assert_eq!(window.record(3), MessageOrder::InOrder);
+    assert_eq!(window.highest, 3);
+}
+
+#[test]
+fn test_replayed_messages() {
+    let mut window = ReceiveWindow::default();
+
+    assert_eq!(window.record(1), MessageOrder::InOrder);
+    assert_eq!(window.record(1), MessageOrder::Replayed);
+    assert_eq!(window.record(2), MessageOrder::InOrder);
+    assert_eq!(window.record(1), MessageOrder::Replayed);
+}
+
+#[test]
+fn test_out_of_order_messages() {
+    let mut window = ReceiveWindow::default();
+
+    assert_eq!(window.record(1), MessageOrder::InOrder);
+    assert_eq!(window.record(4), MessageOrder::OutOfOrder);
+    assert_eq!(window.record(3), MessageOrder::OutOfOrder);
+    assert_eq!(window.record(2), MessageOrder::OutOfOrder);
+    assert_eq!(window.record(3), MessageOrder::Replayed);
+    assert_eq!(window.record(5), MessageOrder::InOrder);
+}
+
+#[test]
+fn test_messages_older_than_window() {
+    let mut window = ReceiveWindow::default();
+
+    assert_eq!(window.record(1), MessageOrder::InOrder);
+    assert_eq!(
+        window.record(RECEIVE_WINDOW_SIZE + 10),
+        MessageOrder::OutOfOrder
+    );
+    assert_eq!(window.record(2), MessageOrder::TooOld);
+    assert_eq!(window.record(11), MessageOrder::OutOfOrder);
+}
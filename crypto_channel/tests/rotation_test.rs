@@ -0,0 +1,80 @@
+mod common;
+
+use common::{exchange, Party, PARTIES};
+use modules::error::Error;
+use modules::key_encoding::parse_peer_key;
+use modules::key_store::{complete_exchange, new_exchange};
+use modules::key_store_classes::ExchangeKind;
+use modules::message::{decrypt_for_exchange, rotate_keys_for_exchange};
+
+fn rotate(from: &Party) -> String {
+    from.act(|name| rotate_keys_for_exchange(name).unwrap().0)
+}
+
+fn receive_rotation(to: &Party, rotation: String) {
+    let decrypted = to.act(|name| decrypt_for_exchange(name, rotation).unwrap());
+    assert!(decrypted.key_rotated);
+}
+
+#[test]
+fn hybrid_public_keys_swapped_at_the_same_time() {
+    let _parties = PARTIES.lock().unwrap_or_else(|e| e.into_inner());
+    let (alice, bob) = (Party::new("bob"), Party::new("alice"));
+    alice.act(|name| new_exchange(name, ExchangeKind::Hybrid).unwrap());
+    bob.act(|name| new_exchange(name, ExchangeKind::Hybrid).unwrap());
+    let (alice_key, bob_key) = (alice.public_key(), bob.public_key());
+
+    let alice_result =
+        alice.act(|name| complete_exchange(name, parse_peer_key(&bob_key).unwrap(), None));
+    let bob_result =
+        bob.act(|name| complete_exchange(name, parse_peer_key(&alice_key).unwrap(), None));
+
+    // Only one of them creates the ML-KEM secret, the other one completes
+    // with the response.
+    let waiting = match (alice_result, bob_result) {
+        (Ok(()), Err(Error::HybridResponseExpected(_))) => &bob,
+        (Err(Error::HybridResponseExpected(_)), Ok(())) => &alice,
+        results => panic!("unexpected results: {:?}", results),
+    };
+    let first = if std::ptr::eq(waiting, &alice) {
+        &bob
+    } else {
+        &alice
+    };
+    waiting.complete_with(first);
+
+    alice.send(&bob, "hello");
+    bob.send(&alice, "hi");
+}
+
+#[test]
+fn rotations_one_after_the_other() {
+    let _parties = PARTIES.lock().unwrap_or_else(|e| e.into_inner());
+    for kind in [ExchangeKind::Classic, ExchangeKind::Hybrid] {
+        let (alice, bob) = exchange(kind);
+
+        receive_rotation(&bob, rotate(&alice));
+        alice.send(&bob, "after alice rotated");
+        bob.send(&alice, "after alice rotated");
+
+        receive_rotation(&alice, rotate(&bob));
+        alice.send(&bob, "after bob rotated");
+        bob.send(&alice, "after bob rotated");
+    }
+}
+
+#[test]
+fn rotations_at_the_same_time() {
+    let _parties = PARTIES.lock().unwrap_or_else(|e| e.into_inner());
+    for kind in [ExchangeKind::Classic, ExchangeKind::Hybrid] {
+        let (alice, bob) = exchange(kind);
+
+        let from_alice = rotate(&alice);
+        let from_bob = rotate(&bob);
+        receive_rotation(&alice, from_bob);
+        receive_rotation(&bob, from_alice);
+
+        alice.send(&bob, "after both rotated");
+        bob.send(&alice, "after both rotated");
+    }
+}
@@ -0,0 +1,40 @@
+use base64::{engine::general_purpose, Engine as _};
+use modules::encryption::str_to_encrypted;
+use modules::signature::{gen_signing_kp, sign_message, verify_signature};
+
+#[test]
+fn test_sign_verify() {
+    let alice_kp = gen_signing_kp();
+    let msg = "nonce;ciphertext";
+
+    let signature = sign_message(msg.as_bytes(), &alice_kp.secret);
+
+    assert!(verify_signature(msg.as_bytes(), &signature, &alice_kp.public).is_ok());
+    assert!(verify_signature(b"nonce;forged", &signature, &alice_kp.public).is_err());
+}
+
+#[test]
+fn test_verify_wrong_sender() {
+    let alice_kp = gen_signing_kp();
+    let mallory_kp = gen_signing_kp();
+    let msg = "nonce;ciphertext";
+
+    let signature = sign_message(msg.as_bytes(), &mallory_kp.secret);
+
+    assert!(verify_signature(msg.as_bytes(), &signature, &alice_kp.public).is_err());
+}
+
+#[test]
+fn test_legacy_signed_message() {
+    let kp = gen_signing_kp();
+    let msg = String::from("bm9uY2U=;Y2lwaGVydGV4dA==");
+    let signature = sign_message(msg.as_bytes(), &kp.secret);
+
+    // Older versions appended the signature to `nonce;ciphertext`.
+    let signed = format!("{};{}", msg, general_purpose::STANDARD.encode(signature));
+    let envelope = str_to_encrypted(signed).unwrap();
+    assert!(envelope.is_signed());
+    assert_eq!(envelope.signature.unwrap(), signature.to_vec());
+
+    assert!(!str_to_encrypted(msg).unwrap().is_signed());
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::path::PathBuf;
+
+use modules::key_exchange::gen_static_kp;
+use modules::key_store::set_storage_passphrase;
+use modules::key_store_classes::{KeyExchange, KeyStorage};
+use modules::storage_backend::{JsonFileStore, KeyStore, MemoryStore};
+use modules::storage_file::backup_path;
+use modules::storage_migration::CURRENT_SCHEMA_VERSION;
+
+fn storage_with_exchange(name: &str) -> KeyStorage {
+    let mut exchange = KeyExchange::new();
+    exchange.add_your_dh_kp(gen_static_kp());
+    let mut storage = KeyStorage::new();
+    storage
+        .create_exchange(name, false, Some(exchange), None)
+        .unwrap();
+    storage
+}
+
+// Saves two versions of the storage, and checks only the last one is loaded.
+fn check_round_trip(store: &dyn KeyStore) {
+    let _lock = store.lock().unwrap();
+    assert!(store.load().unwrap().exchange_map.is_empty());
+
+    store.save(&storage_with_exchange("bob")).unwrap();
+    let saved = storage_with_exchange("carol");
+    store.save(&saved).unwrap();
+
+    let loaded = store.load().unwrap();
+    assert_eq!(loaded.schema_version, CURRENT_SCHEMA_VERSION);
+    assert!(!loaded.exchange_map.contains_key("bob"));
+    assert_eq!(
+        loaded.get_exchange("carol").unwrap().get_your_public_key(),
+        saved.get_exchange("carol").unwrap().get_your_public_key()
+    );
+}
+
+fn temp_storage_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("crypto_channel_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("storage")
+}
+
+#[test]
+fn memory_store_round_trip() {
+    check_round_trip(&MemoryStore::new());
+}
+
+#[test]
+fn json_file_store_round_trip() {
+    let path = temp_storage_path("json_store");
+    check_round_trip(&JsonFileStore::new(path.clone(), false));
+    assert!(path.exists());
+}
+
+#[test]
+fn json_file_store_upgrades_old_files() {
+    let path = temp_storage_path("json_store_migration");
+    fs::write(&path, include_bytes!("fixtures/storage_v0_initial.json")).unwrap();
+
+    let storage = JsonFileStore::new(path.clone(), false).load().unwrap();
+    assert!(storage.name_exists("bob".to_string()));
+    assert_eq!(
+        fs::read(path.with_extension("v0.bak")).unwrap(),
+        include_bytes!("fixtures/storage_v0_initial.json")
+    );
+}
+
+#[test]
+fn json_file_store_erasing_save_replaces_copies() {
+    let path = temp_storage_path("json_store_erasing");
+    fs::write(&path, include_bytes!("fixtures/storage_v0_initial.json")).unwrap();
+    let store = JsonFileStore::new(path.clone(), false);
+    store.load().unwrap();
+
+    store
+        .save(&storage_with_exchange("deleted-contact"))
+        .unwrap();
+    store.save(&KeyStorage::new()).unwrap();
+    assert!(fs::read_to_string(backup_path(&path))
+        .unwrap()
+        .contains("deleted-contact"));
+
+    store.save_erasing(&KeyStorage::new()).unwrap();
+    assert_eq!(
+        fs::read(backup_path(&path)).unwrap(),
+        fs::read(&path).unwrap()
+    );
+    assert!(!path.with_extension("v0.bak").exists());
+}
+
+#[test]
+fn json_file_store_encrypting_leaves_no_plaintext_copy() {
+    let path = temp_storage_path("json_store_encrypting");
+    fs::write(&path, include_bytes!("fixtures/storage_v0_initial.json")).unwrap();
+    let plaintext = JsonFileStore::new(path.clone(), false);
+    plaintext.load().unwrap();
+    plaintext
+        .save_erasing(&storage_with_exchange("secret-contact"))
+        .unwrap();
+    plaintext
+        .save_erasing(&storage_with_exchange("secret-contact"))
+        .unwrap();
+
+    set_storage_passphrase(Some("correct horse battery staple".to_string()));
+    let encrypted = JsonFileStore::new(path.clone(), true);
+    encrypted
+        .save_erasing(&storage_with_exchange("secret-contact"))
+        .unwrap();
+
+    for copy in [path.clone(), backup_path(&path)] {
+        let contents = fs::read_to_string(copy).unwrap();
+        assert!(!contents.contains("secret-contact"));
+        assert!(!contents.contains("exchange_map"));
+    }
+    assert!(!path.with_extension("v0.bak").exists());
+    assert!(encrypted
+        .load()
+        .unwrap()
+        .name_exists("secret-contact".to_string()));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_store_round_trip() {
+    use modules::sqlite_store::SqliteStore;
+
+    let path = temp_storage_path("sqlite_store");
+    check_round_trip(&SqliteStore::open(&path).unwrap());
+
+    // A new connection sees the saved rows.
+    let reopened = SqliteStore::open(&path).unwrap().load().unwrap();
+    assert!(reopened.name_exists("carol".to_string()));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_store_wipes_deleted_rows() {
+    use modules::sqlite_store::SqliteStore;
+
+    let path = temp_storage_path("sqlite_store_delete");
+    let store = SqliteStore::open(&path).unwrap();
+    store
+        .save(&storage_with_exchange("deleted-contact"))
+        .unwrap();
+    store.save(&KeyStorage::new()).unwrap();
+    drop(store);
+
+    let contents = fs::read(&path).unwrap();
+    let name = b"deleted-contact";
+    assert!(!contents.windows(name.len()).any(|window| window == name));
+}
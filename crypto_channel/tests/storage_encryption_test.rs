@@ -0,0 +1,58 @@
+use modules::error::Error;
+use modules::storage_encryption::{
+    derive_storage_key, open_storage, open_storage_with_key, seal_storage, seal_storage_with_key,
+    KdfParams, StorageKey,
+};
+
+#[test]
+fn test_seal_open_storage() {
+    let contents = r#"{"exchange_map":{}}"#;
+
+    let sealed = seal_storage(contents.as_bytes(), "correct horse battery staple").unwrap();
+    assert!(!sealed.ciphertext.contains("exchange_map"));
+
+    let opened = open_storage(&sealed, "correct horse battery staple").unwrap();
+    assert_eq!(*opened, contents.as_bytes());
+}
+
+#[test]
+fn test_open_storage_wrong_passphrase() {
+    let sealed = seal_storage(b"secret keys", "right passphrase").unwrap();
+
+    assert!(open_storage(&sealed, "wrong passphrase").is_err());
+}
+
+#[test]
+fn test_storage_key_reused_across_seals() {
+    let key = StorageKey::new("correct horse battery staple").unwrap();
+    let first = seal_storage_with_key(b"first", &key).unwrap();
+    let second = seal_storage_with_key(b"second", &key).unwrap();
+    assert_eq!(first.salt, second.salt);
+    assert_ne!(first.nonce, second.nonce);
+    assert!(key.matches(&second));
+
+    let opened = open_storage_with_key(&second, &key).unwrap();
+    assert_eq!(*opened, b"second");
+    assert_eq!(
+        *open_storage(&first, "correct horse battery staple").unwrap(),
+        b"first"
+    );
+
+    let other = seal_storage(b"other", "correct horse battery staple").unwrap();
+    assert!(!key.matches(&other));
+    let key = StorageKey::for_sealed(&other, "correct horse battery staple").unwrap();
+    assert_eq!(*open_storage_with_key(&other, &key).unwrap(), b"other");
+}
+
+#[test]
+fn test_unsupported_kdf_params() {
+    let kdf = KdfParams {
+        m_cost: 0,
+        ..KdfParams::new()
+    };
+
+    assert!(matches!(
+        derive_storage_key("passphrase", &[0u8; 16], &kdf),
+        Err(Error::KeyDerivationFailed(_))
+    ));
+}
@@ -0,0 +1,49 @@
+use std::fs;
+
+use modules::storage_file::{backup_path, lock_storage_file, replace_storage_file};
+
+#[test]
+fn replacing_storage_keeps_the_previous_version() {
+    let path = std::env::temp_dir().join("crypto_channel_storage_file_test.json");
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(backup_path(&path));
+
+    replace_storage_file(&path, b"first").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"first");
+    assert!(!backup_path(&path).exists());
+
+    replace_storage_file(&path, b"second").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"second");
+    assert_eq!(fs::read(backup_path(&path)).unwrap(), b"first");
+}
+
+#[test]
+fn storage_lock_is_reentrant() {
+    let path = std::env::temp_dir().join("crypto_channel_storage_lock_test.json");
+
+    let outer = lock_storage_file(&path).unwrap();
+    let inner = lock_storage_file(&path).unwrap();
+    drop(inner);
+    drop(outer);
+    let _again = lock_storage_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn storage_files_are_private() {
+    use modules::storage_file::{is_exposed, restrict_permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join("crypto_channel_storage_permissions_test.json");
+    fs::write(&path, b"old").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(is_exposed(&path).unwrap());
+
+    restrict_permissions(&path).unwrap();
+    assert!(!is_exposed(&path).unwrap());
+
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    replace_storage_file(&path, b"new").unwrap();
+    assert!(!is_exposed(&path).unwrap());
+    assert!(!is_exposed(&backup_path(&path)).unwrap());
+}
@@ -0,0 +1,65 @@
+use modules::error::Error;
+use modules::key_exchange::KDF_V1;
+use modules::storage_migration::{parse_storage, schema_version, CURRENT_SCHEMA_VERSION};
+
+// Frozen storage files, as written by each version of the tool. Never change
+// them, add a new one along with each layout version.
+const STORAGE_V0_INITIAL: &[u8] = include_bytes!("fixtures/storage_v0_initial.json");
+const STORAGE_V0: &[u8] = include_bytes!("fixtures/storage_v0.json");
+const STORAGE_V1: &[u8] = include_bytes!("fixtures/storage_v1.json");
+
+#[test]
+fn initial_layout_is_upgraded() {
+    let (storage, version) = parse_storage(STORAGE_V0_INITIAL).unwrap();
+    assert_eq!(version, 0);
+    assert_eq!(storage.schema_version, CURRENT_SCHEMA_VERSION);
+    assert!(storage.group_map.is_empty());
+
+    let exchange = storage.get_exchange("bob").unwrap();
+    assert_eq!(exchange.get_kdf_version(), KDF_V1);
+    assert_eq!(exchange.get_receive_key(), exchange.get_send_key());
+}
+
+#[test]
+fn unversioned_layout_is_upgraded() {
+    let (storage, version) = parse_storage(STORAGE_V0).unwrap();
+    assert_eq!(version, 0);
+    assert_eq!(storage.schema_version, CURRENT_SCHEMA_VERSION);
+    assert_eq!(storage.group_map["team"].members, vec!["bob".to_string()]);
+    assert_ne!(
+        storage.get_exchange("bob").unwrap().get_kdf_version(),
+        KDF_V1
+    );
+}
+
+#[test]
+fn every_layout_holds_the_same_keys() {
+    let (current, version) = parse_storage(STORAGE_V1).unwrap();
+    assert_eq!(version, 1);
+    let current = current.get_exchange("bob").unwrap().get_send_key();
+
+    for fixture in [STORAGE_V0_INITIAL, STORAGE_V0] {
+        let (storage, _) = parse_storage(fixture).unwrap();
+        assert_eq!(storage.get_exchange("bob").unwrap().get_send_key(), current);
+    }
+}
+
+#[test]
+fn upgraded_storage_is_saved_with_the_current_version() {
+    let (storage, _) = parse_storage(STORAGE_V0_INITIAL).unwrap();
+    let json = serde_json::to_vec(&storage).unwrap();
+    assert_eq!(schema_version(&json).unwrap(), CURRENT_SCHEMA_VERSION);
+    assert_eq!(schema_version(STORAGE_V0).unwrap(), 0);
+}
+
+#[test]
+fn newer_layouts_are_refused() {
+    let newer = format!(
+        r#"{{"schema_version":{},"exchange_map":{{}}}}"#,
+        CURRENT_SCHEMA_VERSION + 1
+    );
+    assert!(matches!(
+        parse_storage(newer.as_bytes()),
+        Err(Error::UnsupportedStorageVersion(_))
+    ));
+}
@@ -1,17 +1,34 @@
-use modules::key_exchange::{gen_static_kp, DhKeyPair};
-use modules::key_store::{self, write_storage};
-use modules::key_store_classes::{self, KeyExchange};
+use modules::config::ConfigSettings;
+use modules::key_exchange::{gen_static_kp, KDF_V1};
+use std::path::PathBuf;
+use std::sync::{Arc, Once};
+
+use modules::key_store::{self, use_key_store, write_storage};
+use modules::key_store_classes::{ExchangeKind, KeyExchange, ROTATION_GRACE_PERIOD};
+use modules::storage_backend::MemoryStore;
+use zeroize::Zeroize;
+
+// Keeps the tests away from the real key storage of the user.
+fn use_memory_store() {
+    static MEMORY_STORE: Once = Once::new();
+    MEMORY_STORE.call_once(|| {
+        let cfg = ConfigSettings::with_storage_path(PathBuf::new());
+        use_key_store(Arc::new(MemoryStore::new()), cfg);
+    });
+}
 
 #[test]
 fn print_storage() {
-    let curr_storage = key_store::read_storage();
+    use_memory_store();
+    let curr_storage = key_store::read_storage().unwrap();
     println!("{:?}", curr_storage);
 }
 
 #[test]
 fn save_your_dh_pair() {
+    use_memory_store();
     let alice_dh_kp = gen_static_kp();
-    let mut curr_storage = key_store::read_storage();
+    let mut curr_storage = key_store::read_storage().unwrap();
 
     let mut new_exchange = KeyExchange::new();
     new_exchange.add_your_dh_kp(alice_dh_kp);
@@ -24,4 +41,78 @@ fn save_your_dh_pair() {
     println!("{:?}", curr_storage.exchange_map);
 
     assert!(write_storage(curr_storage).is_ok());
+    assert!(key_store::get_key_exchange_names()
+        .unwrap()
+        .contains(&"Bob".to_string()));
+}
+
+#[test]
+fn legacy_exchange_defaults_to_kdf_v1() {
+    let mut legacy = KeyExchange::new();
+    legacy.encryption_key = [7u8; 32];
+    let mut json: serde_json::Value = serde_json::to_value(&legacy).unwrap();
+    for field in ["receive_key", "mac_key", "kdf_version", "kind"] {
+        json.as_object_mut().unwrap().remove(field);
+    }
+
+    let exchange: KeyExchange = serde_json::from_value(json).unwrap();
+    assert_eq!(exchange.get_kdf_version(), KDF_V1);
+    assert_eq!(exchange.get_kind(), ExchangeKind::Classic);
+    assert_eq!(exchange.get_send_key(), [7u8; 32]);
+    assert_eq!(exchange.get_receive_key(), [7u8; 32]);
+}
+
+#[test]
+fn verified_flag_is_reset_by_default() {
+    let alice_kp = gen_static_kp();
+    let bob_kp = gen_static_kp();
+
+    let mut exchange = KeyExchange::new();
+    assert_eq!(exchange.get_safety_number(), None);
+
+    exchange.add_your_dh_kp(alice_kp);
+    exchange.add_other_person_public_key(bob_kp.public);
+    exchange.add_shared_secret([1u8; 32], false);
+    assert!(!exchange.is_verified());
+    assert!(exchange.get_safety_number().is_some());
+
+    exchange.set_verified(true);
+    let json = serde_json::to_string(&exchange).unwrap();
+    let exchange: KeyExchange = serde_json::from_str(&json).unwrap();
+    assert!(exchange.is_verified());
+}
+
+#[test]
+fn previous_keys_expire_after_grace_period() {
+    let mut exchange = KeyExchange::new();
+    exchange.encryption_key = [3u8; 32];
+    exchange.receive_key = [4u8; 32];
+    assert_eq!(exchange.get_previous_keys(0), None);
+
+    let now = 1_000_000;
+    exchange.start_grace_period(now + ROTATION_GRACE_PERIOD);
+    let previous = exchange.get_previous_keys(now).unwrap();
+    assert_eq!(previous.receive_key, [4u8; 32]);
+    assert_eq!(previous.ratchet, None);
+
+    assert!(exchange
+        .get_previous_keys(now + ROTATION_GRACE_PERIOD - 1)
+        .is_some());
+    assert_eq!(
+        exchange.get_previous_keys(now + ROTATION_GRACE_PERIOD),
+        None
+    );
+}
+
+#[test]
+fn zeroize_wipes_keys_but_keeps_the_kind() {
+    let mut exchange = KeyExchange::new();
+    exchange.add_your_dh_kp(gen_static_kp());
+    exchange.encryption_key = [5u8; 32];
+    exchange.kind = ExchangeKind::Hybrid;
+
+    exchange.zeroize();
+    assert_eq!(exchange.your_static_secret, [0u8; 32]);
+    assert_eq!(exchange.get_send_key(), [0u8; 32]);
+    assert_eq!(exchange.get_kind(), ExchangeKind::Hybrid);
 }